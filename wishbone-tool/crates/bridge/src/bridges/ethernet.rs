@@ -39,6 +39,7 @@ pub enum EthernetBridgeProtocol {
 pub struct EthernetBridge {
     protocol: EthernetBridgeProtocol,
     addr: SocketAddr,
+    timeout: Duration,
 }
 
 /// Describes all configuration parameters required to connect to a
@@ -53,6 +54,7 @@ impl EthernetBridge {
         Ok(EthernetBridge {
             protocol: EthernetBridgeProtocol::UDP,
             addr,
+            timeout: Duration::from_millis(1000),
         })
     }
 
@@ -68,6 +70,12 @@ impl EthernetBridge {
         self
     }
 
+    /// Set how long to wait for the remote device to answer. Defaults to one second.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut EthernetBridge {
+        self.timeout = timeout;
+        self
+    }
+
     /// Create a new `Bridge` based on the current configuration.
     pub fn create(&self) -> Result<Bridge, BridgeError> {
         Bridge::new(BridgeConfig::EthernetBridge(self.clone()))
@@ -176,7 +184,12 @@ impl EthernetBridgeInner {
                     }
                 }
             } else {
-                match UdpSocket::bind(format!("0.0.0.0:{}", remote_addr.port())) {
+                let local_addr = if remote_addr.is_ipv6() {
+                    format!("[::]:{}", remote_addr.port())
+                } else {
+                    format!("0.0.0.0:{}", remote_addr.port())
+                };
+                match UdpSocket::bind(local_addr) {
                     Ok(conn) => {
                         info!("Re-opened ethernet host {}", remote_addr);
                         EthernetConnection::UDP(conn)
@@ -199,10 +212,10 @@ impl EthernetBridgeInner {
             }
             print_waiting_message = true;
 
-            if let Err(e) = connection.set_read_timeout(Some(Duration::from_millis(1000))) {
+            if let Err(e) = connection.set_read_timeout(Some(cfg.timeout)) {
                 error!("unable to set ethernet read duration timeout: {}", e);
             }
-            if let Err(e) = connection.set_write_timeout(Some(Duration::from_millis(1000))) {
+            if let Err(e) = connection.set_write_timeout(Some(cfg.timeout)) {
                 error!("unable to set ethernet write duration timeout: {}", e);
             }
 
@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::{Bridge, BridgeConfig, BridgeError};
+
+/// A builder to create a bridge that isn't connected to any hardware, for
+/// testing without a target device. Reads and writes go to an in-memory
+/// map of 32-bit words, and addresses that were never written read back
+/// as zero.
+///
+/// ```
+/// use wishbone_bridge::MockBridge;
+/// let bridge = MockBridge::new().value(0x1000_0000, 0x1234_5678).create().unwrap();
+/// assert_eq!(bridge.peek(0x1000_0000).unwrap(), 0x1234_5678);
+/// ```
+#[derive(Clone, Default, Debug)]
+pub struct MockBridge {
+    memory: HashMap<u32, u32>,
+    latency: Duration,
+    corrupt_every: u32,
+    unresponsive: HashSet<u32>,
+    no_burst: bool,
+}
+
+impl MockBridge {
+    /// Create a new `MockBridge` with all memory reading as zero.
+    pub fn new() -> MockBridge {
+        MockBridge {
+            memory: HashMap::new(),
+            latency: Duration::from_millis(0),
+            corrupt_every: 0,
+            unresponsive: HashSet::new(),
+            no_burst: false,
+        }
+    }
+
+    /// Set the initial value of the word at `addr`.
+    pub fn value(&mut self, addr: u32, value: u32) -> &mut MockBridge {
+        self.memory.insert(addr & !3, value);
+        self
+    }
+
+    /// Make every transaction take at least `latency`, to imitate a slow link.
+    pub fn latency(&mut self, latency: Duration) -> &mut MockBridge {
+        self.latency = latency;
+        self
+    }
+
+    /// Invert the value returned by every `every`th `peek()`, to imitate
+    /// a link that loses data. 0, the default, never corrupts a read.
+    pub fn corrupt_every(&mut self, every: u32) -> &mut MockBridge {
+        self.corrupt_every = every;
+        self
+    }
+
+    /// Make every `peek()` of the word at `addr` time out, to imitate a
+    /// device that has stopped answering. `Bridge::peek()` retries
+    /// forever, so use `Bridge::peek_once()` to see the failure.
+    pub fn unresponsive(&mut self, addr: u32) -> &mut MockBridge {
+        self.unresponsive.insert(addr & !3);
+        self
+    }
+
+    /// Refuse `burst_read()` and `burst_write()` and report no burst
+    /// support from `Bridge::capabilities()`, to imitate a bridge that
+    /// moves one word at a time.
+    pub fn no_burst(&mut self) -> &mut MockBridge {
+        self.no_burst = true;
+        self
+    }
+
+    /// Create a bridge based on the current configuration.
+    pub fn create(&self) -> Result<Bridge, BridgeError> {
+        Bridge::new(BridgeConfig::MockBridge(self.clone()))
+    }
+}
+
+#[derive(Clone)]
+pub struct MockBridgeInner {
+    memory: Arc<Mutex<HashMap<u32, u32>>>,
+    latency: Duration,
+    corrupt_every: u32,
+    unresponsive: HashSet<u32>,
+    reads: Arc<AtomicU32>,
+    no_burst: bool,
+}
+
+impl MockBridgeInner {
+    pub fn new(cfg: &MockBridge) -> Result<Self, BridgeError> {
+        Ok(MockBridgeInner {
+            memory: Arc::new(Mutex::new(cfg.memory.clone())),
+            latency: cfg.latency,
+            corrupt_every: cfg.corrupt_every,
+            unresponsive: cfg.unresponsive.clone(),
+            reads: Arc::new(AtomicU32::new(0)),
+            no_burst: cfg.no_burst,
+        })
+    }
+
+    fn delay(&self) {
+        if self.latency > Duration::from_millis(0) {
+            thread::sleep(self.latency);
+        }
+    }
+
+    pub fn connect(&self) -> Result<(), BridgeError> {
+        Ok(())
+    }
+
+    pub fn supports_burst(&self) -> bool {
+        !self.no_burst
+    }
+
+    pub fn peek(&self, addr: u32) -> Result<u32, BridgeError> {
+        self.delay();
+        if self.unresponsive.contains(&(addr & !3)) {
+            debug!("PEEK @ {:08x} timed out", addr);
+            return Err(BridgeError::Timeout);
+        }
+        let mut value = *self.memory.lock().unwrap().get(&(addr & !3)).unwrap_or(&0);
+        let reads = self.reads.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.corrupt_every != 0 && reads % self.corrupt_every == 0 {
+            value = !value;
+        }
+        debug!("PEEK @ {:08x} = {:08x}", addr, value);
+        Ok(value)
+    }
+
+    pub fn poke(&self, addr: u32, value: u32) -> Result<(), BridgeError> {
+        self.delay();
+        debug!("POKE @ {:08x} -> {:08x}", addr, value);
+        self.memory.lock().unwrap().insert(addr & !3, value);
+        Ok(())
+    }
+
+    /// The mock bridge has no protocol of its own, so it echoes raw
+    /// commands back.
+    pub fn raw_command(&self, data: &[u8]) -> Result<Vec<u8>, BridgeError> {
+        self.delay();
+        debug!("RAW {:02x?}", data);
+        Ok(data.to_vec())
+    }
+
+    pub fn burst_read(&self, addr: u32, len: u32) -> Result<Vec<u8>, BridgeError> {
+        self.delay();
+        let memory = self.memory.lock().unwrap();
+        Ok((0..len)
+            .map(|i| {
+                let byte_addr = addr.wrapping_add(i);
+                let word = *memory.get(&(byte_addr & !3)).unwrap_or(&0);
+                (word >> ((byte_addr & 3) * 8)) as u8
+            })
+            .collect())
+    }
+
+    pub fn burst_write(&self, addr: u32, data: &[u8]) -> Result<(), BridgeError> {
+        self.delay();
+        let mut memory = self.memory.lock().unwrap();
+        for (i, byte) in data.iter().enumerate() {
+            let byte_addr = addr.wrapping_add(i as u32);
+            let shift = (byte_addr & 3) * 8;
+            let word = memory.entry(byte_addr & !3).or_insert(0);
+            *word = (*word & !(0xff << shift)) | ((*byte as u32) << shift);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_after_write() {
+        let bridge = MockBridge::new().create().unwrap();
+        assert_eq!(bridge.peek(0x1000_0000).unwrap(), 0);
+        bridge.poke(0x1000_0000, 0xdead_beef).unwrap();
+        assert_eq!(bridge.peek(0x1000_0000).unwrap(), 0xdead_beef);
+    }
+
+    #[test]
+    fn burst_access_is_little_endian() {
+        let bridge = MockBridge::new().value(0x10, 0x4433_2211).create().unwrap();
+        assert_eq!(
+            bridge.burst_read(0x11, 4).unwrap(),
+            vec![0x22, 0x33, 0x44, 0]
+        );
+
+        bridge.burst_write(0x13, &vec![0xaa, 0xbb]).unwrap();
+        assert_eq!(bridge.peek(0x10).unwrap(), 0xaa33_2211);
+        assert_eq!(bridge.peek(0x14).unwrap(), 0x0000_00bb);
+    }
+
+    #[test]
+    fn bursts_can_be_refused() {
+        let bridge = MockBridge::new().no_burst().create().unwrap();
+        assert!(!bridge.capabilities().supports_burst);
+        assert!(matches!(
+            bridge.burst_read(0x10, 4),
+            Err(BridgeError::ProtocolNotSupported)
+        ));
+        assert!(matches!(
+            bridge.burst_write(0x10, &vec![0; 4]),
+            Err(BridgeError::ProtocolNotSupported)
+        ));
+        let bridge = MockBridge::new().create().unwrap();
+        assert!(bridge.capabilities().supports_burst);
+    }
+
+    #[test]
+    fn raw_commands_are_echoed() {
+        let bridge = MockBridge::new().create().unwrap();
+        assert_eq!(
+            bridge.raw_command(&[0x01, 0x02, 0xff]).unwrap(),
+            vec![0x01, 0x02, 0xff]
+        );
+    }
+
+    #[test]
+    fn unresponsive_reads_time_out() {
+        let bridge = MockBridge::new()
+            .value(0x10, 1)
+            .unresponsive(0x20)
+            .create()
+            .unwrap();
+        assert_eq!(bridge.peek_once(0x10).unwrap(), 1);
+        assert!(bridge.peek_once(0x20).unwrap_err().is_timeout());
+    }
+
+    #[test]
+    fn clones_share_memory() {
+        let bridge = MockBridge::new().create().unwrap();
+        let other = bridge.clone();
+        other.poke(0x20, 5).unwrap();
+        assert_eq!(bridge.peek(0x20).unwrap(), 5);
+    }
+
+    #[test]
+    fn transaction_is_not_interleaved() {
+        use crate::Transaction;
+        let bridge = MockBridge::new()
+            .latency(Duration::from_millis(2))
+            .create()
+            .unwrap();
+        let other = bridge.clone();
+        let writer = thread::spawn(move || {
+            for _ in 0..50 {
+                other.poke(0x10, 0xffff_ffff).unwrap();
+            }
+        });
+        for i in 0..20 {
+            let values = bridge
+                .transaction(&[Transaction::Write(0x10, i), Transaction::Read(0x10)])
+                .unwrap();
+            assert_eq!(values, vec![i]);
+        }
+        writer.join().unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 #[cfg(feature = "ethernet")]
 pub mod ethernet;
+#[cfg(feature = "mock")]
+pub mod mock;
 #[cfg(feature = "pcie")]
 pub mod pcie;
 #[cfg(feature = "spi")]
@@ -3,23 +3,34 @@ use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use log::{debug, error, info};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, WriteBytesExt};
 use serialport::prelude::*;
+pub use serialport::FlowControl;
 
 use crate::{Bridge, BridgeConfig, BridgeError};
 
 /// The default baud rate for the serial port. To change, call `set_baud()`
 pub const DEFAULT_BAUD_RATE: u32 = 115_200;
 
+/// How long to wait for the device to answer, unless `timeout()` says otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// The shortest per-read timeout picked from the baud rate
+pub const MIN_READ_TIMEOUT: Duration = Duration::from_millis(10);
+
 /// Describes a connection to a UART or serial port
 #[derive(Clone)]
 pub struct UartBridge {
     serial_port: PathBuf,
     baud: u32,
+    flow_control: FlowControl,
+    reconnect: bool,
+    timeout: Duration,
+    read_timeout: Option<Duration>,
 }
 
 impl UartBridge {
@@ -30,6 +41,10 @@ impl UartBridge {
         Ok(UartBridge {
             serial_port: path.as_ref().to_path_buf(),
             baud: DEFAULT_BAUD_RATE,
+            flow_control: FlowControl::None,
+            reconnect: false,
+            timeout: DEFAULT_TIMEOUT,
+            read_timeout: None,
         })
     }
 
@@ -38,6 +53,35 @@ impl UartBridge {
         self
     }
 
+    /// Set the flow control used on the serial port. Defaults to `FlowControl::None`.
+    pub fn flow_control(&mut self, flow_control: FlowControl) -> &mut UartBridge {
+        self.flow_control = flow_control;
+        self
+    }
+
+    /// If the serial port disappears and doesn't come back at the same path,
+    /// use the only serial port on the system instead, retrying with a
+    /// growing delay. Useful for adapters that re-enumerate when replugged.
+    pub fn reconnect(&mut self, reconnect: bool) -> &mut UartBridge {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Set how long to wait for the device to answer each transaction.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut UartBridge {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how long each read from the serial port may block. The device
+    /// still has the whole `timeout()` to answer, but a raw command's reply
+    /// ends once the port has been quiet this long. Defaults to a value
+    /// picked from the baud rate.
+    pub fn read_timeout(&mut self, read_timeout: Duration) -> &mut UartBridge {
+        self.read_timeout = Some(read_timeout);
+        self
+    }
+
     pub fn create(&self) -> Result<Bridge, BridgeError> {
         Bridge::new(BridgeConfig::UartBridge(self.clone()))
     }
@@ -46,6 +90,10 @@ impl UartBridge {
 pub struct UartBridgeInner {
     path: PathBuf,
     baudrate: u32,
+    flow_control: FlowControl,
+    reconnect: bool,
+    timeout: Duration,
+    read_timeout: Option<Duration>,
     main_tx: Sender<ConnectThreadRequests>,
     main_rx: Arc<(Mutex<Option<ConnectThreadResponses>>, Condvar)>,
     mutex: Arc<Mutex<()>>,
@@ -57,6 +105,10 @@ impl Clone for UartBridgeInner {
         UartBridgeInner {
             path: self.path.clone(),
             baudrate: self.baudrate,
+            flow_control: self.flow_control,
+            reconnect: self.reconnect,
+            timeout: self.timeout,
+            read_timeout: self.read_timeout,
             main_tx: self.main_tx.clone(),
             main_rx: self.main_rx.clone(),
             mutex: self.mutex.clone(),
@@ -65,11 +117,139 @@ impl Clone for UartBridgeInner {
     }
 }
 
+/// Tracks attempts to open the serial port, and decides which path to try
+/// and how long to wait between attempts.
+struct Reconnect {
+    /// Look for another port when the configured one has disappeared
+    rediscover: bool,
+
+    /// Failed attempts since the port was last opened
+    attempts: u32,
+}
+
+impl Reconnect {
+    fn new(rediscover: bool) -> Reconnect {
+        Reconnect {
+            rediscover,
+            attempts: 0,
+        }
+    }
+
+    /// How long to wait after a failed attempt. Without `rediscover` this is
+    /// always 500 ms, otherwise it doubles with each failure up to 8 s.
+    fn delay(&self) -> Duration {
+        if !self.rediscover {
+            return Duration::from_millis(500);
+        }
+        Duration::from_millis(500 << self.attempts.saturating_sub(1).min(4))
+    }
+
+    /// The configured path if it exists, otherwise the only port in
+    /// `available` when there is exactly one.
+    fn candidate(&self, configured: &Path, available: &[PathBuf]) -> PathBuf {
+        match available {
+            [only] if self.rediscover && !configured.exists() => only.clone(),
+            _ => configured.to_path_buf(),
+        }
+    }
+
+    /// Make one attempt at opening a port with `open`, returning the port
+    /// along with the path it was opened from.
+    fn attempt<T, E, F>(
+        &mut self,
+        configured: &Path,
+        available: &[PathBuf],
+        open: F,
+    ) -> Result<(T, PathBuf), E>
+    where
+        F: FnOnce(&Path) -> Result<T, E>,
+    {
+        let path = self.candidate(configured, available);
+        if self.rediscover && self.attempts > 0 {
+            info!(
+                "reconnect attempt {}: trying {}",
+                self.attempts,
+                path.display()
+            );
+        }
+        match open(&path) {
+            Ok(port) => {
+                self.attempts = 0;
+                Ok((port, path))
+            }
+            Err(e) => {
+                self.attempts += 1;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The per-read timeout to use at `baud` when none was asked for: long
+/// enough for sixteen bytes to arrive, but never less than
+/// `MIN_READ_TIMEOUT`.
+fn default_read_timeout(baud: u32) -> Duration {
+    // Each byte is ten bits on the wire, counting start and stop bits
+    let micros = 16 * 10 * 1_000_000 / u64::from(baud.max(1));
+    Duration::from_micros(micros).max(MIN_READ_TIMEOUT)
+}
+
+/// Fill `buffer` from `reader`, carrying on past per-read timeouts until
+/// `timeout` has passed.
+fn read_exact_within<R: Read>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    timeout: Duration,
+) -> std::io::Result<()> {
+    let start = Instant::now();
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut && start.elapsed() < timeout => {}
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Read from `reader` until it times out or reaches the end, returning
+/// everything that was read. A timeout is the normal way for this to end,
+/// since a raw command's reply has no length of its own. Until the first
+/// byte arrives, per-read timeouts are retried until `timeout` has passed.
+fn read_until_quiet<R: Read>(reader: &mut R, timeout: Duration) -> std::io::Result<Vec<u8>> {
+    let start = Instant::now();
+    let mut received = vec![];
+    let mut buffer = [0; 256];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(received),
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                if !received.is_empty() || start.elapsed() >= timeout {
+                    return Ok(received);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Every serial port the system knows about.
+fn available_ports() -> Vec<PathBuf> {
+    serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|p| p.port_name.into()).collect())
+        .unwrap_or_default()
+}
+
 enum ConnectThreadRequests {
     StartPolling(PathBuf /* path */, u32 /* baudrate */),
     Exit,
     Poke(u32 /* addr */, u32 /* val */),
     Peek(u32 /* addr */),
+    Raw(Vec<u8> /* bytes to send */),
 }
 
 #[derive(Debug)]
@@ -78,6 +258,7 @@ enum ConnectThreadResponses {
     OpenedDevice,
     PeekResult(Result<u32, BridgeError>),
     PokeResult(Result<(), BridgeError>),
+    RawResult(Result<Vec<u8>, BridgeError>),
 }
 
 impl UartBridgeInner {
@@ -87,16 +268,24 @@ impl UartBridgeInner {
 
         let path = cfg.serial_port.clone();
         let baudrate = cfg.baud;
+        let flow_control = cfg.flow_control;
+        let reconnect = cfg.reconnect;
+        let timeout = cfg.timeout;
+        let read_timeout = cfg.read_timeout;
 
         let thr_cv = cv.clone();
-        let thr_path = path.clone();
+        let thr_cfg = cfg.clone();
         let poll_thread = Some(thread::spawn(move || {
-            Self::serial_connect_thread(thr_cv, thread_rx, thr_path, baudrate)
+            Self::serial_connect_thread(thr_cv, thread_rx, thr_cfg)
         }));
 
         Ok(UartBridgeInner {
             path,
             baudrate,
+            flow_control,
+            reconnect,
+            timeout,
+            read_timeout,
             main_tx,
             main_rx: cv,
             mutex: Arc::new(Mutex::new(())),
@@ -107,18 +296,25 @@ impl UartBridgeInner {
     fn serial_connect_thread(
         tx: Arc<(Mutex<Option<ConnectThreadResponses>>, Condvar)>,
         rx: Receiver<ConnectThreadRequests>,
-        path: PathBuf,
-        baud: u32,
+        cfg: UartBridge,
     ) {
-        let mut path = path;
-        let mut baud = baud;
+        let UartBridge {
+            serial_port: mut path,
+            mut baud,
+            flow_control,
+            reconnect,
+            timeout,
+            read_timeout,
+        } = cfg;
         let mut print_waiting_message = true;
         let mut first_run = true;
+        let mut attempts = Reconnect::new(reconnect);
         let &(ref response, ref cvar) = &*tx;
         loop {
-            let mut port = match serialport::open(&path) {
-                Ok(port) => {
-                    info!("Re-opened serial device {}", path.display());
+            let available = if reconnect { available_ports() } else { vec![] };
+            let mut port = match attempts.attempt(&path, &available, serialport::open) {
+                Ok((port, opened)) => {
+                    info!("Re-opened serial device {}", opened.display());
                     if first_run {
                         *response.lock().unwrap() = Some(ConnectThreadResponses::OpenedDevice);
                         first_run = false;
@@ -135,21 +331,22 @@ impl UartBridgeInner {
                             e
                         );
                     }
-                    thread::park_timeout(Duration::from_millis(500));
+                    thread::park_timeout(attempts.delay());
                     continue;
                 }
             };
-            port.set_baud_rate(baud as _)
+            let settings = Self::port_settings(baud, flow_control, read_timeout);
+            port.set_baud_rate(settings.baud_rate)
                 .unwrap_or_else(|e| error!("unable to set serial port speed: {}", e));
-            port.set_data_bits(DataBits::Eight)
+            port.set_data_bits(settings.data_bits)
                 .unwrap_or_else(|e| error!("unable to set data bits: {}", e));
-            port.set_parity(Parity::None)
+            port.set_parity(settings.parity)
                 .unwrap_or_else(|e| error!("unable to set parity: {}", e));
-            port.set_stop_bits(StopBits::One)
+            port.set_stop_bits(settings.stop_bits)
                 .unwrap_or_else(|e| error!("unable to set stop bits: {}", e));
-            port.set_flow_control(FlowControl::None)
+            port.set_flow_control(settings.flow_control)
                 .unwrap_or_else(|e| error!("unable to set flow control: {}", e));
-            if let Err(e) = port.set_timeout(Duration::from_millis(1000)) {
+            if let Err(e) = port.set_timeout(settings.timeout) {
                 error!("unable to set port duration timeout: {}", e);
             }
 
@@ -174,7 +371,7 @@ impl UartBridgeInner {
                             baud = v;
                         }
                         ConnectThreadRequests::Peek(addr) => {
-                            let result = Self::do_peek(&mut port, addr);
+                            let result = Self::do_peek(&mut port, addr, timeout);
                             if let Err(err) = &result {
                                 result_error = format!("peek {:?} @ {:08x}", err, addr);
                                 keep_going = false;
@@ -193,6 +390,16 @@ impl UartBridgeInner {
                                 Some(ConnectThreadResponses::PokeResult(result));
                             cvar.notify_one();
                         }
+                        ConnectThreadRequests::Raw(data) => {
+                            let result = Self::do_raw(&mut port, &data, timeout);
+                            if let Err(err) = &result {
+                                result_error = format!("raw command {:?}", err);
+                                keep_going = false;
+                            }
+                            *response.lock().unwrap() =
+                                Some(ConnectThreadResponses::RawResult(result));
+                            cvar.notify_one();
+                        }
                     },
                 }
             }
@@ -224,6 +431,12 @@ impl UartBridgeInner {
                             ));
                             cvar.notify_one();
                         }
+                        ConnectThreadRequests::Raw(_data) => {
+                            *response.lock().unwrap() = Some(ConnectThreadResponses::RawResult(
+                                Err(BridgeError::NotConnected),
+                            ));
+                            cvar.notify_one();
+                        }
                         ConnectThreadRequests::StartPolling(p, v) => {
                             path = p.clone();
                             baud = v;
@@ -238,6 +451,24 @@ impl UartBridgeInner {
         &self.mutex
     }
 
+    /// The settings applied to the serial port each time it is opened.
+    /// `read_timeout` limits each read, and is picked from the baud rate
+    /// when it's `None`.
+    fn port_settings(
+        baud: u32,
+        flow_control: FlowControl,
+        read_timeout: Option<Duration>,
+    ) -> SerialPortSettings {
+        SerialPortSettings {
+            baud_rate: baud,
+            data_bits: DataBits::Eight,
+            flow_control,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            timeout: read_timeout.unwrap_or_else(|| default_read_timeout(baud)),
+        }
+    }
+
     pub fn connect(&self) -> Result<(), BridgeError> {
         self.main_tx
             .send(ConnectThreadRequests::StartPolling(
@@ -278,6 +509,7 @@ impl UartBridgeInner {
     fn do_peek(
         serial: &mut std::boxed::Box<dyn serialport::SerialPort>,
         addr: u32,
+        timeout: Duration,
     ) -> Result<u32, BridgeError> {
         // READ, 1 word
         debug!("Peeking @ {:08x}", addr);
@@ -287,11 +519,49 @@ impl UartBridgeInner {
         // two when writing the address.
         serial.write_u32::<BigEndian>(addr >> 2)?;
 
-        let val = serial.read_u32::<BigEndian>()?;
+        // The port's per-read timeout may be far shorter than `timeout`,
+        // so a plain read_exact() could give up while the reply is on its way
+        let mut reply = [0; 4];
+        read_exact_within(serial, &mut reply, timeout)?;
+        let val = u32::from_be_bytes(reply);
         debug!("PEEK @ {:08x} = {:08x}", addr, val);
         Ok(val)
     }
 
+    /// Send `data` and collect everything that arrives until the port has
+    /// been quiet for the per-read timeout.
+    fn do_raw(
+        serial: &mut std::boxed::Box<dyn serialport::SerialPort>,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, BridgeError> {
+        debug!("RAW -> {:02x?}", data);
+        serial.write_all(data)?;
+        serial.flush()?;
+        let received = read_until_quiet(serial, timeout)?;
+        debug!("RAW <- {:02x?}", received);
+        Ok(received)
+    }
+
+    pub fn raw_command(&self, data: &[u8]) -> Result<Vec<u8>, BridgeError> {
+        let (lock, cvar) = &*self.main_rx;
+        let mut _mtx = lock.lock().unwrap();
+        self.main_tx
+            .send(ConnectThreadRequests::Raw(data.to_vec()))
+            .expect("Unable to send raw command to connect thread");
+        *_mtx = None;
+        while _mtx.is_none() {
+            _mtx = cvar.wait(_mtx).unwrap();
+        }
+        match _mtx.take() {
+            Some(ConnectThreadResponses::RawResult(r)) => Ok(r?),
+            e => {
+                error!("unexpected bridge raw command response: {:?}", e);
+                Err(BridgeError::WrongResponse)
+            }
+        }
+    }
+
     pub fn poke(&self, addr: u32, value: u32) -> Result<(), BridgeError> {
         let &(ref lock, ref cvar) = &*self.main_rx;
         let mut _mtx = lock.lock().unwrap();
@@ -361,3 +631,125 @@ impl Drop for UartBridgeInner {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn port_uses_requested_flow_control() {
+        for flow_control in &[
+            FlowControl::None,
+            FlowControl::Hardware,
+            FlowControl::Software,
+        ] {
+            let settings = UartBridgeInner::port_settings(3_000_000, *flow_control, None);
+            assert_eq!(settings.flow_control, *flow_control);
+            assert_eq!(settings.baud_rate, 3_000_000);
+        }
+    }
+
+    #[test]
+    fn port_uses_requested_read_timeout() {
+        let settings = UartBridgeInner::port_settings(
+            115_200,
+            FlowControl::None,
+            Some(Duration::from_millis(40)),
+        );
+        assert_eq!(settings.timeout, Duration::from_millis(40));
+
+        // Otherwise it's picked from the baud rate
+        let settings = UartBridgeInner::port_settings(115_200, FlowControl::None, None);
+        assert_eq!(settings.timeout, MIN_READ_TIMEOUT);
+        let settings = UartBridgeInner::port_settings(1200, FlowControl::None, None);
+        assert_eq!(settings.timeout, Duration::from_micros(133_333));
+    }
+
+    #[test]
+    fn reconnect_resumes_after_failure() {
+        let configured = Path::new("/dev/wishbone-test-missing");
+        let replugged = vec![PathBuf::from("/dev/ttyUSB1")];
+        let mut reconnect = Reconnect::new(true);
+
+        let first: Result<((), PathBuf), &str> =
+            reconnect.attempt(configured, &replugged, |_| Err("gone"));
+        assert!(first.is_err());
+        assert_eq!(reconnect.delay(), Duration::from_millis(500));
+
+        let (_, opened) = reconnect
+            .attempt(configured, &replugged, |p| Ok::<_, &str>(p.to_path_buf()))
+            .unwrap();
+        assert_eq!(opened, PathBuf::from("/dev/ttyUSB1"));
+        assert_eq!(reconnect.attempts, 0);
+    }
+
+    #[test]
+    fn reconnect_backs_off() {
+        let mut reconnect = Reconnect::new(true);
+        reconnect.attempts = 3;
+        assert_eq!(reconnect.delay(), Duration::from_millis(2000));
+        reconnect.attempts = 30;
+        assert_eq!(reconnect.delay(), Duration::from_millis(8000));
+
+        // Without --reconnect the configured path is always used
+        let reconnect = Reconnect::new(false);
+        let only = vec![PathBuf::from("/dev/ttyUSB1")];
+        assert_eq!(
+            reconnect.candidate(Path::new("/dev/wishbone-test-missing"), &only),
+            PathBuf::from("/dev/wishbone-test-missing")
+        );
+    }
+
+    /// Replies with each chunk in turn, one per read.
+    struct Reply(Vec<std::io::Result<Vec<u8>>>);
+    impl Read for Reply {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let chunk = self.0.remove(0)?;
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    fn timed_out() -> std::io::Result<Vec<u8>> {
+        Err(std::io::ErrorKind::TimedOut.into())
+    }
+
+    #[test]
+    fn raw_reply_ends_at_timeout() {
+        let mut reply = Reply(vec![Ok(vec![1, 2]), Ok(vec![3]), timed_out()]);
+        assert_eq!(
+            read_until_quiet(&mut reply, DEFAULT_TIMEOUT).unwrap(),
+            vec![1, 2, 3]
+        );
+
+        // Quiet before the reply starts is only the end once the timeout passes
+        let mut reply = Reply(vec![timed_out(), Ok(vec![4]), timed_out()]);
+        assert_eq!(
+            read_until_quiet(&mut reply, DEFAULT_TIMEOUT).unwrap(),
+            vec![4]
+        );
+        let mut reply = Reply(vec![timed_out(), Ok(vec![4])]);
+        assert!(read_until_quiet(&mut reply, Duration::from_millis(0))
+            .unwrap()
+            .is_empty());
+
+        let mut reply = Reply(vec![Err(std::io::ErrorKind::BrokenPipe.into())]);
+        assert!(read_until_quiet(&mut reply, DEFAULT_TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn peek_reply_waits_past_read_timeouts() {
+        let mut buffer = [0; 4];
+        let mut reply = Reply(vec![
+            timed_out(),
+            Ok(vec![0x12, 0x34]),
+            Ok(vec![0x56, 0x78]),
+        ]);
+        read_exact_within(&mut reply, &mut buffer, DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(u32::from_be_bytes(buffer), 0x1234_5678);
+
+        let mut reply = Reply(vec![Ok(vec![0x12]), timed_out()]);
+        let err = read_exact_within(&mut reply, &mut buffer, Duration::from_millis(0)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -3,7 +3,7 @@ use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::Duration;
 
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
 use crate::{Bridge, BridgeConfig, BridgeError};
 
@@ -21,6 +21,77 @@ pub struct UsbBridge {
 
     /// If specified, indicate the USB device number to look for.
     device: Option<u8>,
+
+    /// Detach any kernel driver bound to the interface after opening the device.
+    detach_kernel_driver: bool,
+
+    /// If specified, refuse devices that enumerated slower than this.
+    required_speed: Option<UsbSpeed>,
+
+    /// Largest number of bytes moved by one control transfer during a burst.
+    /// `None` means `DEFAULT_BUFFER_SIZE`.
+    buffer_size: Option<u32>,
+}
+
+/// The burst transfer size used unless `UsbBridge::buffer_size()` says
+/// otherwise. The spec suggests 1023 at most, but 4096 works.
+pub const DEFAULT_BUFFER_SIZE: u32 = 4096;
+
+/// The speed a USB device enumerated at, slowest first.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum UsbSpeed {
+    /// 1.5 Mbit/s
+    Low,
+
+    /// 12 Mbit/s
+    Full,
+
+    /// 480 Mbit/s
+    High,
+
+    /// 5 Gbit/s
+    Super,
+}
+
+impl std::fmt::Display for UsbSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsbSpeed::Low => write!(f, "low"),
+            UsbSpeed::Full => write!(f, "full"),
+            UsbSpeed::High => write!(f, "high"),
+            UsbSpeed::Super => write!(f, "super"),
+        }
+    }
+}
+
+/// The interface the bridge talks to.
+const BRIDGE_INTERFACE: u8 = 0;
+
+/// An attached device that a `UsbBridge` would connect to, as listed by
+/// `UsbBridge::matching_devices()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsbDeviceInfo {
+    pub bus: u8,
+    pub device: u8,
+    pub vid: u16,
+    pub pid: u16,
+
+    /// `None` if the device has no serial number, or it couldn't be read.
+    pub serial: Option<String>,
+}
+
+impl std::fmt::Display for UsbDeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bus {:03} device {:03} ({:04x}:{:04x}), serial {}",
+            self.bus,
+            self.device,
+            self.vid,
+            self.pid,
+            self.serial.as_deref().unwrap_or("unknown")
+        )
+    }
 }
 
 /// A builder to create a connection to a target via USB. You should
@@ -40,6 +111,9 @@ impl UsbBridge {
             vid: None,
             bus: None,
             device: None,
+            detach_kernel_driver: false,
+            required_speed: None,
+            buffer_size: None,
         }
     }
 
@@ -67,10 +141,67 @@ impl UsbBridge {
         self
     }
 
+    /// Detach any kernel driver that has claimed the device's interface, and
+    /// re-attach it when the bridge is closed. This is only supported on Linux.
+    pub fn detach_kernel_driver(&mut self, detach: bool) -> &mut UsbBridge {
+        self.detach_kernel_driver = detach;
+        self
+    }
+
+    /// Fail to connect if the device enumerated at less than `speed`, for
+    /// example at full speed on a port that should give high speed.
+    pub fn require_speed(&mut self, speed: UsbSpeed) -> &mut UsbBridge {
+        self.required_speed = Some(speed);
+        self
+    }
+
+    /// Move up to `bytes` in each control transfer of a burst read or
+    /// write. Larger transfers have less overhead, but the size must be a
+    /// multiple of the device's control packet size and no more than 65535.
+    pub fn buffer_size(&mut self, bytes: u32) -> &mut UsbBridge {
+        self.buffer_size = Some(bytes);
+        self
+    }
+
     /// Create a bridge based on the current configuration.
     pub fn create(&self) -> Result<Bridge, BridgeError> {
         Bridge::new(BridgeConfig::UsbBridge(self.clone()))
     }
+
+    /// List the attached devices that match this configuration. A bridge
+    /// connects to the first of these, so when there's more than one,
+    /// `bus()` and `device()` can be used to pick another.
+    pub fn matching_devices(&self) -> Result<Vec<UsbDeviceInfo>, BridgeError> {
+        let usb_ctx = libusb_wishbone_tool::Context::new()?;
+        let mut matching = vec![];
+        for device in usb_ctx.devices()?.iter() {
+            let device_desc = device.device_descriptor()?;
+            if UsbBridgeInner::device_matches(&device, &device_desc, self) {
+                matching.push(UsbDeviceInfo {
+                    bus: device.bus_number(),
+                    device: device.address(),
+                    vid: device_desc.vendor_id(),
+                    pid: device_desc.product_id(),
+                    serial: read_serial_number(&device, &device_desc),
+                });
+            }
+        }
+        Ok(matching)
+    }
+}
+
+/// Read the serial number of `device`, which means opening it. Devices
+/// that can't be opened, perhaps because another program has them, are
+/// still listed without one.
+fn read_serial_number(
+    device: &libusb_wishbone_tool::Device,
+    device_desc: &libusb_wishbone_tool::DeviceDescriptor,
+) -> Option<String> {
+    let timeout = Duration::from_millis(100);
+    let usb = device.open().ok()?;
+    let language = *usb.read_languages(timeout).ok()?.first()?;
+    usb.read_serial_number_string(language, device_desc, timeout)
+        .ok()
 }
 
 pub struct UsbBridgeInner {
@@ -95,6 +226,7 @@ enum ConnectThreadRequests {
 #[derive(Debug)]
 enum ConnectThreadResponses {
     OpenedDevice,
+    OpenFailed(BridgeError),
     PeekResult(Result<u32, BridgeError>),
     BurstReadResult(Result<Vec<u8>, BridgeError>),
     BurstWriteResult(Result<(), BridgeError>),
@@ -102,6 +234,94 @@ enum ConnectThreadResponses {
     Exiting,
 }
 
+/// The part of a device used to find out how fast it enumerated.
+trait DeviceSpeed {
+    fn speed(&self) -> libusb_wishbone_tool::Speed;
+}
+
+impl<'a> DeviceSpeed for libusb_wishbone_tool::Device<'a> {
+    fn speed(&self) -> libusb_wishbone_tool::Speed {
+        libusb_wishbone_tool::Device::speed(self)
+    }
+}
+
+/// Report the speed `device` enumerated at, and check that it is at least
+/// `required`. A speed the operating system doesn't know passes with a
+/// warning, since it can't be checked.
+fn check_speed<D: DeviceSpeed>(device: &D, required: Option<UsbSpeed>) -> Result<(), BridgeError> {
+    use libusb_wishbone_tool::Speed;
+    let speed = match device.speed() {
+        Speed::Low => UsbSpeed::Low,
+        Speed::Full => UsbSpeed::Full,
+        Speed::High => UsbSpeed::High,
+        Speed::Super => UsbSpeed::Super,
+        Speed::Unknown => {
+            if let Some(required) = required {
+                warn!(
+                    "unable to tell how fast the USB device is, so can't check for {} speed",
+                    required
+                );
+            }
+            return Ok(());
+        }
+    };
+    info!("USB device is running at {} speed", speed);
+    match required {
+        Some(required) if speed < required => Err(BridgeError::UsbSpeedTooLow(required, speed)),
+        _ => Ok(()),
+    }
+}
+
+/// Make sure a burst transfer of `size` bytes fits in a control transfer,
+/// and ends on a packet boundary for a control endpoint whose packets are
+/// `max_packet_size` bytes.
+fn check_buffer_size(size: u32, max_packet_size: u8) -> Result<(), BridgeError> {
+    let packet = u32::from(max_packet_size.max(1));
+    if size == 0 || size > u32::from(u16::MAX) || size % packet != 0 {
+        return Err(BridgeError::InvalidBufferSize(size, max_packet_size));
+    }
+    Ok(())
+}
+
+/// The parts of a device handle used to take an interface away from a
+/// kernel driver.
+trait KernelDriver {
+    fn kernel_driver_active(&self, iface: u8) -> libusb_wishbone_tool::Result<bool>;
+    fn detach_kernel_driver(&mut self, iface: u8) -> libusb_wishbone_tool::Result<()>;
+}
+
+impl KernelDriver for libusb_wishbone_tool::DeviceHandle<'_> {
+    fn kernel_driver_active(&self, iface: u8) -> libusb_wishbone_tool::Result<bool> {
+        libusb_wishbone_tool::DeviceHandle::kernel_driver_active(self, iface)
+    }
+    fn detach_kernel_driver(&mut self, iface: u8) -> libusb_wishbone_tool::Result<()> {
+        libusb_wishbone_tool::DeviceHandle::detach_kernel_driver(self, iface)
+    }
+}
+
+/// Detach the kernel driver from `iface` if one is bound to it. Returns
+/// `true` if a driver was detached and should be re-attached later.
+fn detach_kernel_driver<H: KernelDriver>(usb: &mut H, iface: u8) -> Result<bool, BridgeError> {
+    if !usb.kernel_driver_active(iface)? {
+        return Ok(false);
+    }
+    match usb.detach_kernel_driver(iface) {
+        Ok(()) => {
+            info!("detached kernel driver from interface {}", iface);
+            Ok(true)
+        }
+        Err(libusb_wishbone_tool::Error::Access) => {
+            error!(
+                "permission denied detaching kernel driver from interface {}: \
+                 run as root or add a udev rule granting access to the device",
+                iface
+            );
+            Err(BridgeError::USBError(libusb_wishbone_tool::Error::Access))
+        }
+        Err(e) => Err(BridgeError::USBError(e)),
+    }
+}
+
 impl Clone for UsbBridgeInner {
     fn clone(&self) -> Self {
         UsbBridgeInner {
@@ -183,9 +403,10 @@ impl UsbBridgeInner {
             while _mtx.is_none() {
                 _mtx = cvar.wait(_mtx).unwrap();
             }
-            let result = _mtx.take();
-            if let Some(ConnectThreadResponses::OpenedDevice) = result {
-                return Ok(());
+            match _mtx.take() {
+                Some(ConnectThreadResponses::OpenedDevice) => return Ok(()),
+                Some(ConnectThreadResponses::OpenFailed(e)) => return Err(e),
+                _ => (),
             }
         }
     }
@@ -197,6 +418,7 @@ impl UsbBridgeInner {
         mut cfg: UsbBridge,
         debug_byte: u8,
     ) {
+        let buffer_size = cfg.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
         let mut print_waiting_message = true;
         let mut first_open = true;
         let &(ref response, ref cvar) = &*tx;
@@ -205,13 +427,34 @@ impl UsbBridgeInner {
             for device in devices.iter() {
                 let device_desc = device.device_descriptor().unwrap();
                 if Self::device_matches(&device, &device_desc, &cfg) {
-                    let usb = match device.open() {
+                    let mut usb = match device.open() {
                         Ok(o) => {
                             info!(
                                 "opened USB device device {:03} on bus {:03}",
                                 device.address(),
                                 device.bus_number()
                             );
+                            let checked = check_speed(&device, cfg.required_speed).and_then(|_| {
+                                check_buffer_size(buffer_size, device_desc.max_packet_size())
+                            });
+                            if let Err(e) = checked {
+                                if print_waiting_message {
+                                    print_waiting_message = false;
+                                    match e {
+                                        BridgeError::InvalidBufferSize(_, _) => error!(
+                                            "{}, will wait for a device with a suitable packet size",
+                                            e
+                                        ),
+                                        _ => error!("{}, will wait for a faster connection", e),
+                                    }
+                                }
+                                if first_open {
+                                    *response.lock().unwrap() =
+                                        Some(ConnectThreadResponses::OpenFailed(e));
+                                    cvar.notify_one();
+                                }
+                                continue;
+                            }
                             if first_open {
                                 *response.lock().unwrap() =
                                     Some(ConnectThreadResponses::OpenedDevice);
@@ -226,6 +469,20 @@ impl UsbBridgeInner {
                             continue;
                         }
                     };
+                    let mut reattach = false;
+                    if cfg.detach_kernel_driver {
+                        if cfg!(target_os = "linux") {
+                            match detach_kernel_driver(&mut usb, BRIDGE_INTERFACE) {
+                                Ok(detached) => reattach = detached,
+                                Err(e) => {
+                                    error!("unable to detach kernel driver: {}", e);
+                                    continue;
+                                }
+                            }
+                        } else {
+                            error!("detaching kernel drivers is only supported on Linux");
+                        }
+                    }
                     let mut keep_going = true;
                     while keep_going {
                         let var = rx.recv();
@@ -234,6 +491,11 @@ impl UsbBridgeInner {
                             Ok(o) => match o {
                                 ConnectThreadRequests::Exit => {
                                     debug!("usb_poll_thread requested exit");
+                                    if reattach {
+                                        if let Err(e) = usb.attach_kernel_driver(BRIDGE_INTERFACE) {
+                                            error!("unable to re-attach kernel driver: {}", e);
+                                        }
+                                    }
                                     *response.lock().unwrap() =
                                         Some(ConnectThreadResponses::Exiting);
                                     cvar.notify_one();
@@ -258,14 +520,26 @@ impl UsbBridgeInner {
                                     cvar.notify_one();
                                 }
                                 ConnectThreadRequests::BurstRead(addr, len) => {
-                                    let result = Self::do_burst_read(&usb, addr, len, debug_byte);
+                                    let result = Self::do_burst_read(
+                                        &usb,
+                                        addr,
+                                        len,
+                                        buffer_size,
+                                        debug_byte,
+                                    );
                                     keep_going = result.is_ok();
                                     *response.lock().unwrap() =
                                         Some(ConnectThreadResponses::BurstReadResult(result));
                                     cvar.notify_one();
                                 }
                                 ConnectThreadRequests::BurstWrite(addr, data) => {
-                                    let result = Self::do_burst_write(&usb, addr, data, debug_byte);
+                                    let result = Self::do_burst_write(
+                                        &usb,
+                                        addr,
+                                        data,
+                                        buffer_size,
+                                        debug_byte,
+                                    );
                                     keep_going = result.is_ok();
                                     *response.lock().unwrap() =
                                         Some(ConnectThreadResponses::BurstWriteResult(result));
@@ -377,13 +651,14 @@ impl UsbBridgeInner {
         usb: &libusb_wishbone_tool::DeviceHandle,
         addr: u32,
         data: Vec<u8>,
+        maxlen: u32,
         debug_byte: u8,
     ) -> Result<(), BridgeError> {
         if data.len() == 0 {
             return Ok(());
         }
 
-        let maxlen = 4096; // spec says...1023 max? but 4096 works.
+        let maxlen = maxlen as usize;
 
         let packet_count = data.len() / maxlen + if (data.len() % maxlen) != 0 { 1 } else { 0 };
         for pkt_num in 0..packet_count {
@@ -464,6 +739,7 @@ impl UsbBridgeInner {
         usb: &libusb_wishbone_tool::DeviceHandle,
         addr: u32,
         len: u32,
+        maxlen: u32,
         debug_byte: u8,
     ) -> Result<Vec<u8>, BridgeError> {
         let mut data_val = vec![];
@@ -472,8 +748,6 @@ impl UsbBridgeInner {
             return Ok(data_val);
         }
 
-        let maxlen = 4096; // spec says...1023 max? but 4096 works.
-
         let packet_count = len / maxlen + if (len % maxlen) != 0 { 1 } else { 0 };
         for pkt_num in 0..packet_count {
             let cur_addr = addr + pkt_num * maxlen;
@@ -629,3 +903,99 @@ impl Drop for UsbBridgeInner {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libusb_wishbone_tool::Error;
+
+    struct FakeHandle {
+        active: bool,
+        detach_result: Option<Error>,
+        detached: Vec<u8>,
+    }
+
+    impl KernelDriver for FakeHandle {
+        fn kernel_driver_active(&self, _iface: u8) -> libusb_wishbone_tool::Result<bool> {
+            Ok(self.active)
+        }
+        fn detach_kernel_driver(&mut self, iface: u8) -> libusb_wishbone_tool::Result<()> {
+            self.detached.push(iface);
+            match self.detach_result.take() {
+                Some(e) => Err(e),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn active_driver_is_detached() {
+        let mut usb = FakeHandle {
+            active: true,
+            detach_result: None,
+            detached: vec![],
+        };
+        assert!(detach_kernel_driver(&mut usb, 0).unwrap());
+        assert_eq!(usb.detached, vec![0]);
+
+        let mut usb = FakeHandle {
+            active: false,
+            detach_result: None,
+            detached: vec![],
+        };
+        assert!(!detach_kernel_driver(&mut usb, 0).unwrap());
+        assert!(usb.detached.is_empty());
+    }
+
+    #[test]
+    fn permission_error_is_reported() {
+        let mut usb = FakeHandle {
+            active: true,
+            detach_result: Some(Error::Access),
+            detached: vec![],
+        };
+        assert!(matches!(
+            detach_kernel_driver(&mut usb, 0),
+            Err(BridgeError::USBError(Error::Access))
+        ));
+    }
+
+    struct FakeDevice(libusb_wishbone_tool::Speed);
+
+    impl DeviceSpeed for FakeDevice {
+        fn speed(&self) -> libusb_wishbone_tool::Speed {
+            self.0
+        }
+    }
+
+    #[test]
+    fn slow_device_is_refused() {
+        use libusb_wishbone_tool::Speed;
+        let full = FakeDevice(Speed::Full);
+        assert!(check_speed(&full, None).is_ok());
+        assert!(check_speed(&full, Some(UsbSpeed::Full)).is_ok());
+        match check_speed(&full, Some(UsbSpeed::High)) {
+            Err(BridgeError::UsbSpeedTooLow(required, actual)) => {
+                assert_eq!((required, actual), (UsbSpeed::High, UsbSpeed::Full))
+            }
+            other => panic!("expected UsbSpeedTooLow, got {:?}", other),
+        }
+        assert!(check_speed(&FakeDevice(Speed::Super), Some(UsbSpeed::High)).is_ok());
+        assert!(check_speed(&FakeDevice(Speed::Unknown), Some(UsbSpeed::Super)).is_ok());
+    }
+
+    #[test]
+    fn buffer_size_must_fit_packets() {
+        assert_eq!(UsbBridge::new().buffer_size, None);
+        assert_eq!(UsbBridge::new().buffer_size(16384).buffer_size, Some(16384));
+
+        assert!(check_buffer_size(DEFAULT_BUFFER_SIZE, 64).is_ok());
+        assert!(check_buffer_size(16384, 64).is_ok());
+        assert!(check_buffer_size(0, 64).is_err());
+        assert!(check_buffer_size(100, 64).is_err());
+        assert!(matches!(
+            check_buffer_size(65536, 8),
+            Err(BridgeError::InvalidBufferSize(65536, 8))
+        ));
+    }
+}
@@ -32,9 +32,10 @@
     feature = "uart",
     feature = "spi",
     feature = "ethernet",
-    feature = "usb"
+    feature = "usb",
+    feature = "mock"
 )))]
-compile_error!("Must enable at least one bridge type: pcie, uart, spi, ethernet, or usb");
+compile_error!("Must enable at least one bridge type: pcie, uart, spi, ethernet, usb, or mock");
 
 pub(crate) mod bridges;
 
@@ -42,6 +43,9 @@ pub(crate) mod bridges;
 #[cfg(feature = "ethernet")]
 pub use bridges::ethernet::EthernetBridgeInner;
 #[doc(hidden)]
+#[cfg(feature = "mock")]
+pub use bridges::mock::MockBridgeInner;
+#[doc(hidden)]
 #[cfg(feature = "pcie")]
 pub use bridges::pcie::PCIeBridgeInner;
 #[doc(hidden)]
@@ -56,19 +60,22 @@ pub use bridges::usb::UsbBridgeInner;
 
 #[cfg(feature = "ethernet")]
 pub use bridges::ethernet::{EthernetBridge, EthernetBridgeProtocol};
+#[cfg(feature = "mock")]
+pub use bridges::mock::MockBridge;
 #[cfg(feature = "pcie")]
 pub use bridges::pcie::PCIeBridge;
 #[cfg(feature = "spi")]
 pub use bridges::spi::SpiBridge;
 #[cfg(feature = "uart")]
-pub use bridges::uart::UartBridge;
+pub use bridges::uart::{FlowControl, UartBridge};
 #[cfg(feature = "usb")]
-pub use bridges::usb::UsbBridge;
+pub use bridges::usb::{UsbBridge, UsbDeviceInfo, UsbSpeed};
 
 use log::debug;
 
 use std::io;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 #[doc(hidden)]
 #[derive(Clone)]
@@ -103,6 +110,10 @@ pub enum BridgeConfig {
     /// Describes a connection to a device via USB.
     #[cfg(feature = "usb")]
     UsbBridge(UsbBridge),
+
+    /// Describes a bridge backed by memory rather than a device.
+    #[cfg(feature = "mock")]
+    MockBridge(MockBridge),
 }
 
 #[doc(hidden)]
@@ -118,6 +129,8 @@ pub enum BridgeCore {
     UartBridge(UartBridgeInner),
     #[cfg(feature = "usb")]
     UsbBridge(UsbBridgeInner),
+    #[cfg(feature = "mock")]
+    MockBridge(MockBridgeInner),
 }
 
 /// Bridges represent the actual connection to the device. You must create
@@ -139,8 +152,53 @@ pub struct Bridge {
     /// Current offset for `Read` and `Write` operations
     offset: usize,
 
-    /// A Mutex to enforce only a single operation at a time
-    mutex: Arc<Mutex<()>>,
+    /// A Mutex to enforce only a single operation at a time. It holds the
+    /// time the most recent operation started.
+    mutex: Arc<Mutex<Instant>>,
+
+    /// Called with every word that is read or written, see `observe()`.
+    observer: Option<Observer>,
+}
+
+/// A function that is told about every word a bridge reads or writes.
+type Observer = Arc<dyn Fn(Access) + Send + Sync>;
+
+/// A word that was read from or written to the device, as passed to the
+/// function given to `Bridge::observe()`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Access {
+    /// A word was read
+    Read(u32 /* addr */, u32 /* value */),
+
+    /// A word was written
+    Write(u32 /* addr */, u32 /* value */),
+}
+
+/// What a bridge can do, as reported by `Bridge::capabilities()`, so that
+/// callers can refuse an operation up front rather than fail part way.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BridgeCapabilities {
+    /// `burst_read()` and `burst_write()` are implemented
+    pub supports_burst: bool,
+
+    /// The widest single access, in bytes
+    pub max_width: u8,
+
+    /// The width of a bus address, in bytes
+    pub address_width: u8,
+
+    /// `raw_command()` is implemented
+    pub supports_raw_command: bool,
+}
+
+/// One step of a `Bridge::transaction()`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Transaction {
+    /// Read the word at an address
+    Read(u32),
+
+    /// Write a value to an address
+    Write(u32 /* addr */, u32 /* value */),
 }
 
 /// Errors that are generated while creating or using the Wishbone Bridge.
@@ -175,6 +233,14 @@ pub enum BridgeError {
     /// We got nothing back from the bridge
     #[allow(dead_code)]
     Timeout,
+
+    /// The USB device enumerated slower than required
+    #[cfg(feature = "usb")]
+    UsbSpeedTooLow(UsbSpeed /* required */, UsbSpeed /* actual */),
+
+    /// The USB burst buffer size can't be used with the device
+    #[cfg(feature = "usb")]
+    InvalidBufferSize(u32 /* size */, u8 /* control packet size */),
 }
 
 impl ::std::fmt::Display for BridgeError {
@@ -193,12 +259,134 @@ impl ::std::fmt::Display for BridgeError {
             InvalidAddress => write!(f, "bad address or path"),
             ProtocolNotSupported => write!(f, "protocol not supported on this platform"),
             Timeout => write!(f, "connection timed out"),
+            #[cfg(feature = "usb")]
+            UsbSpeedTooLow(required, actual) => write!(
+                f,
+                "USB device is running at {} speed, but {} speed is required",
+                actual, required
+            ),
+            #[cfg(feature = "usb")]
+            InvalidBufferSize(size, packet) => write!(
+                f,
+                "USB buffer size of {} bytes must be from 1 to 65535 and a multiple of the device's {} byte packets",
+                size, packet
+            ),
         }
     }
 }
 
 impl std::error::Error for BridgeError {}
 
+impl BridgeError {
+    /// Whether the error means the device stopped answering, no matter
+    /// which bridge reported it.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            BridgeError::Timeout => true,
+            BridgeError::IoError(e) => e.kind() == io::ErrorKind::TimedOut,
+            #[cfg(feature = "usb")]
+            BridgeError::USBError(libusb_wishbone_tool::Error::Timeout) => true,
+            _ => false,
+        }
+    }
+
+    /// Which kind of failure this is.
+    pub fn kind(&self) -> BridgeErrorKind {
+        if self.is_timeout() {
+            return BridgeErrorKind::Timeout;
+        }
+        match self {
+            BridgeError::IoError(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                BridgeErrorKind::Pipe
+            }
+            #[cfg(feature = "usb")]
+            BridgeError::USBError(libusb_wishbone_tool::Error::Pipe) => BridgeErrorKind::Pipe,
+            #[cfg(feature = "usb")]
+            BridgeError::USBError(libusb_wishbone_tool::Error::NoDevice) => {
+                BridgeErrorKind::Disconnected
+            }
+            BridgeError::NotConnected => BridgeErrorKind::Disconnected,
+            BridgeError::LengthError(_, _) | BridgeError::WrongResponse => {
+                BridgeErrorKind::Protocol
+            }
+            BridgeError::InvalidAddress => BridgeErrorKind::Address,
+            BridgeError::NoBridgeSpecified | BridgeError::ProtocolNotSupported => {
+                BridgeErrorKind::Unsupported
+            }
+            #[cfg(feature = "usb")]
+            BridgeError::UsbSpeedTooLow(_, _) | BridgeError::InvalidBufferSize(_, _) => {
+                BridgeErrorKind::Unsupported
+            }
+            _ => BridgeErrorKind::Io,
+        }
+    }
+}
+
+/// A coarse classification of `BridgeError`s that is the same for every
+/// bridge, so callers can decide how to handle an error without matching
+/// on bridge-specific details.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BridgeErrorKind {
+    /// The device didn't answer in time
+    Timeout,
+
+    /// The link to the device broke, e.g. a USB pipe error
+    Pipe,
+
+    /// The bridge isn't connected, or the device went away
+    Disconnected,
+
+    /// The device answered with something unexpected
+    Protocol,
+
+    /// The address or path was rejected
+    Address,
+
+    /// The bridge can't do what was asked, or was set up incorrectly
+    Unsupported,
+
+    /// Any other I/O or USB failure
+    Io,
+}
+
+impl BridgeErrorKind {
+    pub const ALL: [BridgeErrorKind; 7] = [
+        BridgeErrorKind::Timeout,
+        BridgeErrorKind::Pipe,
+        BridgeErrorKind::Disconnected,
+        BridgeErrorKind::Protocol,
+        BridgeErrorKind::Address,
+        BridgeErrorKind::Unsupported,
+        BridgeErrorKind::Io,
+    ];
+
+    /// The name used for this kind on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            BridgeErrorKind::Timeout => "timeout",
+            BridgeErrorKind::Pipe => "pipe",
+            BridgeErrorKind::Disconnected => "disconnected",
+            BridgeErrorKind::Protocol => "protocol",
+            BridgeErrorKind::Address => "address",
+            BridgeErrorKind::Unsupported => "unsupported",
+            BridgeErrorKind::Io => "io",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BridgeErrorKind> {
+        BridgeErrorKind::ALL
+            .iter()
+            .copied()
+            .find(|k| k.name() == name)
+    }
+
+    /// Whether an error of this kind may go away by itself, so that the
+    /// same operation is worth trying again.
+    pub fn is_transient(self) -> bool {
+        self == BridgeErrorKind::Timeout
+    }
+}
+
 #[cfg(feature = "usb")]
 impl std::convert::From<libusb_wishbone_tool::Error> for BridgeError {
     fn from(e: libusb_wishbone_tool::Error) -> BridgeError {
@@ -212,12 +400,20 @@ impl std::convert::From<io::Error> for BridgeError {
     }
 }
 
+/// Take the lock that serializes a bridge's operations, noting that the
+/// bridge is in use.
+fn lock(mutex: &Mutex<Instant>) -> MutexGuard<'_, Instant> {
+    let mut last_used = mutex.lock().unwrap();
+    *last_used = Instant::now();
+    last_used
+}
+
 impl Bridge {
     /// Create a new Bridge with the specified configuration. The new bridge
     /// starts out in a Disconnected state, but may be connecting in the background.
     /// To ensure the bridge is connected, so you must call `connect()`.
     pub(crate) fn new(bridge_cfg: BridgeConfig) -> Result<Bridge, BridgeError> {
-        let mutex = Arc::new(Mutex::new(()));
+        let mutex = Arc::new(Mutex::new(Instant::now()));
         match &bridge_cfg {
             BridgeConfig::None => Err(BridgeError::NoBridgeSpecified),
             #[cfg(feature = "ethernet")]
@@ -225,31 +421,130 @@ impl Bridge {
                 mutex,
                 core: BridgeCore::EthernetBridge(EthernetBridgeInner::new(bridge_cfg)?),
                 offset: 0,
+                observer: None,
             }),
             #[cfg(feature = "pcie")]
             BridgeConfig::PCIeBridge(bridge_cfg) => Ok(Bridge {
                 mutex,
                 core: BridgeCore::PCIeBridge(PCIeBridgeInner::new(bridge_cfg)?),
                 offset: 0,
+                observer: None,
             }),
             #[cfg(feature = "spi")]
             BridgeConfig::SpiBridge(bridge_cfg) => Ok(Bridge {
                 mutex,
                 core: BridgeCore::SpiBridge(SpiBridgeInner::new(bridge_cfg)?),
                 offset: 0,
+                observer: None,
             }),
             #[cfg(feature = "uart")]
             BridgeConfig::UartBridge(bridge_cfg) => Ok(Bridge {
                 mutex,
                 core: BridgeCore::UartBridge(UartBridgeInner::new(bridge_cfg)?),
                 offset: 0,
+                observer: None,
             }),
             #[cfg(feature = "usb")]
             BridgeConfig::UsbBridge(bridge_cfg) => Ok(Bridge {
                 mutex,
                 core: BridgeCore::UsbBridge(UsbBridgeInner::new(bridge_cfg)?),
                 offset: 0,
+                observer: None,
             }),
+            #[cfg(feature = "mock")]
+            BridgeConfig::MockBridge(bridge_cfg) => Ok(Bridge {
+                mutex,
+                core: BridgeCore::MockBridge(MockBridgeInner::new(bridge_cfg)?),
+                offset: 0,
+                observer: None,
+            }),
+        }
+    }
+
+    /// How long it has been since an operation was last started on this
+    /// bridge or any of its clones.
+    /// ```
+    /// use wishbone_bridge::MockBridge;
+    /// let bridge = MockBridge::new().create().unwrap();
+    /// bridge.peek(0).unwrap();
+    /// assert!(bridge.idle_time().as_secs() < 1);
+    /// ```
+    pub fn idle_time(&self) -> Duration {
+        self.mutex.lock().unwrap().elapsed()
+    }
+
+    /// What this bridge can do. Every bridge currently moves 32-bit words
+    /// over a 32-bit bus.
+    /// ```
+    /// use wishbone_bridge::MockBridge;
+    /// let bridge = MockBridge::new().create().unwrap();
+    /// assert!(bridge.capabilities().supports_burst);
+    /// assert_eq!(bridge.capabilities().address_width, 4);
+    /// ```
+    pub fn capabilities(&self) -> BridgeCapabilities {
+        let (supports_burst, supports_raw_command) = match &self.core {
+            #[cfg(feature = "ethernet")]
+            BridgeCore::EthernetBridge(_b) => (false, false),
+            #[cfg(feature = "pcie")]
+            BridgeCore::PCIeBridge(_b) => (false, false),
+            #[cfg(feature = "spi")]
+            BridgeCore::SpiBridge(_b) => (false, false),
+            #[cfg(feature = "uart")]
+            BridgeCore::UartBridge(_b) => (false, true),
+            #[cfg(feature = "usb")]
+            BridgeCore::UsbBridge(_b) => (true, false),
+            #[cfg(feature = "mock")]
+            BridgeCore::MockBridge(b) => (b.supports_burst(), true),
+        };
+        BridgeCapabilities {
+            supports_burst,
+            max_width: 4,
+            address_width: 4,
+            supports_raw_command,
+        }
+    }
+
+    /// Call `observer` after every word that is successfully read or
+    /// written, including each whole word of a burst. Clones made after
+    /// this call share the observer. Only one observer may be set, and
+    /// setting another replaces it.
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use wishbone_bridge::{Access, MockBridge};
+    /// let accesses = Arc::new(Mutex::new(vec![]));
+    /// let seen = accesses.clone();
+    /// let mut bridge = MockBridge::new().create().unwrap();
+    /// bridge.observe(move |access| seen.lock().unwrap().push(access));
+    /// bridge.poke(0x10, 5).unwrap();
+    /// assert_eq!(*accesses.lock().unwrap(), vec![Access::Write(0x10, 5)]);
+    /// ```
+    pub fn observe<F>(&mut self, observer: F)
+    where
+        F: Fn(Access) + Send + Sync + 'static,
+    {
+        self.observer = Some(Arc::new(observer));
+    }
+
+    /// Pass `access` to the observer, if there is one.
+    fn notify(&self, access: Access) {
+        if let Some(observer) = &self.observer {
+            observer(access);
+        }
+    }
+
+    /// Pass each whole word of a burst starting at `addr` to the observer.
+    fn notify_burst(&self, addr: u32, data: &[u8], write: bool) {
+        if self.observer.is_none() {
+            return;
+        }
+        for (i, word) in data.chunks_exact(4).enumerate() {
+            let word_addr = addr.wrapping_add(i as u32 * 4);
+            let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            self.notify(if write {
+                Access::Write(word_addr, value)
+            } else {
+                Access::Read(word_addr, value)
+            });
         }
     }
 
@@ -257,7 +552,7 @@ impl Bridge {
     /// in the background, so calling `connect()` ensures that the bridge has been
     /// established.
     pub fn connect(&self) -> Result<(), BridgeError> {
-        let _mtx = self.mutex.lock().unwrap();
+        let _mtx = lock(&self.mutex);
         match &self.core {
             #[cfg(feature = "ethernet")]
             BridgeCore::EthernetBridge(b) => b.connect(),
@@ -269,6 +564,8 @@ impl Bridge {
             BridgeCore::UartBridge(b) => b.connect(),
             #[cfg(feature = "usb")]
             BridgeCore::UsbBridge(b) => b.connect(),
+            #[cfg(feature = "mock")]
+            BridgeCore::MockBridge(b) => b.connect(),
         }
     }
 
@@ -280,20 +577,26 @@ impl Bridge {
     /// println!("The value at address 0 is: {:08x}", bridge.peek(0).unwrap());
     /// ```
     pub fn peek(&self, addr: u32) -> Result<u32, BridgeError> {
-        let _mtx = self.mutex.lock().unwrap();
+        let _mtx = lock(&self.mutex);
+        self.peek_locked(addr)
+    }
+
+    /// Read a single 32-bit value, giving up after the first failure
+    /// instead of trying again like `peek()` does. This is useful to check
+    /// that the device is answering at all.
+    pub fn peek_once(&self, addr: u32) -> Result<u32, BridgeError> {
+        let _mtx = lock(&self.mutex);
+        let result = self.peek_core(addr);
+        if let Ok(value) = result {
+            self.notify(Access::Read(addr, value));
+        }
+        result
+    }
+
+    /// The body of `peek()`, for callers that already hold the lock.
+    fn peek_locked(&self, addr: u32) -> Result<u32, BridgeError> {
         loop {
-            let result = match &self.core {
-                #[cfg(feature = "ethernet")]
-                BridgeCore::EthernetBridge(b) => b.peek(addr),
-                #[cfg(feature = "pcie")]
-                BridgeCore::PCIeBridge(b) => b.peek(addr),
-                #[cfg(feature = "spi")]
-                BridgeCore::SpiBridge(b) => b.peek(addr),
-                #[cfg(feature = "uart")]
-                BridgeCore::UartBridge(b) => b.peek(addr),
-                #[cfg(feature = "usb")]
-                BridgeCore::UsbBridge(b) => b.peek(addr),
-            };
+            let result = self.peek_core(addr);
             #[allow(unreachable_code)] // Only possible when no features are enabled (compile error)
             if let Err(e) = result {
                 #[cfg(feature = "usb")]
@@ -303,11 +606,32 @@ impl Bridge {
                 }
                 debug!("Peek failed, trying again: {:?}", e);
             } else {
+                if let Ok(value) = result {
+                    self.notify(Access::Read(addr, value));
+                }
                 return result;
             }
         }
     }
 
+    /// A single attempt at a read, made by the bridge in use.
+    fn peek_core(&self, addr: u32) -> Result<u32, BridgeError> {
+        match &self.core {
+            #[cfg(feature = "ethernet")]
+            BridgeCore::EthernetBridge(b) => b.peek(addr),
+            #[cfg(feature = "pcie")]
+            BridgeCore::PCIeBridge(b) => b.peek(addr),
+            #[cfg(feature = "spi")]
+            BridgeCore::SpiBridge(b) => b.peek(addr),
+            #[cfg(feature = "uart")]
+            BridgeCore::UartBridge(b) => b.peek(addr),
+            #[cfg(feature = "usb")]
+            BridgeCore::UsbBridge(b) => b.peek(addr),
+            #[cfg(feature = "mock")]
+            BridgeCore::MockBridge(b) => b.peek(addr),
+        }
+    }
+
     /// Write a single 32-bit value into the specified address.
     /// ```no_run
     /// use wishbone_bridge::UsbBridge;
@@ -317,7 +641,12 @@ impl Bridge {
     /// bridge.poke(0, 0x12345678).unwrap();
     /// ```
     pub fn poke(&self, addr: u32, value: u32) -> Result<(), BridgeError> {
-        let _mtx = self.mutex.lock().unwrap();
+        let _mtx = lock(&self.mutex);
+        self.poke_locked(addr, value)
+    }
+
+    /// The body of `poke()`, for callers that already hold the lock.
+    fn poke_locked(&self, addr: u32, value: u32) -> Result<(), BridgeError> {
         loop {
             let result = match &self.core {
                 #[cfg(feature = "ethernet")]
@@ -330,6 +659,8 @@ impl Bridge {
                 BridgeCore::UartBridge(b) => b.poke(addr, value),
                 #[cfg(feature = "usb")]
                 BridgeCore::UsbBridge(b) => b.poke(addr, value),
+                #[cfg(feature = "mock")]
+                BridgeCore::MockBridge(b) => b.poke(addr, value),
             };
             #[allow(unreachable_code)] // Only possible when no features are enabled (compile error)
             if let Err(e) = result {
@@ -348,13 +679,38 @@ impl Bridge {
                 }
                 debug!("Poke failed, trying again: {:?}", e);
             } else {
+                self.notify(Access::Write(addr, value));
                 return result;
             }
         }
     }
 
+    /// Perform each step of `steps` in order without letting go of the
+    /// bridge in between, so that no other user of this bridge or of any
+    /// of its clones can slip a transaction into the sequence. Returns the
+    /// value of every `Read`, in order.
+    /// ```
+    /// use wishbone_bridge::{MockBridge, Transaction};
+    /// let bridge = MockBridge::new().create().unwrap();
+    /// let values = bridge
+    ///     .transaction(&[Transaction::Write(0x10, 5), Transaction::Read(0x10)])
+    ///     .unwrap();
+    /// assert_eq!(values, vec![5]);
+    /// ```
+    pub fn transaction(&self, steps: &[Transaction]) -> Result<Vec<u32>, BridgeError> {
+        let _mtx = lock(&self.mutex);
+        let mut values = vec![];
+        for step in steps {
+            match *step {
+                Transaction::Read(addr) => values.push(self.peek_locked(addr)?),
+                Transaction::Write(addr, value) => self.poke_locked(addr, value)?,
+            }
+        }
+        Ok(values)
+    }
+
     pub fn burst_read(&self, addr: u32, length: u32) -> Result<Vec<u8>, BridgeError> {
-        let _mtx = self.mutex.lock().unwrap();
+        let _mtx = lock(&self.mutex);
         loop {
             let result = match &self.core {
                 #[cfg(feature = "ethernet")]
@@ -367,6 +723,12 @@ impl Bridge {
                 BridgeCore::UartBridge(_b) => return Err(BridgeError::ProtocolNotSupported),
                 #[cfg(feature = "usb")]
                 BridgeCore::UsbBridge(b) => b.burst_read(addr, length),
+                #[cfg(feature = "mock")]
+                BridgeCore::MockBridge(b) if !b.supports_burst() => {
+                    return Err(BridgeError::ProtocolNotSupported)
+                }
+                #[cfg(feature = "mock")]
+                BridgeCore::MockBridge(b) => b.burst_read(addr, length),
             };
             #[allow(unreachable_code)] // Only possible when no features are enabled (compile error)
             if let Err(e) = result {
@@ -377,13 +739,16 @@ impl Bridge {
                 }
                 debug!("Peek failed, trying again: {:?}", e);
             } else {
+                if let Ok(data) = &result {
+                    self.notify_burst(addr, data, false);
+                }
                 return result;
             }
         }
     }
 
     pub fn burst_write(&self, addr: u32, data: &Vec<u8>) -> Result<(), BridgeError> {
-        let _mtx = self.mutex.lock().unwrap();
+        let _mtx = lock(&self.mutex);
         loop {
             let result = match &self.core {
                 #[cfg(feature = "ethernet")]
@@ -396,6 +761,12 @@ impl Bridge {
                 BridgeCore::UartBridge(_b) => return Err(BridgeError::ProtocolNotSupported),
                 #[cfg(feature = "usb")]
                 BridgeCore::UsbBridge(b) => b.burst_write(addr, data),
+                #[cfg(feature = "mock")]
+                BridgeCore::MockBridge(b) if !b.supports_burst() => {
+                    return Err(BridgeError::ProtocolNotSupported)
+                }
+                #[cfg(feature = "mock")]
+                BridgeCore::MockBridge(b) => b.burst_write(addr, data),
             };
             #[allow(unreachable_code)] // Only possible when no features are enabled (compile error)
             if let Err(e) = result {
@@ -406,15 +777,44 @@ impl Bridge {
                 }
                 debug!("Peek failed, trying again: {:?}", e);
             } else {
+                self.notify_burst(addr, data, true);
                 return result;
             }
         }
     }
+
+    /// Send `data` to the bridge exactly as given and return whatever comes
+    /// back, bypassing the Wishbone protocol. This is meant for bringing up
+    /// vendor-specific bridge commands, and nothing stops the bytes from
+    /// confusing the device. Unlike other operations, a failure isn't
+    /// retried, since the command may not be safe to repeat.
+    /// ```
+    /// use wishbone_bridge::MockBridge;
+    /// let bridge = MockBridge::new().create().unwrap();
+    /// assert_eq!(bridge.raw_command(&[0xca, 0xfe]).unwrap(), vec![0xca, 0xfe]);
+    /// ```
+    pub fn raw_command(&self, data: &[u8]) -> Result<Vec<u8>, BridgeError> {
+        let _mtx = lock(&self.mutex);
+        match &self.core {
+            #[cfg(feature = "ethernet")]
+            BridgeCore::EthernetBridge(_b) => Err(BridgeError::ProtocolNotSupported),
+            #[cfg(feature = "pcie")]
+            BridgeCore::PCIeBridge(_b) => Err(BridgeError::ProtocolNotSupported),
+            #[cfg(feature = "spi")]
+            BridgeCore::SpiBridge(_b) => Err(BridgeError::ProtocolNotSupported),
+            #[cfg(feature = "uart")]
+            BridgeCore::UartBridge(b) => b.raw_command(data),
+            #[cfg(feature = "usb")]
+            BridgeCore::UsbBridge(_b) => Err(BridgeError::ProtocolNotSupported),
+            #[cfg(feature = "mock")]
+            BridgeCore::MockBridge(b) => b.raw_command(data),
+        }
+    }
 }
 
 impl std::io::Read for Bridge {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let _mtx = self.mutex.lock().unwrap();
+        let _mtx = lock(&self.mutex);
         let addr = self.offset as _;
         use std::convert::TryInto;
         use std::io::{Error, ErrorKind};
@@ -443,6 +843,10 @@ impl std::io::Read for Bridge {
             BridgeCore::UsbBridge(b) => b
                 .burst_read(addr, buf.len().try_into().unwrap())
                 .map(|v| fill_array(&v, buf)),
+            #[cfg(feature = "mock")]
+            BridgeCore::MockBridge(b) => b
+                .burst_read(addr, buf.len().try_into().unwrap())
+                .map(|v| fill_array(&v, buf)),
         }
         .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
         self.offset += copied;
@@ -477,7 +881,7 @@ impl std::io::Write for Bridge {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         use std::convert::TryInto;
         use std::io::{Error, ErrorKind};
-        let _mtx = self.mutex.lock().unwrap();
+        let _mtx = lock(&self.mutex);
 
         fn slice_to_u32(buf: &[u8]) -> std::io::Result<u32> {
             if buf.len() < 3 {
@@ -501,6 +905,8 @@ impl std::io::Write for Bridge {
             BridgeCore::UartBridge(_) => self.poke(addr, slice_to_u32(buf)?).map(|_| 4),
             #[cfg(feature = "usb")]
             BridgeCore::UsbBridge(b) => b.burst_write(addr, buf).map(|_| buf.len()),
+            #[cfg(feature = "mock")]
+            BridgeCore::MockBridge(b) => b.burst_write(addr, buf).map(|_| buf.len()),
         }
         .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
         self.offset += bytes_written;
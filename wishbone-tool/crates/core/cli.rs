@@ -0,0 +1,352 @@
+//! Conversion from command line arguments into a `Config`. This is the only
+//! place that knows about `clap`, so that `Config` itself may be used
+//! without going through the command line.
+
+use crate::config::{
+    explain_named_address, parse_address_offset, parse_bool_value, parse_define, parse_f64,
+    parse_flow_control, parse_hex_bytes, parse_named_address, parse_retry_on, parse_rfc3339,
+    parse_u16, parse_u32, parse_u32_address, parse_u8, parse_usb_speed, BridgeKind, Config,
+    ConfigError, CsrCsvContents, CsvSource, FillPattern, LengthPolicy, LogTarget, RetryBackoff,
+};
+use crate::output::OutputFormat;
+use crate::server::ServerKind;
+use crate::watch::WatchExpr;
+use clap::ArgMatches;
+
+/// Build a `Config` from the arguments given on the command line.
+/// `log_target` is parsed separately, since logging has to start first.
+pub fn parse_config(matches: &ArgMatches, log_target: LogTarget) -> Result<Config, ConfigError> {
+    let mut builder = Config::builder();
+
+    // Bridge parameters. The bridge itself is chosen based on which
+    // of these were specified.
+    if let Some(pins) = matches.value_of("spi-pins") {
+        builder.spi_pins(pins);
+    }
+    if let Some(port) = matches.value_of("serial") {
+        builder.serial_port(port);
+    }
+    if let Some(baud) = matches.value_of("baud") {
+        builder.serial_baud(parse_u32(baud)?);
+    }
+    if let Some(timeout) = matches.value_of("timeout") {
+        // With --watch-expr, this also limits the whole wait
+        let timeout = parse_u32(timeout)?;
+        builder.bridge_timeout(timeout);
+        if matches.is_present("watch-expr") {
+            builder.watch_timeout(timeout);
+        }
+    }
+    if let Some(timeout) = matches.value_of("serial-timeout") {
+        builder.serial_timeout(parse_u32(timeout)?);
+    }
+    // unwrap() is safe because there is a default value
+    builder.serial_flow_control(parse_flow_control(
+        matches.value_of("flow-control").unwrap(),
+    )?);
+    builder.serial_reconnect(matches.is_present("reconnect"));
+    if let Some(pcie_bar) = matches.value_of("pcie-bar") {
+        builder.pcie_bar(pcie_bar);
+    }
+    if let Some(host) = matches.value_of("ethernet-host") {
+        builder.ethernet_host(host);
+    }
+    if let Some(port) = matches.value_of("ethernet-port") {
+        builder.ethernet_port(parse_u16(port)?);
+    }
+    builder.ethernet_tcp(matches.is_present("ethernet-tcp"));
+    if let Some(vid) = matches.value_of("vid") {
+        builder.usb_vid(parse_u16(vid)?);
+    }
+    if let Some(pid) = matches.value_of("pid") {
+        builder.usb_pid(parse_u16(pid)?);
+    }
+    if let Some(bus) = matches.value_of("bus") {
+        builder.usb_bus(parse_u8(bus)?);
+    }
+    if let Some(device) = matches.value_of("device") {
+        builder.usb_device(parse_u8(device)?);
+    }
+    builder.usb_detach_kernel_driver(matches.is_present("detach-kernel-driver"));
+    if let Some(speed) = matches.value_of("require-speed") {
+        builder.usb_require_speed(parse_usb_speed(speed)?);
+    }
+    if let Some(bytes) = matches.value_of("usb-buffer-size") {
+        builder.usb_buffer_size(parse_u32(bytes)?);
+    }
+    builder.usb_pick(matches.is_present("pick"));
+
+    if let Some(name) = matches.value_of("load-name") {
+        builder.load_name(name);
+    }
+    builder.load_flash(matches.is_present("load-flash"));
+    if let Some(bytes) = matches.value_of("confirm-size") {
+        builder.confirm_size(parse_u32(bytes)?);
+    }
+    if let Some(addr) = matches.value_of("load-address") {
+        builder.load_addr(parse_u32(addr)?);
+    }
+
+    if let Some(value) = matches.value_of("value") {
+        builder.memory_value(parse_bool_value(value)?);
+    }
+
+    // unwrap() is safe because there is a default value
+    builder
+        .gdb_port(parse_u16(matches.value_of("gdb-port").unwrap())?)
+        .bind_port(parse_u16(matches.value_of("wishbone-port").unwrap())?)
+        .burst_length(parse_u32(matches.value_of("burst-length").unwrap())?);
+
+    if let Some(addr) = matches.value_of("bind-addr") {
+        builder.bind_addr(addr);
+    }
+
+    if let Some(server_kinds) = matches.values_of("server-kind") {
+        for sk in server_kinds {
+            builder.server_kind(ServerKind::from_string(sk)?);
+        }
+    }
+
+    if let Some(random_loops) = matches.value_of("random-loops") {
+        builder.random_loops(parse_u32(random_loops)?);
+    }
+    if let Some(seed) = matches.value_of("seed") {
+        builder.random_seed(parse_u32(seed)?);
+    }
+    if let Some(random_address) = matches.value_of("random-address") {
+        builder.random_address(parse_u32(random_address)?);
+    }
+    if let Some(random_range) = matches.value_of("random-range") {
+        builder.random_range(parse_u32(random_range)?);
+    }
+
+    let csr_csv = matches.value_of("csr-csv").map(CsvSource::from_string);
+    let CsrCsvContents {
+        register_mapping,
+        offset,
+        memory_regions,
+        csr_bases,
+        registers,
+    } = Config::parse_csr_csv(csr_csv.as_ref(), matches.value_of("register-offset"))?;
+    if let Some(source) = csr_csv {
+        builder.csr_csv(source);
+    }
+
+    if let Some(messible_address) = matches.value_of("messible-address") {
+        builder.messible_address(
+            parse_u32_address(messible_address, offset)?
+                .ok_or_else(|| ConfigError::AddressOutOfRange(messible_address.to_owned()))?,
+        );
+    }
+
+    if let Some(debug_offset) = matches.value_of("debug-offset") {
+        builder.debug_offset(
+            parse_u32_address(debug_offset, offset)?
+                .ok_or_else(|| ConfigError::AddressOutOfRange(debug_offset.to_owned()))?,
+        );
+    }
+
+    if let Some(addr) = matches.value_of("address") {
+        builder.memory_address(parse_named_address(addr, &register_mapping, offset)?);
+        if matches.is_present("explain-address") {
+            builder.address_resolution(explain_named_address(addr, &register_mapping, offset)?);
+        }
+    }
+    builder.explain_address(matches.is_present("explain-address"));
+    if let Some(addr) = matches.value_of("keepalive-address") {
+        builder.keepalive_address(parse_named_address(addr, &register_mapping, offset)?);
+    }
+    builder.preflight(matches.is_present("preflight"));
+    if let Some(addr) = matches.value_of("preflight-address") {
+        builder.preflight_address(parse_named_address(addr, &register_mapping, offset)?);
+    }
+    if let Some(expr) = matches.value_of("watch-expr") {
+        builder.watch_expr(WatchExpr::parse(expr, |addr| {
+            parse_named_address(addr, &register_mapping, offset)
+        })?);
+    }
+    builder.register_mapping(register_mapping);
+    builder.memory_regions(memory_regions);
+    builder.csr_bases(csr_bases);
+    builder.csr_registers(registers);
+
+    builder
+        .terminal_mouse(matches.is_present("terminal-mouse") || cfg!(windows))
+        .hexdump(matches.is_present("hexdump"))
+        .compact(matches.is_present("compact"))
+        .flash_no_reset(matches.is_present("flash-no-reset"))
+        .careful_flashing(matches.is_present("careful-flashing"))
+        .allow_unaligned(matches.is_present("allow-unaligned"));
+    if matches.is_present("warn-unaligned-length") {
+        builder.length_policy(LengthPolicy::RoundDown);
+    }
+
+    if let Some(source) = matches.value_of("burst-source") {
+        builder.burst_source(source);
+    }
+    if let Some(path) = matches.value_of("value-file") {
+        builder.value_file(path);
+    }
+    if let Some(label) = matches.value_of("label") {
+        builder.label(label);
+    }
+    builder.log_target(log_target);
+
+    if let Some(path) = matches.value_of("poll-file") {
+        builder.poll_file(path);
+    }
+    // unwrap() is safe because there is a default value
+    builder.poll_interval(parse_u32(matches.value_of("poll-interval").unwrap())?);
+
+    builder.double_read(matches.is_present("double-read"));
+    // unwrap() is safe because there is a default value
+    builder.retry_count(parse_u32(matches.value_of("retry-count").unwrap())?);
+    if let Some(kinds) = matches.value_of("retry-on") {
+        builder.retry_on(parse_retry_on(kinds)?);
+    }
+
+    if let Some(path) = matches.value_of("out") {
+        builder.logic_file(path);
+    }
+    if let Some(max) = matches.value_of("max-samples") {
+        builder.logic_max_samples(parse_u32(max)?);
+    }
+
+    // unwrap() is safe because there is a default value
+    builder.max_clients(parse_u32(matches.value_of("max-clients").unwrap())?);
+    // unwrap() is safe because there is a default value
+    builder.max_read_size(parse_u32(matches.value_of("max-read-size").unwrap())?);
+
+    // unwrap() is safe because there are default values
+    builder
+        .retry_backoff(RetryBackoff::from_string(
+            matches.value_of("retry-backoff").unwrap(),
+        )?)
+        .retry_delay(parse_u32(matches.value_of("retry-delay").unwrap())?)
+        .retry_max_delay(parse_u32(matches.value_of("retry-max-delay").unwrap())?);
+
+    if let Some(path) = matches.value_of("replay-log") {
+        builder.replay_file(path);
+    }
+    builder.preserve_timing(matches.is_present("preserve-timing"));
+
+    if let Some(offset) = matches.value_of("address-offset") {
+        builder.address_offset(parse_address_offset(offset)?);
+    }
+
+    if matches.is_present("mock") {
+        builder.bridge_kind(BridgeKind::Mock);
+    }
+    if let Some(path) = matches.value_of("mock-init") {
+        builder.mock_init(path);
+    }
+
+    // unwrap() is safe because there is a default value
+    builder.output_format(OutputFormat::from_string(
+        matches.value_of("format").unwrap(),
+    )?);
+
+    if let Some(path) = matches.value_of("output") {
+        builder.output_path(path);
+    }
+
+    if let Some(name) = matches.value_of("file-region") {
+        builder.file_region(name);
+    }
+    // unwrap() is safe because there is a default value
+    builder.file_port(parse_u16(matches.value_of("file-port").unwrap())?);
+
+    if let Some(ms) = matches.value_of("slow-threshold") {
+        builder.slow_threshold(parse_u32(ms)?);
+    }
+
+    if let Some(definitions) = matches.values_of("define") {
+        for definition in definitions {
+            let (key, value) = parse_define(definition)?;
+            builder.define(&key, &value);
+        }
+    }
+
+    builder.verify_checksum(matches.is_present("verify-checksum"));
+
+    if let Some(value) = matches.value_of("pulse") {
+        builder.pulse_value(parse_u32(value)?);
+    }
+    // unwrap() is safe because there is a default value
+    builder.pulse_width(parse_u32(matches.value_of("pulse-width").unwrap())?);
+
+    builder.dump_registers(matches.is_present("dump-registers"));
+    builder.group_by_region(matches.is_present("group-by-region"));
+    builder.reverse(matches.is_present("reverse"));
+
+    if let Some(words) = matches.value_of("chunk-size") {
+        builder.chunk_size(parse_u32(words)?);
+    }
+
+    if let Some(snapshot) = matches.value_of("compare-registers") {
+        builder.compare_registers(snapshot);
+    }
+
+    builder.histogram(matches.is_present("histogram"));
+    // unwrap() is safe because there are default values
+    builder.histogram_samples(parse_u32(matches.value_of("samples").unwrap())?);
+    builder.histogram_bins(parse_u32(matches.value_of("bins").unwrap())?);
+
+    if let Some(failures) = matches.value_of("abort-after-failures") {
+        builder.abort_after_failures(parse_u32(failures)?);
+    }
+    if let Some(percent) = matches.value_of("min-success-rate") {
+        builder.min_success_rate(parse_f64("--min-success-rate", percent)?);
+    }
+
+    if let Some(scale) = matches.value_of("scale") {
+        builder.read_scale(parse_f64("--scale", scale)?);
+    }
+    if let Some(offset) = matches.value_of("offset") {
+        builder.read_offset(parse_f64("--offset", offset)?);
+    }
+
+    builder
+        .safe_writes(matches.is_present("safe"))
+        .unsafe_writes(matches.is_present("unsafe"));
+
+    if let Some(count) = matches.value_of("poll-count") {
+        builder.poll_count(parse_u32(count)?);
+    }
+    if let Some(time) = matches.value_of("deadline") {
+        builder.deadline(parse_rfc3339(time)?);
+    }
+
+    if let Some(bytes) = matches.value_of("raw-command") {
+        builder.raw_command(parse_hex_bytes(bytes)?);
+    }
+
+    if let Some(bytes) = matches.value_of("addr-width") {
+        builder.address_width(parse_u8(bytes)?);
+    }
+
+    builder.hex_prefix(!matches.is_present("no-hex-prefix"));
+    builder.human_readable(matches.is_present("human"));
+
+    if let Some(pattern) = matches.value_of("pattern") {
+        builder.fill_pattern(FillPattern::from_string(pattern)?);
+    }
+    builder.memtest(matches.is_present("memtest"));
+
+    if let Some(path) = matches.value_of("trace") {
+        builder.trace_file(path);
+    }
+    builder.trace_changes_only(matches.is_present("trace-changes-only"));
+
+    if let Some(value) = matches.value_of("expect") {
+        builder.expect_value(parse_u32(value)?);
+    }
+    if let Some(mask) = matches.value_of("mask") {
+        builder.expect_mask(parse_u32(mask)?);
+    }
+
+    // unwrap() is safe because there is a default value
+    builder.keepalive(parse_u32(matches.value_of("keepalive").unwrap())?);
+
+    builder.build()
+}
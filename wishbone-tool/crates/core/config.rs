@@ -1,11 +1,20 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io;
+use std::net::Ipv6Addr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use log::warn;
+
+use crate::output::OutputFormat;
 use crate::server::ServerKind;
-use clap::ArgMatches;
+use crate::watch::WatchExpr;
 use wishbone_bridge::{
-    Bridge, EthernetBridge, EthernetBridgeProtocol, PCIeBridge, SpiBridge, UartBridge, UsbBridge,
+    Bridge, BridgeErrorKind, EthernetBridge, EthernetBridgeProtocol, FlowControl, MockBridge,
+    PCIeBridge, SpiBridge, UartBridge, UsbBridge, UsbDeviceInfo, UsbSpeed,
 };
 
 #[derive(Debug)]
@@ -30,8 +39,138 @@ pub enum ConfigError {
 
     /// The specified address is outside of legal memory
     AddressOutOfRange(String),
+
+    /// The address is not aligned to the access width
+    UnalignedAddress(u32),
+
+    /// A host or host:port string could not be parsed
+    MalformedAddress(String),
+
+    /// A value was neither a number nor a recognized boolean token
+    UnrecognizedValue(String),
+
+    /// Options were given that can't be used together
+    IncompatibleOptions(Vec<String>),
+
+    /// The register map couldn't be downloaded from the given URL
+    CsvFetchError(String, String),
+
+    /// A `${KEY}` reference had no matching --define
+    UndefinedVariable(String),
+
+    /// An option that looks things up in the register map was used, but
+    /// no map was loaded
+    RegisterMapRequired(String),
+
+    /// An option was given without another one that it needs
+    MissingDependentOption {
+        option: &'static str,
+        requires: &'static str,
+    },
+
+    /// `--safe` is in effect and a write targets an address that isn't in
+    /// the register map
+    UnsafeWrite { address: u32 },
+
+    /// A range read is longer than `--max-read-size`, in words
+    ReadTooLarge { requested: u64, limit: u32 },
+
+    /// A range length isn't a whole number of 32-bit words
+    UnalignedLength(u32),
+
+    /// A line of a `--value-file` couldn't be understood
+    ValueFileParseError(
+        usize, // line number
+        String,
+    ),
+
+    /// A `--watch-expr` couldn't be understood
+    ExpressionParseError(
+        usize, // column
+        String,
+    ),
+
+    /// `--confirm-size` doesn't match the size of the file to be flashed
+    ConfirmationMismatch { confirmed: u32, actual: u64 },
+}
+
+impl ::std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        use ConfigError::*;
+        match self {
+            NumberParseError(num, e) => write!(f, "unable to parse the number \"{}\": {}", num, e),
+            NoOperationSpecified => write!(f, "no operation was specified"),
+            UnknownServerKind(s) => write!(f, "unknown server '{}', see --help", s),
+            SpiParseError(s) => write!(f, "couldn't parse spi pins: {}", s),
+            IoError(s) => write!(f, "file error: {}", s),
+            InvalidConfig(s) => write!(f, "invalid configuration: {}", s),
+            AddressOutOfRange(s) => write!(f, "address was not in mappable range: {}", s),
+            UnalignedAddress(a) => write!(
+                f,
+                "address 0x{:08x} is not aligned to a 32-bit word (use --allow-unaligned to override)",
+                a
+            ),
+            MalformedAddress(s) => write!(
+                f,
+                "malformed address \"{}\", expected host, host:port or [ipv6]:port",
+                s
+            ),
+            UnrecognizedValue(s) => write!(
+                f,
+                "\"{}\" is not a number or one of on, off, true or false",
+                s
+            ),
+            IncompatibleOptions(options) => write!(
+                f,
+                "these options can't be used together: {}",
+                options.join(", ")
+            ),
+            CsvFetchError(url, reason) => write!(f, "unable to fetch {}: {}", url, reason),
+            UndefinedVariable(key) => write!(
+                f,
+                "\"${{{}}}\" is not defined, pass --define {}=VALUE",
+                key, key
+            ),
+            RegisterMapRequired(feature) => write!(
+                f,
+                "{} needs a register map, load one with --csr-csv",
+                feature
+            ),
+            MissingDependentOption { option, requires } => {
+                write!(f, "{} requires {}", option, requires)
+            }
+            UnsafeWrite { address } => write!(
+                f,
+                "0x{:08x} is not a known register or memory region, pass --unsafe to write to it anyway",
+                address
+            ),
+            ReadTooLarge { requested, limit } => write!(
+                f,
+                "reading {} words is more than the limit of {}, pass --max-read-size 0 to read it anyway",
+                requested, limit
+            ),
+            UnalignedLength(length) => write!(
+                f,
+                "a length of {} bytes is not a whole number of 32-bit words (use --warn-unaligned-length to drop the partial word)",
+                length
+            ),
+            ValueFileParseError(line, reason) => {
+                write!(f, "line {} of the value file: {}", line, reason)
+            }
+            ExpressionParseError(column, reason) => {
+                write!(f, "column {} of --watch-expr: {}", column, reason)
+            }
+            ConfirmationMismatch { confirmed, actual } => write!(
+                f,
+                "--confirm-size is {} bytes but the file is {} bytes, make sure this is the right file before flashing",
+                confirmed, actual
+            ),
+        }
+    }
 }
 
+impl std::error::Error for ConfigError {}
+
 impl std::convert::From<io::Error> for ConfigError {
     fn from(e: io::Error) -> ConfigError {
         ConfigError::IoError(e)
@@ -78,6 +217,315 @@ pub fn parse_u32(value: &str) -> Result<u32, ConfigError> {
     }
 }
 
+/// Parse a string of hex digits, such as `0x0201cafe` or `02 01 ca fe`,
+/// into bytes. Whitespace between digits is ignored.
+pub fn parse_hex_bytes(value: &str) -> Result<Vec<u8>, ConfigError> {
+    let digits: String = value.split_whitespace().collect();
+    let digits = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+        .unwrap_or(&digits);
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return Err(ConfigError::InvalidConfig(format!(
+            "\"{}\" is not a whole number of hex bytes",
+            value
+        )));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(digits.get(i..i + 2).unwrap_or(""), 16).map_err(|_| {
+                ConfigError::InvalidConfig(format!("\"{}\" is not a string of hex bytes", value))
+            })
+        })
+        .collect()
+}
+
+/// Parse a number for `option` that may have a fractional part.
+pub fn parse_f64(option: &str, value: &str) -> Result<f64, ConfigError> {
+    match value.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(n),
+        _ => Err(ConfigError::InvalidConfig(format!(
+            "{} takes a number, not '{}'",
+            option, value
+        ))),
+    }
+}
+
+/// Parse an RFC 3339 timestamp, such as `2024-05-01T22:00:00Z` or
+/// `2024-05-01T23:00:00.5+01:00`. Times before 1970 are refused.
+pub fn parse_rfc3339(value: &str) -> Result<SystemTime, ConfigError> {
+    use std::convert::TryFrom;
+    let invalid = || {
+        ConfigError::InvalidConfig(format!(
+            "\"{}\" is not an RFC 3339 timestamp, such as 2024-05-01T22:00:00Z",
+            value
+        ))
+    };
+    let number = |digits: &str| -> Result<u32, ConfigError> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        digits.parse().map_err(|_| invalid())
+    };
+    let bytes = value.as_bytes();
+    if !value.is_ascii()
+        || bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return Err(invalid());
+    }
+    let (year, month, day) = (
+        number(&value[0..4])?,
+        number(&value[5..7])?,
+        number(&value[8..10])?,
+    );
+    let (hour, minute, second) = (
+        number(&value[11..13])?,
+        number(&value[14..16])?,
+        number(&value[17..19])?,
+    );
+
+    let mut rest = &value[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        // Anything finer than a nanosecond is dropped
+        let digits = &fraction[..len.min(9)];
+        nanos = number(digits)? * 10u32.pow(9 - digits.len() as u32);
+        rest = &fraction[len..];
+    }
+    let offset = match rest.as_bytes() {
+        b"Z" | b"z" => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let (hours, minutes) = (number(&rest[1..3])?, number(&rest[4..6])?);
+            if hours > 23 || minutes > 59 {
+                return Err(invalid());
+            }
+            let offset = i64::from(hours * 3600 + minutes * 60);
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    // A second of 60 is a leap second
+    if !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
+
+    // Days since 1970-01-01, counting from March so the leap day comes last
+    let (y, m) = if month <= 2 {
+        (i64::from(year) - 1, i64::from(month) + 9)
+    } else {
+        (i64::from(year), i64::from(month) - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86400 + i64::from(hour * 3600 + minute * 60 + second) - offset;
+    let seconds = u64::try_from(seconds).map_err(|_| invalid())?;
+    Ok(UNIX_EPOCH + Duration::new(seconds, nanos))
+}
+
+/// Parse a value to write, accepting `on`/`true` and `off`/`false` (in any
+/// case) as 1 and 0 in addition to the numbers `parse_u32` understands.
+pub fn parse_bool_value(value: &str) -> Result<u32, ConfigError> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" => return Ok(1),
+        "off" | "false" => return Ok(0),
+        _ => (),
+    }
+    match parse_u32(value) {
+        Ok(o) => Ok(o),
+        // Something that looks like a number gets the more specific error
+        Err(e) if value.starts_with(|c: char| c.is_ascii_digit()) => Err(e),
+        Err(_) => Err(ConfigError::UnrecognizedValue(value.to_owned())),
+    }
+}
+
+/// Parse a serial flow control mode: `none`, `rtscts` or `xonxoff`.
+pub fn parse_flow_control(value: &str) -> Result<FlowControl, ConfigError> {
+    match value {
+        "none" => Ok(FlowControl::None),
+        "rtscts" => Ok(FlowControl::Hardware),
+        "xonxoff" => Ok(FlowControl::Software),
+        unknown => Err(ConfigError::InvalidConfig(format!(
+            "unknown flow control '{}'",
+            unknown
+        ))),
+    }
+}
+
+/// Parse the minimum USB speed to accept: `full`, `high` or `super`.
+pub fn parse_usb_speed(value: &str) -> Result<UsbSpeed, ConfigError> {
+    match value {
+        "full" => Ok(UsbSpeed::Full),
+        "high" => Ok(UsbSpeed::High),
+        "super" => Ok(UsbSpeed::Super),
+        unknown => Err(ConfigError::InvalidConfig(format!(
+            "unknown usb speed '{}'",
+            unknown
+        ))),
+    }
+}
+
+/// Split a `KEY=VALUE` definition.
+pub fn parse_define(definition: &str) -> Result<(String, String), ConfigError> {
+    match definition.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(ConfigError::InvalidConfig(format!(
+            "definition \"{}\" should look like KEY=VALUE",
+            definition
+        ))),
+    }
+}
+
+/// Replace each `${KEY}` in `line` with its value from `defines`.
+pub fn substitute_defines(
+    line: &str,
+    defines: &HashMap<String, String>,
+) -> Result<String, ConfigError> {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let key = &rest[start + 2..end];
+        let value = defines
+            .get(key)
+            .ok_or_else(|| ConfigError::UndefinedVariable(key.to_owned()))?;
+        out.push_str(&rest[..start]);
+        out.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Parse an address offset, which may be negative, such as `0x1000` or
+/// `-0x1000`.
+pub fn parse_address_offset(value: &str) -> Result<i64, ConfigError> {
+    match value.strip_prefix('-') {
+        Some(magnitude) => Ok(-i64::from(parse_u32(magnitude)?)),
+        None => Ok(i64::from(parse_u32(value)?)),
+    }
+}
+
+/// Move `addr` by `offset`, failing if the result doesn't fit in 32 bits.
+pub fn offset_address(addr: u32, offset: i64) -> Result<u32, ConfigError> {
+    use std::convert::TryFrom;
+    u32::try_from(i64::from(addr) + offset).map_err(|_| {
+        ConfigError::AddressOutOfRange(format!(
+            "0x{:08x} moved by address offset {} is outside the 32-bit address space",
+            addr, offset
+        ))
+    })
+}
+
+/// Ensure `addr` is aligned to a 32-bit access, unless `allow_unaligned`
+/// is set for buses that support unaligned accesses.
+pub fn check_alignment(addr: u32, allow_unaligned: bool) -> Result<(), ConfigError> {
+    if !allow_unaligned && addr & 3 != 0 {
+        return Err(ConfigError::UnalignedAddress(addr));
+    }
+    Ok(())
+}
+
+/// What to do with a range length that isn't a whole number of words.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LengthPolicy {
+    /// Refuse it with `ConfigError::UnalignedLength`
+    Reject,
+
+    /// Drop the partial word at the end, with a warning
+    RoundDown,
+}
+
+/// Apply `policy` to a range of `length` bytes, returning the length that
+/// should be used. A length that would round down to nothing is refused
+/// either way.
+pub fn check_length(length: u32, policy: LengthPolicy) -> Result<u32, ConfigError> {
+    if length % 4 == 0 {
+        return Ok(length);
+    }
+    let rounded = length & !3;
+    match policy {
+        LengthPolicy::RoundDown if rounded != 0 => {
+            warn!(
+                "a length of {} bytes is not a whole number of words, only the first {} will be used",
+                length, rounded
+            );
+            Ok(rounded)
+        }
+        _ => Err(ConfigError::UnalignedLength(length)),
+    }
+}
+
+/// Ensure a write of `length` bytes at `addr` lands entirely on registers
+/// or inside memory regions of the register map, if `--safe` asks for that.
+/// A length under one word still checks the word at `addr`.
+pub fn check_write_allowed(cfg: &Config, addr: u32, length: u32) -> Result<(), ConfigError> {
+    if !cfg.safe_writes || cfg.unsafe_writes {
+        return Ok(());
+    }
+    let end = u64::from(addr) + u64::from(length.max(4));
+    let mut next = u64::from(addr);
+    while next < end {
+        let word = u32::try_from(next).map_err(|_| ConfigError::UnsafeWrite { address: addr })?;
+        let region = cfg
+            .memory_regions
+            .values()
+            .find(|r| word >= r.base && u64::from(word) < u64::from(r.base) + u64::from(r.length));
+        if let Some(region) = region {
+            // Skip the rest of the region in one step
+            next = u64::from(region.base) + u64::from(region.length);
+        } else if cfg.csr_registers.iter().any(|r| r.address == word)
+            || cfg.register_mapping.values().any(|a| *a == Some(word))
+        {
+            next += 4;
+        } else {
+            return Err(ConfigError::UnsafeWrite { address: word });
+        }
+    }
+    Ok(())
+}
+
+/// The size of `file_name` in bytes, or 0 if it can't be read yet. Used to
+/// size writes of a whole file; opening the file later reports the error.
+fn file_length(file_name: &str) -> u32 {
+    std::fs::metadata(file_name)
+        .map(|m| u32::try_from(m.len()).unwrap_or(u32::MAX))
+        .unwrap_or(0)
+}
+
 pub fn parse_u32_address(value: &str, offset: u32) -> Result<Option<u32>, ConfigError> {
     let (value, base) = get_base(value);
     u32::from_str_radix(value, base)
@@ -85,350 +533,2822 @@ pub fn parse_u32_address(value: &str, offset: u32) -> Result<Option<u32>, Config
         .or_else(|e| Err(ConfigError::NumberParseError(value.to_owned(), e)))
 }
 
-#[derive(Clone)]
-pub struct Config {
-    pub memory_address: Option<u32>,
-    pub memory_value: Option<u32>,
-    pub server_kind: Vec<ServerKind>,
-    pub bind_addr: String,
-    pub bind_port: u16,
-    pub gdb_port: u16,
-    pub random_loops: Option<u32>,
-    pub random_address: Option<u32>,
-    pub random_range: Option<u32>,
-    pub messible_address: Option<u32>,
+/// Parse an address that is either a number or the name of a register in
+/// `register_mapping`. Names can only be looked up once a register map is
+/// loaded, so without one a name is reported as `RegisterMapRequired`
+/// rather than as a malformed number.
+pub fn parse_named_address(
+    value: &str,
+    register_mapping: &HashMap<String, Option<u32>>,
+    offset: u32,
+) -> Result<u32, ConfigError> {
+    if let Some(mapped_addr) = register_mapping.get(&value.to_lowercase()) {
+        return (*mapped_addr).ok_or_else(|| ConfigError::AddressOutOfRange(value.to_owned()));
+    }
+    match parse_u32_address(value, offset) {
+        Ok(addr) => addr.ok_or_else(|| ConfigError::AddressOutOfRange(value.to_owned())),
+        Err(_) if register_mapping.is_empty() && is_register_name(value) => Err(
+            ConfigError::RegisterMapRequired(format!("the named address \"{}\"", value)),
+        ),
+        Err(e) => Err(e),
+    }
+}
 
-    /// A mapping of CSR names to translated register offsets. If an
-    /// address is a valid CSR but cannot be mapped due to the Wishbone
-    /// aperture being too small, this will contain `Some(None)`.
-    pub register_mapping: HashMap<String, Option<u32>>,
-    pub debug_offset: u32,
-    pub load_name: Option<String>,
-    pub load_addr: Option<u32>,
-    pub load_flash: bool,
-    pub terminal_mouse: bool,
-    pub burst_length: u32,
-    pub hexdump: bool,
-    pub burst_source: Option<String>,
-    pub flash_no_reset: bool,
-    pub careful_flashing: bool,
+/// Describe how `parse_named_address()` turns `value` into an address,
+/// e.g. `uart_rxtx (csv) = 0xe0001800, - register offset 0x00000000 = 0xe0001800`.
+pub fn explain_named_address(
+    value: &str,
+    register_mapping: &HashMap<String, Option<u32>>,
+    offset: u32,
+) -> Result<String, ConfigError> {
+    let addr = parse_named_address(value, register_mapping, offset)?;
+    let source = if register_mapping.contains_key(&value.to_lowercase()) {
+        "csv"
+    } else {
+        "number"
+    };
+    Ok(format!(
+        "{} ({}) = 0x{:08x}, - register offset 0x{:08x} = 0x{:08x}",
+        value,
+        source,
+        addr.wrapping_add(offset),
+        offset,
+        addr
+    ))
 }
 
-impl Default for Config {
-    fn default() -> Config {
-        Config {
-            memory_address: None,
-            memory_value: None,
-            server_kind: vec![],
-            bind_addr: "127.0.0.1".to_owned(),
-            bind_port: 1234,
-            gdb_port: 3333,
-            random_loops: None,
-            random_address: None,
-            random_range: None,
-            messible_address: None,
-            register_mapping: HashMap::new(),
-            debug_offset: 0,
-            load_name: None,
-            load_addr: None,
-            load_flash: false,
-            terminal_mouse: false,
-            burst_length: 4,
-            hexdump: false,
-            burst_source: None,
-            flash_no_reset: false,
-            careful_flashing: false,
+/// Whether `value` could be the name of a register rather than a number.
+fn is_register_name(value: &str) -> bool {
+    value.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && !value.to_lowercase().starts_with("0x")
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split a `host`, `host:port`, `ipv6` or `[ipv6]:port` string into the
+/// host and an optional port. Brackets are removed from IPv6 literals.
+pub fn split_host_port(value: &str) -> Result<(String, Option<u16>), ConfigError> {
+    let malformed = || ConfigError::MalformedAddress(value.to_owned());
+    let parse_port = |s: &str| s.parse::<u16>().map_err(|_| malformed());
+
+    if let Some(bracketed) = value.strip_prefix('[') {
+        let end = bracketed.find(']').ok_or_else(malformed)?;
+        let host = &bracketed[..end];
+        if host.parse::<Ipv6Addr>().is_err() {
+            return Err(malformed());
+        }
+        match &bracketed[end + 1..] {
+            "" => Ok((host.to_owned(), None)),
+            rest => match rest.strip_prefix(':') {
+                Some(port) => Ok((host.to_owned(), Some(parse_port(port)?))),
+                None => Err(malformed()),
+            },
+        }
+    } else if value.parse::<Ipv6Addr>().is_ok() {
+        Ok((value.to_owned(), None))
+    } else if let Some((host, port)) = value.rsplit_once(':') {
+        // An unbracketed IPv6 address can't carry a port
+        if host.is_empty() || host.contains(':') {
+            return Err(malformed());
         }
+        Ok((host.to_owned(), Some(parse_port(port)?)))
+    } else {
+        Ok((value.to_owned(), None))
     }
 }
 
-impl Config {
-    fn create_bridge(matches: &ArgMatches) -> Result<Bridge, ConfigError> {
-        // If SPI pins are specified, then assume the bridge must be SPI.
-        if let Some(pins) = matches.value_of("spi-pins") {
-            return SpiBridge::new(pins)
-                .or_else(|e| Err(ConfigError::SpiParseError(e)))?
-                .create()
-                .map_err(|e| {
-                    ConfigError::InvalidConfig(format!("unable to create spi bridge: {}", e))
-                });
-        }
+/// Join a host and port, adding brackets around IPv6 literals.
+pub fn join_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
 
-        // UART bridge config
-        if let Some(port) = matches.value_of("serial") {
-            // Strip off the trailing ":" on Windows, since it's confusing
-            let serial_port = if cfg!(windows) && port.ends_with(':') {
-                port.get(0..port.len() - 1).unwrap_or("")
-            } else {
-                port
-            };
-            let mut uart_config = UartBridge::new(serial_port).or_else(|e| {
-                Err(ConfigError::InvalidConfig(format!(
-                    "invalid serial port: {}",
-                    e
-                )))
-            })?;
+/// How long to wait between retries of a failed operation.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RetryBackoff {
+    /// Retry immediately
+    None,
 
-            if let Some(baud) = matches.value_of("baud") {
-                uart_config.baud(parse_u32(baud)?);
-            }
+    /// Wait the base delay before every retry
+    Fixed,
+
+    /// Double the delay after every retry, up to the maximum delay
+    Exponential,
+}
 
-            return uart_config.create().map_err(|e| {
-                ConfigError::InvalidConfig(format!("unable to create uart bridge: {}", e))
-            });
+impl RetryBackoff {
+    pub fn from_string(item: &str) -> Result<RetryBackoff, ConfigError> {
+        match item {
+            "none" => Ok(RetryBackoff::None),
+            "fixed" => Ok(RetryBackoff::Fixed),
+            "exponential" => Ok(RetryBackoff::Exponential),
+            unknown => Err(ConfigError::InvalidConfig(format!(
+                "unknown retry backoff '{}'",
+                unknown
+            ))),
         }
+    }
 
-        // PCIe BAR-as-a-file
-        if let Some(pcie_bar) = matches.value_of("pcie-bar") {
-            return PCIeBridge::new(pcie_bar)
-                .or_else(|e| {
-                    Err(ConfigError::InvalidConfig(format!(
-                        "invalid pcie bar: {}",
-                        e
-                    )))
-                })?
-                .create()
-                .map_err(|e| {
-                    ConfigError::InvalidConfig(format!("unable to create pcie bridge: {}", e))
-                });
+    /// The delay before retry number `attempt`, counting from 1.
+    pub fn delay(self, attempt: u32, base_ms: u32, max_ms: u32) -> Duration {
+        let ms = match self {
+            RetryBackoff::None => 0,
+            RetryBackoff::Fixed => base_ms,
+            RetryBackoff::Exponential => 1u32
+                .checked_shl(attempt.saturating_sub(1))
+                .and_then(|factor| base_ms.checked_mul(factor))
+                .map_or(max_ms, |ms| ms.min(max_ms)),
+        };
+        Duration::from_millis(ms.into())
+    }
+}
+
+/// Where diagnostics are logged.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogTarget {
+    /// Standard error, with colours
+    Stderr,
+
+    /// The local syslog daemon, through `/dev/log`
+    Syslog,
+
+    /// The systemd journal, through its native socket
+    Journald,
+}
+
+impl LogTarget {
+    pub fn from_string(item: &str) -> Result<LogTarget, ConfigError> {
+        match item {
+            "stderr" => Ok(LogTarget::Stderr),
+            "syslog" => Ok(LogTarget::Syslog),
+            "journald" => Ok(LogTarget::Journald),
+            unknown => Err(ConfigError::InvalidConfig(format!(
+                "unknown log target '{}'",
+                unknown
+            ))),
         }
+    }
 
-        // Ethernet (TCP or UDP)
-        if let Some(host) = matches.value_of("ethernet-host") {
-            let ethernet_tcp = matches.is_present("ethernet-tcp");
-            let ethernet_port = parse_u16(matches.value_of("ethernet-port").unwrap())?;
-            let mut ebc = EthernetBridge::new(host)
-                .or_else(|_| EthernetBridge::new(&format!("{}:{}", host, ethernet_port)))
-                .or_else(|e| {
-                    Err(ConfigError::InvalidConfig(format!(
-                        "invalid ethernet address: {}",
-                        e
-                    )))
-                })?;
-            ebc.protocol(if ethernet_tcp {
-                EthernetBridgeProtocol::TCP
-            } else {
-                EthernetBridgeProtocol::UDP
-            })
-            .port(ethernet_port);
-            return ebc.create().map_err(|e| {
-                ConfigError::InvalidConfig(format!("unable to create ethernet bridge: {}", e))
-            });
+    pub fn name(self) -> &'static str {
+        match self {
+            LogTarget::Stderr => "stderr",
+            LogTarget::Syslog => "syslog",
+            LogTarget::Journald => "journald",
         }
+    }
+}
+
+/// A sequence of words written across a range by `--pattern`, for memory
+/// testing. Word `i` is the word at `base + 4 * i`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FillPattern {
+    /// Every word is `0x00000000`
+    Zeros,
+
+    /// Every word is `0xffffffff`
+    Ones,
+
+    /// Word `i` has only bit `i % 32` set, so the set bit walks from bit 0
+    /// to bit 31 and starts over
+    Walking,
+
+    /// Even words are `0x55555555` and odd words are `0xaaaaaaaa`, so every
+    /// bit differs from its neighbours in the word and in the next word
+    Checker,
+
+    /// Word `i` is `base + i`, wrapping around at 32 bits
+    Incrementing,
+}
+
+impl FillPattern {
+    /// Every pattern, in the order `--memtest` runs them.
+    pub const ALL: &'static [FillPattern] = &[
+        FillPattern::Zeros,
+        FillPattern::Ones,
+        FillPattern::Walking,
+        FillPattern::Checker,
+        FillPattern::Incrementing,
+    ];
 
-        // Fall back to USB
-        let mut usb_config = UsbBridge::new();
-        if let Some(vid) = matches.value_of("vid") {
-            usb_config.vid(parse_u16(vid)?);
+    pub fn from_string(item: &str) -> Result<FillPattern, ConfigError> {
+        match item {
+            "zeros" => Ok(FillPattern::Zeros),
+            "ones" => Ok(FillPattern::Ones),
+            "walking" => Ok(FillPattern::Walking),
+            "checker" => Ok(FillPattern::Checker),
+            "incrementing" => Ok(FillPattern::Incrementing),
+            unknown => Err(ConfigError::InvalidConfig(format!(
+                "unknown pattern '{}'",
+                unknown
+            ))),
         }
-        if let Some(pid) = matches.value_of("pid") {
-            usb_config.pid(parse_u16(pid)?);
+    }
+
+    /// The word written at index `i` of a range starting at `base`.
+    pub fn word(self, base: u32, i: u32) -> u32 {
+        match self {
+            FillPattern::Zeros => 0,
+            FillPattern::Ones => 0xffff_ffff,
+            FillPattern::Walking => 1 << (i % 32),
+            FillPattern::Checker if i % 2 == 0 => 0x5555_5555,
+            FillPattern::Checker => 0xaaaa_aaaa,
+            FillPattern::Incrementing => base.wrapping_add(i),
         }
-        if let Some(bus) = matches.value_of("bus") {
-            usb_config.bus(parse_u8(bus)?);
+    }
+
+    /// `len` bytes of the pattern for a range starting at `base`, as they
+    /// are laid out in memory. A trailing partial word is cut short.
+    pub fn bytes(self, base: u32, len: u32) -> Vec<u8> {
+        let mut data: Vec<u8> = (0..len.div_ceil(4))
+            .flat_map(|i| self.word(base, i).to_le_bytes())
+            .collect();
+        data.truncate(len as usize);
+        data
+    }
+}
+
+/// Parse a comma-separated list of bridge error kinds, such as
+/// `timeout,pipe`.
+pub fn parse_retry_on(value: &str) -> Result<Vec<BridgeErrorKind>, ConfigError> {
+    value
+        .split(',')
+        .map(|name| {
+            BridgeErrorKind::from_name(name.trim()).ok_or_else(|| {
+                let names: Vec<&str> = BridgeErrorKind::ALL.iter().map(|k| k.name()).collect();
+                ConfigError::InvalidConfig(format!(
+                    "unknown error kind '{}', expected one of {}",
+                    name.trim(),
+                    names.join(", ")
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Parse the initial memory contents for a mock bridge. Each line holds
+/// an `address,value` pair, and blank lines and `#` comments are ignored.
+pub fn parse_mock_init(contents: &str) -> Result<Vec<(u32, u32)>, ConfigError> {
+    let mut values = vec![];
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
         }
-        if let Some(device) = matches.value_of("device") {
-            usb_config.device(parse_u8(device)?);
+        match line
+            .split(',')
+            .map(|f| f.trim())
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [addr, value] => values.push((parse_u32(addr)?, parse_u32(value)?)),
+            _ => {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "mock memory line \"{}\" is not an address,value pair",
+                    line
+                )))
+            }
         }
-        usb_config
-            .create()
-            .map_err(|e| ConfigError::InvalidConfig(format!("unable to create usb bridge: {}", e)))
     }
+    Ok(values)
+}
 
-    pub fn parse(matches: ArgMatches) -> Result<(Self, Bridge), ConfigError> {
-        let mut server_kind = vec![];
+/// List `candidates` on `output` and read the number of one from `input`,
+/// asking again until a valid choice is made.
+pub fn pick_usb_device<R: io::BufRead, W: io::Write>(
+    candidates: &[UsbDeviceInfo],
+    mut input: R,
+    mut output: W,
+) -> Result<&UsbDeviceInfo, ConfigError> {
+    writeln!(output, "{} usb devices match:", candidates.len())?;
+    for (i, candidate) in candidates.iter().enumerate() {
+        writeln!(output, "  {}) {}", i + 1, candidate)?;
+    }
+    let mut line = String::new();
+    loop {
+        write!(output, "use device [1-{}]: ", candidates.len())?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(ConfigError::InvalidConfig(
+                "no usb device was chosen".to_owned(),
+            ));
+        }
+        match line.trim().parse::<usize>() {
+            Ok(n) if n >= 1 && n <= candidates.len() => return Ok(&candidates[n - 1]),
+            _ => writeln!(output, "\"{}\" is not one of the devices", line.trim())?,
+        }
+    }
+}
 
-        let load_name = matches.value_of("load-name").map(|n| n.to_owned());
-        let load_flash = matches.is_present("load-flash");
-        let load_addr = if let Some(addr) = matches.value_of("load-address") {
-            if load_name.is_none() & !load_flash {
-                server_kind.push(ServerKind::MemoryAccess);
-            }
-            Some(parse_u32(addr)?)
-        } else {
-            None
-        };
-        if load_addr.is_some() & load_name.is_some() & load_flash {
-            server_kind.push(ServerKind::FlashProgram);
+/// Parse a `--value-file`, which holds one value per line. Blank lines and
+/// `#` comments are ignored.
+pub fn parse_value_list(contents: &str) -> Result<Vec<u32>, ConfigError> {
+    let mut values = vec![];
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
         }
+        values.push(
+            parse_u32(line)
+                .map_err(|e| ConfigError::ValueFileParseError(idx + 1, e.to_string()))?,
+        );
+    }
+    Ok(values)
+}
 
-        let memory_value = matches
-            .value_of("value")
-            .map(|v| parse_u32(v))
-            .transpose()?;
+/// Where the `csr.csv` register map comes from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum CsvSource {
+    /// A file on the local filesystem
+    File(String),
 
-        // unwrap() is safe because there is a default value
-        let gdb_port = parse_u16(matches.value_of("gdb-port").unwrap())?;
-        let bind_port = parse_u16(matches.value_of("wishbone-port").unwrap())?;
-        let burst_length = parse_u32(matches.value_of("burst-length").unwrap())?;
+    /// An `http://` or `https://` URL, downloaded once at startup
+    Url(String),
+}
 
-        let bind_addr = matches
-            .value_of("bind-addr")
-            .map(|addr| addr.to_owned())
-            .unwrap_or_else(|| "127.0.0.1".to_owned());
+impl CsvSource {
+    pub fn from_string(item: &str) -> CsvSource {
+        if item.starts_with("http://") || item.starts_with("https://") {
+            CsvSource::Url(item.to_owned())
+        } else {
+            CsvSource::File(item.to_owned())
+        }
+    }
 
-        if let Some(server_kinds) = matches.values_of("server-kind") {
-            for sk in server_kinds {
-                server_kind.push(ServerKind::from_string(sk)?);
-            }
+    fn read(&self) -> Result<Vec<u8>, ConfigError> {
+        match self {
+            CsvSource::File(path) => Ok(std::fs::read(path)?),
+            CsvSource::Url(url) => crate::fetch::fetch_url(url),
         }
+    }
+}
 
-        let random_loops = if let Some(random_loops) = matches.value_of("random-loops") {
-            Some(parse_u32(random_loops)?)
-        } else {
-            None
-        };
+/// Describes which bridge is used to reach the target device.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BridgeKind {
+    /// USB, such as a Fomu running ValentyUSB
+    Usb,
 
-        let random_address = if let Some(random_address) = matches.value_of("random-address") {
-            Some(parse_u32(random_address)?)
-        } else {
-            None
-        };
+    /// A serial or other UART port
+    Uart,
 
-        let random_range = if let Some(random_range) = matches.value_of("random-range") {
-            Some(parse_u32(random_range)?)
-        } else {
-            None
-        };
+    /// Ethernet, using either TCP or UDP
+    Ethernet,
 
-        let (register_mapping, offset) = Self::parse_csr_csv(
-            matches.value_of("csr-csv"),
-            matches.value_of("register-offset"),
-        )?;
+    /// A PCIe BAR exposed as a file
+    PCIe,
 
-        let messible_address = if let Some(messible_address) = matches.value_of("messible-address")
-        {
-            Some(
-                parse_u32_address(messible_address, offset)?
-                    .ok_or_else(|| ConfigError::AddressOutOfRange(messible_address.to_owned()))?,
-            )
-        } else if let Some(base) = register_mapping.get("messible_out") {
-            Some((*base).ok_or_else(|| ConfigError::AddressOutOfRange("messible_out".to_owned()))?)
-        } else {
-            None
-        };
+    /// Bit-banged SPI pins
+    Spi,
 
-        let debug_offset = if let Some(debug_offset) = matches.value_of("debug-offset") {
-            parse_u32_address(debug_offset, offset)?
-                .ok_or_else(|| ConfigError::AddressOutOfRange(debug_offset.to_owned()))?
-        } else if let Some(debug_offset) = register_mapping.get("vexriscv_debug") {
-            (*debug_offset)
-                .ok_or_else(|| ConfigError::AddressOutOfRange("vexriscv_debug".to_owned()))?
-        } else {
-            0xf00f_0000
-        };
+    /// No device at all, reads and writes go to memory on the host
+    Mock,
 
-        let memory_address = if let Some(addr) = matches.value_of("address") {
-            if let Some(mapped_addr) = register_mapping.get(&addr.to_lowercase()) {
-                Some(
-                    (*mapped_addr)
-                        .ok_or_else(|| ConfigError::AddressOutOfRange(addr.to_owned()))?,
-                )
-            } else {
-                Some(
-                    parse_u32_address(addr, offset)?
-                        .ok_or_else(|| ConfigError::AddressOutOfRange(addr.to_owned()))?,
-                )
-            }
-        } else {
-            None
-        };
+    /// Nothing is opened, for operations that never touch the device
+    None,
+}
 
-        if server_kind.is_empty() {
-            if memory_address.is_none() {
-                return Err(ConfigError::NoOperationSpecified);
-            }
-            server_kind.push(ServerKind::MemoryAccess);
+impl BridgeKind {
+    /// Every kind of bridge, in the order `--list-bridge-kinds` shows them.
+    /// `None` is left out, as it's never chosen by the user.
+    pub const ALL: &'static [BridgeKind] = &[
+        BridgeKind::Usb,
+        BridgeKind::Uart,
+        BridgeKind::Ethernet,
+        BridgeKind::PCIe,
+        BridgeKind::Spi,
+        BridgeKind::Mock,
+    ];
+
+    /// A short lowercase name for the bridge.
+    pub fn name(self) -> &'static str {
+        match self {
+            BridgeKind::Usb => "usb",
+            BridgeKind::Uart => "uart",
+            BridgeKind::Ethernet => "ethernet",
+            BridgeKind::PCIe => "pcie",
+            BridgeKind::Spi => "spi",
+            BridgeKind::Mock => "mock",
+            BridgeKind::None => "none",
         }
+    }
+}
 
-        // Validate the configuration is correct
-        if matches.value_of("csr-csv").is_some() {
-            if server_kind.contains(&ServerKind::GDB) {
-                // You asked for --server gdb but no vexriscv jtag interfaces is found in the csr.csv file it should complain.
-                if !register_mapping.contains_key("vexriscv_debug") {
-                    return Err(ConfigError::InvalidConfig(
+/// Settings that depend on which bridge is in use. `ConfigBuilder::build()`
+/// takes any that weren't given explicitly from here.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BridgeDefaults {
+    /// Serial baud rate, for bridges that have one
+    pub baud: Option<u32>,
+
+    /// Port on the remote host, for network bridges
+    pub port: Option<u16>,
+
+    /// How long, in ms, to wait for the device to answer. `None` if the
+    /// bridge uses its own fixed timeouts.
+    pub timeout: Option<u32>,
+
+    /// Words moved per bridge call by range operations. USB bursts go out
+    /// as 4096-byte packets, so this matches one packet.
+    pub chunk_size: u32,
+}
+
+impl BridgeDefaults {
+    pub fn for_kind(kind: BridgeKind) -> BridgeDefaults {
+        let none = BridgeDefaults {
+            baud: None,
+            port: None,
+            timeout: None,
+            chunk_size: 1,
+        };
+        match kind {
+            BridgeKind::Usb | BridgeKind::Mock => BridgeDefaults {
+                chunk_size: 1024,
+                ..none
+            },
+            BridgeKind::Uart => BridgeDefaults {
+                baud: Some(115_200),
+                timeout: Some(1000),
+                ..none
+            },
+            BridgeKind::Ethernet => BridgeDefaults {
+                port: Some(1234),
+                timeout: Some(1000),
+                ..none
+            },
+            BridgeKind::PCIe | BridgeKind::Spi | BridgeKind::None => none,
+        }
+    }
+}
+
+/// A named memory region from the `memory_region` rows of `csr.csv`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub length: u32,
+}
+
+/// Memory regions keyed by lowercase name.
+pub type MemoryRegions = HashMap<String, MemoryRegion>;
+
+/// One 32-bit word of a CSR from the `csr_register` rows of `csr.csv`.
+/// CSRs that span several words are split, using the same indexed names as
+/// the register map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsrRegister {
+    pub name: String,
+    pub address: u32,
+    pub write_only: bool,
+}
+
+/// Everything read from a `csr.csv` file.
+#[derive(Default)]
+pub struct CsrCsvContents {
+    /// Register, CSR base and memory region names, and their addresses
+    pub register_mapping: HashMap<String, Option<u32>>,
+
+    /// The numeric offset that was subtracted from each address
+    pub offset: u32,
+
+    pub memory_regions: MemoryRegions,
+
+    /// CSR peripheral base addresses, from `csr_base` rows
+    pub csr_bases: HashMap<String, u32>,
+
+    /// Every CSR word, in the order they appear in the file
+    pub registers: Vec<CsrRegister>,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub memory_address: Option<u32>,
+    pub memory_value: Option<u32>,
+    pub server_kind: Vec<ServerKind>,
+    pub bind_addr: String,
+    pub bind_port: u16,
+    pub gdb_port: u16,
+    pub random_loops: Option<u32>,
+    pub random_address: Option<u32>,
+    pub random_range: Option<u32>,
+    pub random_seed: Option<u32>,
+    pub messible_address: Option<u32>,
+
+    /// A mapping of CSR names to translated register offsets. If an
+    /// address is a valid CSR but cannot be mapped due to the Wishbone
+    /// aperture being too small, this will contain `Some(None)`.
+    pub register_mapping: HashMap<String, Option<u32>>,
+
+    /// Memory regions listed in `csr.csv`, by lowercase name.
+    pub memory_regions: MemoryRegions,
+
+    /// CSR peripheral base addresses listed in `csr.csv`, by lowercase name.
+    pub csr_bases: HashMap<String, u32>,
+
+    /// Every CSR word listed in `csr.csv`.
+    pub csr_registers: Vec<CsrRegister>,
+    pub debug_offset: u32,
+    pub load_name: Option<String>,
+    pub load_addr: Option<u32>,
+    pub load_flash: bool,
+
+    /// The size of `load_name`, restated by the user, which must match
+    /// before anything is written to flash.
+    pub confirm_size: Option<u32>,
+    pub terminal_mouse: bool,
+    pub burst_length: u32,
+    pub hexdump: bool,
+    pub burst_source: Option<String>,
+
+    /// A file of values to write to consecutive words from `memory_address`.
+    pub value_file: Option<String>,
+
+    /// The contents of `value_file`, loaded by `build()`.
+    pub values: Vec<u32>,
+
+    pub flash_no_reset: bool,
+    pub careful_flashing: bool,
+    pub allow_unaligned: bool,
+
+    /// What to do when `burst_length` isn't a whole number of words.
+    pub length_policy: LengthPolicy,
+
+    /// If set, result and diagnostic lines are prefixed with `[label] `
+    /// so output from several instances can be told apart.
+    pub label: Option<String>,
+
+    /// Where diagnostics go. Results are always printed on stdout.
+    pub log_target: LogTarget,
+
+    /// If set, a single read is repeated every `poll_interval` milliseconds
+    /// and the latest value is written to this file. With JSON Lines output,
+    /// each value is added to the end of the file instead.
+    pub poll_file: Option<String>,
+
+    pub poll_interval: u32,
+
+    /// If set, every single-word read is issued twice and only accepted
+    /// when both reads agree. This halves read throughput.
+    pub double_read: bool,
+
+    /// Number of extra attempts made when a `double_read` pair disagrees,
+    /// or when a read or write fails with an error in `retry_on`.
+    pub retry_count: u32,
+
+    /// Kinds of bridge error that are worth retrying. Any other error
+    /// fails the operation straight away.
+    pub retry_on: Vec<BridgeErrorKind>,
+
+    /// LOGIC: file that `timestamp,value` rows are appended to.
+    pub logic_file: Option<String>,
+
+    /// LOGIC: stop after recording this many samples.
+    pub logic_max_samples: Option<u32>,
+
+    /// Maximum number of clients connected across all TCP servers, or 0
+    /// for no limit.
+    pub max_clients: u32,
+
+    /// Most words that a range read or register dump may read, or 0 for no
+    /// limit.
+    pub max_read_size: u32,
+
+    /// How to wait between retries: `Fixed` waits `retry_delay` ms each time,
+    /// `Exponential` doubles it on each retry up to `retry_max_delay` ms.
+    pub retry_backoff: RetryBackoff,
+
+    pub retry_delay: u32,
+
+    pub retry_max_delay: u32,
+
+    /// REPLAY: transaction log to play back.
+    pub replay_file: Option<String>,
+
+    /// REPLAY: reproduce the gaps between the recorded timestamps.
+    pub preserve_timing: bool,
+
+    /// Added to every address before it is used, including the addresses
+    /// taken from the register map. May be negative.
+    pub address_offset: i64,
+
+    /// MOCK: file giving the initial memory contents of the mock bridge.
+    pub mock_init: Option<String>,
+
+    /// Where the register map was loaded from, if anywhere.
+    pub csr_csv: Option<CsvSource>,
+
+    /// How results of memory operations are printed.
+    pub output_format: OutputFormat,
+
+    /// FILE: name of the memory region to serve.
+    pub file_region: Option<String>,
+
+    pub file_port: u16,
+
+    /// Warn about any single transaction that takes longer than this many ms.
+    pub slow_threshold: Option<u32>,
+
+    /// Values substituted for `${KEY}` in replay logs, from `--define KEY=VALUE`.
+    pub defines: HashMap<String, String>,
+
+    /// LOAD_FILE: compare a CRC32 of the loaded region with that of the file.
+    pub verify_checksum: bool,
+
+    /// Write this value to `memory_address`, then restore the original value.
+    pub pulse_value: Option<u32>,
+
+    /// How long, in ms, the pulse value is held before restoring.
+    pub pulse_width: u32,
+
+    /// Read and print every readable CSR in `csr_registers`.
+    pub dump_registers: bool,
+
+    /// Print the registers read by `dump_registers` under the name of
+    /// the CSR peripheral or memory region that holds each one.
+    pub group_by_region: bool,
+
+    /// Read ranges and registers from the highest address down, rather
+    /// than from the lowest up.
+    pub reverse: bool,
+
+    /// Words moved per bridge call by range operations. If unset, the
+    /// value from `BridgeDefaults` is used.
+    pub chunk_size: Option<u32>,
+
+    /// Read every register and compare it against this snapshot, as written
+    /// by `--dump-registers`.
+    pub compare_registers: Option<String>,
+
+    /// Where results of memory operations are written, instead of stdout.
+    pub output_path: Option<PathBuf>,
+
+    /// The file at `output_path`, opened when the configuration was built.
+    pub output_file: Option<Arc<File>>,
+
+    /// If set, `memory_address` is read `histogram_samples` times and the
+    /// distribution of the values is printed instead of a single value.
+    pub histogram: bool,
+
+    pub histogram_samples: u32,
+
+    /// The largest number of buckets a histogram is split into.
+    pub histogram_bins: u32,
+
+    /// RANDOM_TEST: keep going after a failure, and stop once this many have
+    /// been seen. Without it, the first failure stops the test.
+    pub abort_after_failures: Option<u32>,
+
+    /// RANDOM_TEST and MEMTEST: keep going after a failure, and pass at the
+    /// end if at least this percentage of loops or words succeeded.
+    pub min_success_rate: Option<f64>,
+
+    /// Single reads are also printed as `value * read_scale + read_offset`
+    /// if either of these is set.
+    pub read_scale: Option<f64>,
+
+    pub read_offset: Option<f64>,
+
+    /// Refuse writes to addresses that aren't a register or inside a memory
+    /// region of the register map, unless `unsafe_writes` is also set.
+    pub safe_writes: bool,
+
+    pub unsafe_writes: bool,
+
+    /// Stop polling after this many reads instead of running until interrupted.
+    pub poll_count: Option<u32>,
+
+    /// Stop long-running operations cleanly once this time has passed.
+    pub deadline: Option<SystemTime>,
+
+    /// Bytes sent to the bridge as they are, bypassing the Wishbone protocol.
+    /// Whatever the bridge sends back is printed.
+    pub raw_command: Option<Vec<u8>>,
+
+    /// How many bytes of address to show in dumps and listings. Without it,
+    /// addresses are padded to the 32-bit bus width.
+    pub address_width: Option<u8>,
+
+    /// Whether hex values in text and CSV output start with `0x`. JSON is
+    /// unaffected, as its values are plain numbers.
+    pub hex_prefix: bool,
+
+    /// In text output, also show values read as a size in binary units,
+    /// such as `1.5 MiB`.
+    pub human_readable: bool,
+
+    /// Put every word of a multi-word read on one line of text, as
+    /// `address=value` pairs separated by spaces.
+    pub compact: bool,
+
+    /// Read `keepalive_address` whenever the bridge has been idle for this
+    /// many ms, to keep the link from stalling. 0 disables it.
+    pub keepalive: u32,
+
+    /// The address read by `keepalive`. It must be safe to read at any time.
+    pub keepalive_address: Option<u32>,
+
+    /// Read `preflight_address`, or `keepalive_address` if it isn't set,
+    /// once before any server starts listening, and stop if that fails.
+    pub preflight: bool,
+
+    /// The address read by `preflight`. It must be safe to read.
+    pub preflight_address: Option<u32>,
+
+    /// Write this pattern across `burst_length` bytes at `memory_address`,
+    /// instead of a single value.
+    pub fill_pattern: Option<FillPattern>,
+
+    /// Write each pattern in turn across `burst_length` bytes at
+    /// `memory_address` and read it back, reporting every word that differs.
+    /// Only `fill_pattern` is used if it is set.
+    pub memtest: bool,
+
+    /// Append every word read or written to this file, in the format
+    /// `replay` reads.
+    pub trace_file: Option<String>,
+
+    /// Only add a read to `trace_file` or `logic_file` when its value has
+    /// changed since the last one recorded from that address.
+    pub trace_changes_only: bool,
+
+    /// Fail unless the word read from `memory_address` equals this.
+    pub expect_value: Option<u32>,
+
+    /// Only compare these bits of the word read against `expect_value`.
+    pub expect_mask: Option<u32>,
+
+    /// Keep reading the registers in this expression until it's true.
+    pub watch_expr: Option<WatchExpr>,
+
+    /// Give up on `watch_expr` after this many ms, rather than waiting
+    /// forever.
+    pub watch_timeout: Option<u32>,
+
+    /// Print how ADDRESS was resolved instead of running any operation.
+    pub explain_address: bool,
+
+    /// How ADDRESS was turned into `memory_address`, for `explain_address`.
+    pub address_resolution: Option<String>,
+
+    /// The bridge used to reach the device, along with its parameters.
+    /// Only the parameters relevant to `bridge_kind` are used.
+    pub bridge_kind: BridgeKind,
+    pub usb_pid: Option<u16>,
+    pub usb_vid: Option<u16>,
+    pub usb_bus: Option<u8>,
+    pub usb_device: Option<u8>,
+    pub usb_detach_kernel_driver: bool,
+    pub usb_require_speed: Option<UsbSpeed>,
+
+    /// USB: bytes moved per control transfer in a burst, if not the default.
+    pub usb_buffer_size: Option<u32>,
+
+    /// USB: ask which device to use when more than one matches, rather
+    /// than taking the first.
+    pub usb_pick: bool,
+
+    pub serial_port: Option<String>,
+    pub serial_baud: Option<u32>,
+    pub serial_flow_control: FlowControl,
+    pub serial_reconnect: bool,
+    pub ethernet_host: Option<String>,
+    pub ethernet_port: Option<u16>,
+    pub ethernet_tcp: bool,
+    pub pcie_bar: Option<String>,
+    pub spi_pins: Option<String>,
+
+    /// How long, in ms, the bridge waits for the device to answer
+    pub bridge_timeout: Option<u32>,
+
+    /// How long, in ms, each read from the serial port may block. Picked
+    /// from the baud rate when unset.
+    pub serial_timeout: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            memory_address: None,
+            memory_value: None,
+            server_kind: vec![],
+            bind_addr: "127.0.0.1".to_owned(),
+            bind_port: 1234,
+            gdb_port: 3333,
+            random_loops: None,
+            random_address: None,
+            random_range: None,
+            random_seed: None,
+            messible_address: None,
+            register_mapping: HashMap::new(),
+            memory_regions: HashMap::new(),
+            csr_bases: HashMap::new(),
+            csr_registers: vec![],
+            debug_offset: 0,
+            load_name: None,
+            load_addr: None,
+            load_flash: false,
+            confirm_size: None,
+            terminal_mouse: false,
+            burst_length: 4,
+            hexdump: false,
+            burst_source: None,
+            value_file: None,
+            values: vec![],
+            flash_no_reset: false,
+            careful_flashing: false,
+            allow_unaligned: false,
+            length_policy: LengthPolicy::Reject,
+            label: None,
+            log_target: LogTarget::Stderr,
+            poll_file: None,
+            poll_interval: 1000,
+            double_read: false,
+            retry_count: 3,
+            retry_on: BridgeErrorKind::ALL
+                .iter()
+                .copied()
+                .filter(|k| k.is_transient())
+                .collect(),
+            logic_file: None,
+            logic_max_samples: None,
+            max_clients: 8,
+            max_read_size: 0x10_0000,
+            retry_backoff: RetryBackoff::Fixed,
+            retry_delay: 10,
+            retry_max_delay: 1000,
+            replay_file: None,
+            preserve_timing: false,
+            address_offset: 0,
+            mock_init: None,
+            csr_csv: None,
+            output_format: OutputFormat::Text,
+            file_region: None,
+            file_port: 6969,
+            slow_threshold: None,
+            defines: HashMap::new(),
+            verify_checksum: false,
+            pulse_value: None,
+            pulse_width: 0,
+            dump_registers: false,
+            group_by_region: false,
+            reverse: false,
+            chunk_size: None,
+            compare_registers: None,
+            output_path: None,
+            output_file: None,
+            histogram: false,
+            histogram_samples: 1000,
+            histogram_bins: 16,
+            abort_after_failures: None,
+            min_success_rate: None,
+            read_scale: None,
+            read_offset: None,
+            safe_writes: false,
+            unsafe_writes: false,
+            poll_count: None,
+            deadline: None,
+            raw_command: None,
+            address_width: None,
+            hex_prefix: true,
+            human_readable: false,
+            compact: false,
+            keepalive: 0,
+            keepalive_address: None,
+            preflight: false,
+            preflight_address: None,
+            fill_pattern: None,
+            memtest: false,
+            trace_file: None,
+            trace_changes_only: false,
+            expect_value: None,
+            expect_mask: None,
+            watch_expr: None,
+            watch_timeout: None,
+            explain_address: false,
+            address_resolution: None,
+            bridge_kind: BridgeKind::Usb,
+            usb_pid: None,
+            usb_vid: None,
+            usb_bus: None,
+            usb_device: None,
+            usb_detach_kernel_driver: false,
+            usb_require_speed: None,
+            usb_buffer_size: None,
+            usb_pick: false,
+            serial_port: None,
+            serial_baud: None,
+            serial_flow_control: FlowControl::None,
+            serial_reconnect: false,
+            ethernet_host: None,
+            ethernet_port: None,
+            ethernet_tcp: false,
+            pcie_bar: None,
+            spi_pins: None,
+            bridge_timeout: None,
+            serial_timeout: None,
+        }
+    }
+}
+
+/// A builder to create a `Config` without going through the command line.
+/// Call the setters for the fields you care about, then call `build()` to
+/// resolve the operation and validate the result.
+///
+/// ```ignore
+/// let cfg = Config::builder()
+///     .serial_port("/dev/ttyUSB0")
+///     .memory_address(0x1000_0000)
+///     .build()?;
+/// let bridge = cfg.create_bridge()?;
+/// ```
+#[derive(Clone, Default)]
+pub struct ConfigBuilder {
+    cfg: Config,
+
+    /// If not specified, the bridge is inferred from its parameters.
+    bridge_kind: Option<BridgeKind>,
+
+    /// If not specified, this is taken from the register map.
+    debug_offset: Option<u32>,
+
+    /// If not specified, this is taken from the register map.
+    messible_address: Option<u32>,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Select the bridge explicitly, rather than inferring it from
+    /// which bridge parameters were specified.
+    pub fn bridge_kind(&mut self, kind: BridgeKind) -> &mut ConfigBuilder {
+        self.bridge_kind = Some(kind);
+        self
+    }
+
+    pub fn usb_pid(&mut self, pid: u16) -> &mut ConfigBuilder {
+        self.cfg.usb_pid = Some(pid);
+        self
+    }
+
+    pub fn usb_vid(&mut self, vid: u16) -> &mut ConfigBuilder {
+        self.cfg.usb_vid = Some(vid);
+        self
+    }
+
+    pub fn usb_bus(&mut self, bus: u8) -> &mut ConfigBuilder {
+        self.cfg.usb_bus = Some(bus);
+        self
+    }
+
+    pub fn usb_device(&mut self, device: u8) -> &mut ConfigBuilder {
+        self.cfg.usb_device = Some(device);
+        self
+    }
+
+    pub fn usb_detach_kernel_driver(&mut self, detach: bool) -> &mut ConfigBuilder {
+        self.cfg.usb_detach_kernel_driver = detach;
+        self
+    }
+
+    pub fn usb_require_speed(&mut self, speed: UsbSpeed) -> &mut ConfigBuilder {
+        self.cfg.usb_require_speed = Some(speed);
+        self
+    }
+
+    pub fn usb_buffer_size(&mut self, bytes: u32) -> &mut ConfigBuilder {
+        self.cfg.usb_buffer_size = Some(bytes);
+        self
+    }
+
+    pub fn usb_pick(&mut self, pick: bool) -> &mut ConfigBuilder {
+        self.cfg.usb_pick = pick;
+        self
+    }
+
+    pub fn serial_port(&mut self, port: &str) -> &mut ConfigBuilder {
+        self.cfg.serial_port = Some(port.to_owned());
+        self
+    }
+
+    pub fn serial_baud(&mut self, baud: u32) -> &mut ConfigBuilder {
+        self.cfg.serial_baud = Some(baud);
+        self
+    }
+
+    pub fn serial_flow_control(&mut self, flow_control: FlowControl) -> &mut ConfigBuilder {
+        self.cfg.serial_flow_control = flow_control;
+        self
+    }
+
+    pub fn serial_reconnect(&mut self, reconnect: bool) -> &mut ConfigBuilder {
+        self.cfg.serial_reconnect = reconnect;
+        self
+    }
+
+    pub fn ethernet_host(&mut self, host: &str) -> &mut ConfigBuilder {
+        self.cfg.ethernet_host = Some(host.to_owned());
+        self
+    }
+
+    pub fn ethernet_port(&mut self, port: u16) -> &mut ConfigBuilder {
+        self.cfg.ethernet_port = Some(port);
+        self
+    }
+
+    pub fn ethernet_tcp(&mut self, tcp: bool) -> &mut ConfigBuilder {
+        self.cfg.ethernet_tcp = tcp;
+        self
+    }
+
+    pub fn pcie_bar(&mut self, path: &str) -> &mut ConfigBuilder {
+        self.cfg.pcie_bar = Some(path.to_owned());
+        self
+    }
+
+    pub fn spi_pins(&mut self, pins: &str) -> &mut ConfigBuilder {
+        self.cfg.spi_pins = Some(pins.to_owned());
+        self
+    }
+
+    /// How long, in ms, the bridge waits for the device to answer.
+    pub fn bridge_timeout(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.bridge_timeout = Some(ms);
+        self
+    }
+
+    /// How long, in ms, each read from the serial port may block.
+    pub fn serial_timeout(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.serial_timeout = Some(ms);
+        self
+    }
+
+    pub fn memory_address(&mut self, addr: u32) -> &mut ConfigBuilder {
+        self.cfg.memory_address = Some(addr);
+        self
+    }
+
+    pub fn memory_value(&mut self, value: u32) -> &mut ConfigBuilder {
+        self.cfg.memory_value = Some(value);
+        self
+    }
+
+    /// Add a server to run. May be called multiple times to run
+    /// several servers at once.
+    pub fn server_kind(&mut self, kind: ServerKind) -> &mut ConfigBuilder {
+        self.cfg.server_kind.push(kind);
+        self
+    }
+
+    pub fn bind_addr(&mut self, addr: &str) -> &mut ConfigBuilder {
+        self.cfg.bind_addr = addr.to_owned();
+        self
+    }
+
+    pub fn bind_port(&mut self, port: u16) -> &mut ConfigBuilder {
+        self.cfg.bind_port = port;
+        self
+    }
+
+    pub fn gdb_port(&mut self, port: u16) -> &mut ConfigBuilder {
+        self.cfg.gdb_port = port;
+        self
+    }
+
+    pub fn random_loops(&mut self, loops: u32) -> &mut ConfigBuilder {
+        self.cfg.random_loops = Some(loops);
+        self
+    }
+
+    pub fn random_address(&mut self, addr: u32) -> &mut ConfigBuilder {
+        self.cfg.random_address = Some(addr);
+        self
+    }
+
+    pub fn random_range(&mut self, range: u32) -> &mut ConfigBuilder {
+        self.cfg.random_range = Some(range);
+        self
+    }
+
+    pub fn random_seed(&mut self, seed: u32) -> &mut ConfigBuilder {
+        self.cfg.random_seed = Some(seed);
+        self
+    }
+
+    pub fn messible_address(&mut self, addr: u32) -> &mut ConfigBuilder {
+        self.messible_address = Some(addr);
+        self
+    }
+
+    pub fn register_mapping(&mut self, map: HashMap<String, Option<u32>>) -> &mut ConfigBuilder {
+        self.cfg.register_mapping = map;
+        self
+    }
+
+    pub fn memory_regions(&mut self, regions: MemoryRegions) -> &mut ConfigBuilder {
+        self.cfg.memory_regions = regions;
+        self
+    }
+
+    pub fn csr_bases(&mut self, bases: HashMap<String, u32>) -> &mut ConfigBuilder {
+        self.cfg.csr_bases = bases;
+        self
+    }
+
+    pub fn csr_registers(&mut self, registers: Vec<CsrRegister>) -> &mut ConfigBuilder {
+        self.cfg.csr_registers = registers;
+        self
+    }
+
+    pub fn debug_offset(&mut self, offset: u32) -> &mut ConfigBuilder {
+        self.debug_offset = Some(offset);
+        self
+    }
+
+    pub fn load_name(&mut self, name: &str) -> &mut ConfigBuilder {
+        self.cfg.load_name = Some(name.to_owned());
+        self
+    }
+
+    pub fn load_addr(&mut self, addr: u32) -> &mut ConfigBuilder {
+        self.cfg.load_addr = Some(addr);
+        self
+    }
+
+    pub fn load_flash(&mut self, flash: bool) -> &mut ConfigBuilder {
+        self.cfg.load_flash = flash;
+        self
+    }
+
+    pub fn confirm_size(&mut self, bytes: u32) -> &mut ConfigBuilder {
+        self.cfg.confirm_size = Some(bytes);
+        self
+    }
+
+    pub fn terminal_mouse(&mut self, mouse: bool) -> &mut ConfigBuilder {
+        self.cfg.terminal_mouse = mouse;
+        self
+    }
+
+    pub fn burst_length(&mut self, length: u32) -> &mut ConfigBuilder {
+        self.cfg.burst_length = length;
+        self
+    }
+
+    pub fn hexdump(&mut self, hexdump: bool) -> &mut ConfigBuilder {
+        self.cfg.hexdump = hexdump;
+        self
+    }
+
+    pub fn compact(&mut self, compact: bool) -> &mut ConfigBuilder {
+        self.cfg.compact = compact;
+        self
+    }
+
+    pub fn burst_source(&mut self, source: &str) -> &mut ConfigBuilder {
+        self.cfg.burst_source = Some(source.to_owned());
+        self
+    }
+
+    pub fn value_file(&mut self, path: &str) -> &mut ConfigBuilder {
+        self.cfg.value_file = Some(path.to_owned());
+        self
+    }
+
+    pub fn flash_no_reset(&mut self, no_reset: bool) -> &mut ConfigBuilder {
+        self.cfg.flash_no_reset = no_reset;
+        self
+    }
+
+    pub fn careful_flashing(&mut self, careful: bool) -> &mut ConfigBuilder {
+        self.cfg.careful_flashing = careful;
+        self
+    }
+
+    pub fn allow_unaligned(&mut self, allow: bool) -> &mut ConfigBuilder {
+        self.cfg.allow_unaligned = allow;
+        self
+    }
+
+    pub fn length_policy(&mut self, policy: LengthPolicy) -> &mut ConfigBuilder {
+        self.cfg.length_policy = policy;
+        self
+    }
+
+    pub fn label(&mut self, label: &str) -> &mut ConfigBuilder {
+        self.cfg.label = Some(label.to_owned());
+        self
+    }
+
+    pub fn log_target(&mut self, target: LogTarget) -> &mut ConfigBuilder {
+        self.cfg.log_target = target;
+        self
+    }
+
+    pub fn poll_file(&mut self, path: &str) -> &mut ConfigBuilder {
+        self.cfg.poll_file = Some(path.to_owned());
+        self
+    }
+
+    pub fn poll_interval(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.poll_interval = ms;
+        self
+    }
+
+    pub fn double_read(&mut self, double_read: bool) -> &mut ConfigBuilder {
+        self.cfg.double_read = double_read;
+        self
+    }
+
+    pub fn retry_count(&mut self, count: u32) -> &mut ConfigBuilder {
+        self.cfg.retry_count = count;
+        self
+    }
+
+    pub fn retry_on(&mut self, kinds: Vec<BridgeErrorKind>) -> &mut ConfigBuilder {
+        self.cfg.retry_on = kinds;
+        self
+    }
+
+    pub fn logic_file(&mut self, path: &str) -> &mut ConfigBuilder {
+        self.cfg.logic_file = Some(path.to_owned());
+        self
+    }
+
+    pub fn logic_max_samples(&mut self, count: u32) -> &mut ConfigBuilder {
+        self.cfg.logic_max_samples = Some(count);
+        self
+    }
+
+    pub fn max_clients(&mut self, max: u32) -> &mut ConfigBuilder {
+        self.cfg.max_clients = max;
+        self
+    }
+
+    pub fn max_read_size(&mut self, words: u32) -> &mut ConfigBuilder {
+        self.cfg.max_read_size = words;
+        self
+    }
+
+    pub fn retry_backoff(&mut self, backoff: RetryBackoff) -> &mut ConfigBuilder {
+        self.cfg.retry_backoff = backoff;
+        self
+    }
+
+    pub fn retry_delay(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.retry_delay = ms;
+        self
+    }
+
+    pub fn retry_max_delay(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.retry_max_delay = ms;
+        self
+    }
+
+    pub fn replay_file(&mut self, path: &str) -> &mut ConfigBuilder {
+        self.cfg.replay_file = Some(path.to_owned());
+        self
+    }
+
+    pub fn preserve_timing(&mut self, preserve_timing: bool) -> &mut ConfigBuilder {
+        self.cfg.preserve_timing = preserve_timing;
+        self
+    }
+
+    pub fn address_offset(&mut self, offset: i64) -> &mut ConfigBuilder {
+        self.cfg.address_offset = offset;
+        self
+    }
+
+    pub fn mock_init(&mut self, path: &str) -> &mut ConfigBuilder {
+        self.cfg.mock_init = Some(path.to_owned());
+        self
+    }
+
+    pub fn csr_csv(&mut self, source: CsvSource) -> &mut ConfigBuilder {
+        self.cfg.csr_csv = Some(source);
+        self
+    }
+
+    pub fn output_format(&mut self, format: OutputFormat) -> &mut ConfigBuilder {
+        self.cfg.output_format = format;
+        self
+    }
+
+    pub fn file_region(&mut self, name: &str) -> &mut ConfigBuilder {
+        self.cfg.file_region = Some(name.to_lowercase());
+        self
+    }
+
+    pub fn file_port(&mut self, port: u16) -> &mut ConfigBuilder {
+        self.cfg.file_port = port;
+        self
+    }
+
+    pub fn slow_threshold(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.slow_threshold = Some(ms);
+        self
+    }
+
+    pub fn define(&mut self, key: &str, value: &str) -> &mut ConfigBuilder {
+        self.cfg.defines.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    pub fn verify_checksum(&mut self, verify: bool) -> &mut ConfigBuilder {
+        self.cfg.verify_checksum = verify;
+        self
+    }
+
+    pub fn pulse_value(&mut self, value: u32) -> &mut ConfigBuilder {
+        self.cfg.pulse_value = Some(value);
+        self
+    }
+
+    pub fn pulse_width(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.pulse_width = ms;
+        self
+    }
+
+    pub fn dump_registers(&mut self, dump: bool) -> &mut ConfigBuilder {
+        self.cfg.dump_registers = dump;
+        self
+    }
+
+    pub fn group_by_region(&mut self, group: bool) -> &mut ConfigBuilder {
+        self.cfg.group_by_region = group;
+        self
+    }
+
+    pub fn reverse(&mut self, reverse: bool) -> &mut ConfigBuilder {
+        self.cfg.reverse = reverse;
+        self
+    }
+
+    pub fn chunk_size(&mut self, words: u32) -> &mut ConfigBuilder {
+        self.cfg.chunk_size = Some(words);
+        self
+    }
+
+    pub fn compare_registers(&mut self, snapshot: &str) -> &mut ConfigBuilder {
+        self.cfg.compare_registers = Some(snapshot.to_owned());
+        self
+    }
+
+    pub fn output_path(&mut self, path: &str) -> &mut ConfigBuilder {
+        self.cfg.output_path = Some(PathBuf::from(path));
+        self
+    }
+
+    pub fn histogram(&mut self, histogram: bool) -> &mut ConfigBuilder {
+        self.cfg.histogram = histogram;
+        self
+    }
+
+    pub fn histogram_samples(&mut self, samples: u32) -> &mut ConfigBuilder {
+        self.cfg.histogram_samples = samples;
+        self
+    }
+
+    pub fn histogram_bins(&mut self, bins: u32) -> &mut ConfigBuilder {
+        self.cfg.histogram_bins = bins;
+        self
+    }
+
+    pub fn abort_after_failures(&mut self, failures: u32) -> &mut ConfigBuilder {
+        self.cfg.abort_after_failures = Some(failures);
+        self
+    }
+
+    pub fn min_success_rate(&mut self, percent: f64) -> &mut ConfigBuilder {
+        self.cfg.min_success_rate = Some(percent);
+        self
+    }
+
+    pub fn read_scale(&mut self, scale: f64) -> &mut ConfigBuilder {
+        self.cfg.read_scale = Some(scale);
+        self
+    }
+
+    pub fn read_offset(&mut self, offset: f64) -> &mut ConfigBuilder {
+        self.cfg.read_offset = Some(offset);
+        self
+    }
+
+    pub fn safe_writes(&mut self, safe: bool) -> &mut ConfigBuilder {
+        self.cfg.safe_writes = safe;
+        self
+    }
+
+    pub fn unsafe_writes(&mut self, allow: bool) -> &mut ConfigBuilder {
+        self.cfg.unsafe_writes = allow;
+        self
+    }
+
+    pub fn poll_count(&mut self, count: u32) -> &mut ConfigBuilder {
+        self.cfg.poll_count = Some(count);
+        self
+    }
+
+    pub fn deadline(&mut self, deadline: SystemTime) -> &mut ConfigBuilder {
+        self.cfg.deadline = Some(deadline);
+        self
+    }
+
+    pub fn raw_command(&mut self, bytes: Vec<u8>) -> &mut ConfigBuilder {
+        self.cfg.raw_command = Some(bytes);
+        self
+    }
+
+    pub fn address_width(&mut self, bytes: u8) -> &mut ConfigBuilder {
+        self.cfg.address_width = Some(bytes);
+        self
+    }
+
+    pub fn human_readable(&mut self, human: bool) -> &mut ConfigBuilder {
+        self.cfg.human_readable = human;
+        self
+    }
+
+    pub fn hex_prefix(&mut self, prefix: bool) -> &mut ConfigBuilder {
+        self.cfg.hex_prefix = prefix;
+        self
+    }
+
+    pub fn keepalive(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.keepalive = ms;
+        self
+    }
+
+    pub fn keepalive_address(&mut self, addr: u32) -> &mut ConfigBuilder {
+        self.cfg.keepalive_address = Some(addr);
+        self
+    }
+
+    pub fn preflight(&mut self, preflight: bool) -> &mut ConfigBuilder {
+        self.cfg.preflight = preflight;
+        self
+    }
+
+    pub fn preflight_address(&mut self, addr: u32) -> &mut ConfigBuilder {
+        self.cfg.preflight_address = Some(addr);
+        self
+    }
+
+    pub fn fill_pattern(&mut self, pattern: FillPattern) -> &mut ConfigBuilder {
+        self.cfg.fill_pattern = Some(pattern);
+        self
+    }
+
+    pub fn memtest(&mut self, memtest: bool) -> &mut ConfigBuilder {
+        self.cfg.memtest = memtest;
+        self
+    }
+
+    pub fn trace_file(&mut self, path: &str) -> &mut ConfigBuilder {
+        self.cfg.trace_file = Some(path.to_owned());
+        self
+    }
+
+    pub fn trace_changes_only(&mut self, changes_only: bool) -> &mut ConfigBuilder {
+        self.cfg.trace_changes_only = changes_only;
+        self
+    }
+
+    pub fn expect_value(&mut self, value: u32) -> &mut ConfigBuilder {
+        self.cfg.expect_value = Some(value);
+        self
+    }
+
+    pub fn expect_mask(&mut self, mask: u32) -> &mut ConfigBuilder {
+        self.cfg.expect_mask = Some(mask);
+        self
+    }
+
+    pub fn watch_expr(&mut self, expr: WatchExpr) -> &mut ConfigBuilder {
+        self.cfg.watch_expr = Some(expr);
+        self
+    }
+
+    pub fn watch_timeout(&mut self, ms: u32) -> &mut ConfigBuilder {
+        self.cfg.watch_timeout = Some(ms);
+        self
+    }
+
+    pub fn explain_address(&mut self, explain: bool) -> &mut ConfigBuilder {
+        self.cfg.explain_address = explain;
+        self
+    }
+
+    pub fn address_resolution(&mut self, resolution: String) -> &mut ConfigBuilder {
+        self.cfg.address_resolution = Some(resolution);
+        self
+    }
+
+    /// Figure out which bridge to use, giving the same priority to
+    /// each bridge that the command line does.
+    fn resolve_bridge_kind(&self) -> Result<BridgeKind, ConfigError> {
+        let cfg = &self.cfg;
+        let inferred = if cfg.mock_init.is_some() {
+            BridgeKind::Mock
+        } else if cfg.spi_pins.is_some() {
+            BridgeKind::Spi
+        } else if cfg.serial_port.is_some() {
+            BridgeKind::Uart
+        } else if cfg.pcie_bar.is_some() {
+            BridgeKind::PCIe
+        } else if cfg.ethernet_host.is_some() {
+            BridgeKind::Ethernet
+        } else {
+            BridgeKind::Usb
+        };
+
+        let kind = match self.bridge_kind {
+            None => return Ok(inferred),
+            Some(k) => k,
+        };
+
+        let (has_param, param) = match kind {
+            BridgeKind::Usb => (true, ""),
+            BridgeKind::Uart => (cfg.serial_port.is_some(), "a serial port"),
+            BridgeKind::Ethernet => (cfg.ethernet_host.is_some(), "an ethernet host"),
+            BridgeKind::PCIe => (cfg.pcie_bar.is_some(), "a pcie bar"),
+            BridgeKind::Spi => (cfg.spi_pins.is_some(), "spi pins"),
+            BridgeKind::Mock | BridgeKind::None => (true, ""),
+        };
+        if !has_param {
+            return Err(ConfigError::InvalidConfig(format!(
+                "{:?} bridge selected, but no {} specified",
+                kind, param
+            )));
+        }
+        if inferred != BridgeKind::Usb && inferred != kind {
+            return Err(ConfigError::InvalidConfig(format!(
+                "{:?} bridge selected, but {:?} bridge parameters were specified",
+                kind, inferred
+            )));
+        }
+        Ok(kind)
+    }
+
+    /// Resolve the operation and validate the configuration, producing
+    /// a `Config` that is ready to use.
+    pub fn build(&self) -> Result<Config, ConfigError> {
+        let mut cfg = self.cfg.clone();
+        check_register_map(&cfg)?;
+        if let Some(path) = &cfg.value_file {
+            cfg.values = parse_value_list(&std::fs::read_to_string(path)?)?;
+        }
+        cfg.bridge_kind = self.resolve_bridge_kind()?;
+
+        let (bind_addr, bind_port) = split_host_port(&cfg.bind_addr)?;
+        if bind_port.is_some() {
+            return Err(ConfigError::InvalidConfig(
+                "the bind address takes no port, use --gdb-port or --wishbone-port".to_owned(),
+            ));
+        }
+        cfg.bind_addr = bind_addr;
+
+        if let Some(host) = &cfg.ethernet_host {
+            let (host, port) = split_host_port(host)?;
+            if let Some(port) = port {
+                cfg.ethernet_port = Some(port);
+            }
+            cfg.ethernet_host = Some(host);
+        }
+
+        let defaults = BridgeDefaults::for_kind(cfg.bridge_kind);
+        if cfg.bridge_timeout.is_some() && defaults.timeout.is_none() {
+            // --timeout may only be there to limit --watch-expr
+            if cfg.watch_timeout.is_none() {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "the {} bridge doesn't support --timeout",
+                    cfg.bridge_kind.name()
+                )));
+            }
+            cfg.bridge_timeout = None;
+        }
+        if cfg.usb_require_speed.is_some() && cfg.bridge_kind != BridgeKind::Usb {
+            return Err(ConfigError::InvalidConfig(format!(
+                "--require-speed only applies to usb, but the {} bridge is in use",
+                cfg.bridge_kind.name()
+            )));
+        }
+        if let Some(bytes) = cfg.address_width {
+            if bytes == 0 || bytes > 8 {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "--addr-width must be from 1 to 8 bytes, not {}",
+                    bytes
+                )));
+            }
+        }
+        if let Some(bytes) = cfg.usb_buffer_size {
+            if cfg.bridge_kind != BridgeKind::Usb {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "--usb-buffer-size only applies to usb, but the {} bridge is in use",
+                    cfg.bridge_kind.name()
+                )));
+            }
+            // Whether it suits the device's packet size is checked on connect
+            if bytes == 0 || bytes > u32::from(u16::MAX) {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "--usb-buffer-size must be from 1 to 65535 bytes, not {}",
+                    bytes
+                )));
+            }
+        }
+        if let Some(ms) = cfg.serial_timeout {
+            if cfg.bridge_kind != BridgeKind::Uart {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "--serial-timeout only applies to uart, but the {} bridge is in use",
+                    cfg.bridge_kind.name()
+                )));
+            }
+            if ms == 0 {
+                return Err(ConfigError::InvalidConfig(
+                    "--serial-timeout must be at least 1 ms".to_owned(),
+                ));
+            }
+        }
+        if cfg.usb_pick && cfg.bridge_kind != BridgeKind::Usb {
+            return Err(ConfigError::InvalidConfig(format!(
+                "--pick only applies to usb, but the {} bridge is in use",
+                cfg.bridge_kind.name()
+            )));
+        }
+        cfg.serial_baud = cfg.serial_baud.or(defaults.baud);
+        cfg.ethernet_port = cfg.ethernet_port.or(defaults.port);
+        cfg.bridge_timeout = cfg.bridge_timeout.or(defaults.timeout);
+
+        let mut server_kind = vec![];
+        if cfg.load_addr.is_some() && cfg.load_name.is_none() && !cfg.load_flash {
+            server_kind.push(ServerKind::MemoryAccess);
+        }
+        if cfg.load_addr.is_some() && cfg.load_name.is_some() && cfg.load_flash {
+            server_kind.push(ServerKind::FlashProgram);
+        }
+        server_kind.extend(cfg.server_kind.iter());
+
+        cfg.messible_address = if let Some(addr) = self.messible_address {
+            Some(addr)
+        } else if let Some(base) = cfg.register_mapping.get("messible_out") {
+            Some((*base).ok_or_else(|| ConfigError::AddressOutOfRange("messible_out".to_owned()))?)
+        } else {
+            None
+        };
+
+        cfg.debug_offset = if let Some(debug_offset) = self.debug_offset {
+            debug_offset
+        } else if let Some(debug_offset) = cfg.register_mapping.get("vexriscv_debug") {
+            (*debug_offset)
+                .ok_or_else(|| ConfigError::AddressOutOfRange("vexriscv_debug".to_owned()))?
+        } else {
+            0xf00f_0000
+        };
+
+        // Every address we know about, whether given directly or taken from
+        // the register map, sits behind the same bus window.
+        if cfg.address_offset != 0 {
+            let offset = cfg.address_offset;
+            for addr in cfg.register_mapping.values_mut() {
+                *addr = addr.and_then(|a| offset_address(a, offset).ok());
+            }
+            cfg.memory_regions = cfg
+                .memory_regions
+                .drain()
+                .filter_map(|(name, region)| {
+                    offset_address(region.base, offset)
+                        .ok()
+                        .map(|base| (name, MemoryRegion { base, ..region }))
+                })
+                .collect();
+            cfg.csr_bases = cfg
+                .csr_bases
+                .drain()
+                .filter_map(|(name, base)| {
+                    offset_address(base, offset).ok().map(|base| (name, base))
+                })
+                .collect();
+            cfg.csr_registers = cfg
+                .csr_registers
+                .drain(..)
+                .filter_map(|register| {
+                    offset_address(register.address, offset)
+                        .ok()
+                        .map(|address| CsrRegister {
+                            address,
+                            ..register
+                        })
+                })
+                .collect();
+            for addr in [
+                &mut cfg.memory_address,
+                &mut cfg.load_addr,
+                &mut cfg.random_address,
+                &mut cfg.messible_address,
+            ] {
+                *addr = addr.map(|a| offset_address(a, offset)).transpose()?;
+            }
+            cfg.watch_expr = cfg
+                .watch_expr
+                .take()
+                .map(|expr| expr.map_registers(&|a| offset_address(a, offset)))
+                .transpose()?;
+            if let (Some(resolution), Some(addr)) =
+                (cfg.address_resolution.as_mut(), cfg.memory_address)
+            {
+                let sign = if offset < 0 { '-' } else { '+' };
+                resolution.push_str(&format!(
+                    ", {} address offset 0x{:x} = 0x{:08x}",
+                    sign,
+                    offset.abs(),
+                    addr
+                ));
+            }
+            // The debug bridge always has an address, so only move it when it's used.
+            if server_kind.contains(&ServerKind::GDB) {
+                cfg.debug_offset = offset_address(cfg.debug_offset, offset)?;
+            }
+        }
+
+        if cfg.chunk_size == Some(0) {
+            return Err(ConfigError::InvalidConfig(
+                "--chunk-size must be at least 1 word".to_owned(),
+            ));
+        }
+
+        if cfg.histogram && (cfg.histogram_samples == 0 || cfg.histogram_bins == 0) {
+            return Err(ConfigError::InvalidConfig(
+                "--samples and --bins must be at least 1".to_owned(),
+            ));
+        }
+
+        if cfg.raw_command.is_some() && server_kind.is_empty() {
+            server_kind.push(ServerKind::MemoryAccess);
+        }
+
+        if let Some(expr) = &cfg.watch_expr {
+            for addr in expr.registers() {
+                check_alignment(addr, cfg.allow_unaligned)?;
+            }
+            if server_kind.is_empty() {
+                server_kind.push(ServerKind::MemoryAccess);
+            }
+        }
+
+        if cfg.dump_registers || cfg.compare_registers.is_some() {
+            if cfg.csr_registers.is_empty() {
+                return Err(ConfigError::InvalidConfig(
+                    "reading every register needs a --csr-csv file that lists registers".to_owned(),
+                ));
+            }
+            if server_kind.is_empty() {
+                server_kind.push(ServerKind::MemoryAccess);
+            }
+        }
+
+        if server_kind.is_empty() {
+            if cfg.memory_address.is_none() {
+                return Err(ConfigError::NoOperationSpecified);
+            }
+            server_kind.push(ServerKind::MemoryAccess);
+        }
+
+        let writes = cfg.memory_value.is_some()
+            || cfg.pulse_value.is_some()
+            || cfg.burst_source.is_some()
+            || cfg.fill_pattern.is_some()
+            || cfg.value_file.is_some();
+        if server_kind.contains(&ServerKind::MemoryAccess) {
+            if let Some(addr) = cfg.memory_address {
+                check_alignment(addr, cfg.allow_unaligned)?;
+                // Single-value writes and file contents don't use the length
+                if cfg.memory_value.is_none()
+                    && cfg.pulse_value.is_none()
+                    && cfg.burst_source.is_none()
+                    && cfg.value_file.is_none()
+                {
+                    cfg.burst_length = check_length(cfg.burst_length, cfg.length_policy)?;
+                }
+                if writes || cfg.memtest {
+                    let length = if cfg.memory_value.is_some() || cfg.pulse_value.is_some() {
+                        4
+                    } else if let Some(file_name) = &cfg.burst_source {
+                        file_length(file_name)
+                    } else if cfg.value_file.is_some() {
+                        4 * cfg.values.len() as u32
+                    } else {
+                        cfg.burst_length
+                    };
+                    check_write_allowed(&cfg, addr, length)?;
+                }
+                if addr
+                    .checked_add(cfg.burst_length.saturating_sub(1))
+                    .is_none()
+                {
+                    return Err(ConfigError::AddressOutOfRange(format!(
+                        "0x{:08x} + {} bytes runs past the end of the address space",
+                        addr, cfg.burst_length
+                    )));
+                }
+                let value_bytes = 4 * cfg.values.len() as u64;
+                if value_bytes != 0 && u64::from(addr) + value_bytes - 1 > u64::from(u32::MAX) {
+                    return Err(ConfigError::AddressOutOfRange(format!(
+                        "{} values from 0x{:08x} run past the end of the address space",
+                        cfg.values.len(),
+                        addr
+                    )));
+                }
+            }
+        }
+
+        cfg.server_kind = server_kind;
+        check_dependencies(&cfg)?;
+        if cfg.max_read_size != 0 {
+            if let Some(requested) = cfg.read_size() {
+                if requested > u64::from(cfg.max_read_size) {
+                    return Err(ConfigError::ReadTooLarge {
+                        requested,
+                        limit: cfg.max_read_size,
+                    });
+                }
+            }
+        }
+        let server_kind = &cfg.server_kind;
+
+        if let (true, Some(addr), Some(name)) = (
+            server_kind.contains(&ServerKind::LoadFile),
+            cfg.load_addr,
+            &cfg.load_name,
+        ) {
+            check_write_allowed(&cfg, addr, file_length(name))?;
+        }
+
+        if server_kind.contains(&ServerKind::FileService) {
+            // unwrap() is safe because check_dependencies() requires it
+            let name = cfg.file_region.as_ref().unwrap();
+            let region = cfg.memory_regions.get(name).ok_or_else(|| {
+                ConfigError::InvalidConfig(format!(
+                    "File service requested, but no memory region named \"{}\" is in the csv file",
+                    name
+                ))
+            })?;
+            if region.base.checked_add(region.length).is_none() {
+                return Err(ConfigError::AddressOutOfRange(format!(
+                    "region {} runs past the end of the address space",
+                    name
+                )));
+            }
+        }
+
+        if server_kind.contains(&ServerKind::FlashProgram) {
+            // unwrap() is safe because check_dependencies() requires both of these
+            let name = cfg.load_name.as_ref().unwrap();
+            let confirmed = cfg.confirm_size.unwrap();
+            let actual = std::fs::metadata(name)?.len();
+            if actual != u64::from(confirmed) {
+                return Err(ConfigError::ConfirmationMismatch { confirmed, actual });
+            }
+        }
+
+        // If a register map was loaded, make sure it has what each server needs.
+        let map = &cfg.register_mapping;
+        if !map.is_empty() {
+            if server_kind.contains(&ServerKind::GDB) {
+                // You asked for --server gdb but no vexriscv jtag interfaces is found in the csr.csv file it should complain.
+                if !map.contains_key("vexriscv_debug") {
+                    return Err(ConfigError::InvalidConfig(
                         "GDB specified but no vexriscv address present in csv file".to_owned(),
                     ));
                 }
             }
-            if server_kind.contains(&ServerKind::Terminal) {
-                // You asked for --server terminal but no uart is found in the csr.csv file it should complain.
-                if !(register_mapping.contains_key("uart_xover_rxtx")
-                    && register_mapping.contains_key("uart_xover_rxempty")
-                    && register_mapping.contains_key("uart_xover_ev_pending"))
-                {
-                    return Err(ConfigError::InvalidConfig(
-                        "Terminal specified, but no xover uart addresses present in csv file"
-                            .to_owned(),
-                    ));
+            if server_kind.contains(&ServerKind::Terminal) {
+                // You asked for --server terminal but no uart is found in the csr.csv file it should complain.
+                if !(map.contains_key("uart_xover_rxtx")
+                    && map.contains_key("uart_xover_rxempty")
+                    && map.contains_key("uart_xover_ev_pending"))
+                {
+                    return Err(ConfigError::InvalidConfig(
+                        "Terminal specified, but no xover uart addresses present in csv file"
+                            .to_owned(),
+                    ));
+                }
+            }
+            if server_kind.contains(&ServerKind::FlashProgram) && !map.contains_key("spinor") {
+                return Err(ConfigError::InvalidConfig(
+                    "Flash programming requested, but no spinor block present in csv file"
+                        .to_owned(),
+                ));
+            }
+        }
+
+        if cfg.poll_count == Some(0) {
+            return Err(ConfigError::InvalidConfig(
+                "--poll-count must be at least 1".to_owned(),
+            ));
+        }
+        if cfg.abort_after_failures == Some(0) {
+            return Err(ConfigError::InvalidConfig(
+                "--abort-after-failures must be at least 1".to_owned(),
+            ));
+        }
+        if let Some(percent) = cfg.min_success_rate {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "--min-success-rate must be from 0 to 100, not {}",
+                    percent
+                )));
+            }
+        }
+
+        check_compatibility(&cfg)?;
+
+        // Explaining an address never touches the device, so don't open
+        // a bridge that may not even be plugged in.
+        if cfg.explain_address {
+            cfg.bridge_kind = BridgeKind::None;
+            cfg.server_kind.clear();
+        }
+        if cfg.bridge_kind == BridgeKind::None && !cfg.server_kind.is_empty() {
+            return Err(ConfigError::InvalidConfig(format!(
+                "{:?} needs a bridge, but none was selected",
+                cfg.server_kind[0]
+            )));
+        }
+
+        // Only create the file once everything else checks out, so a bad
+        // command line doesn't truncate it.
+        if let Some(path) = &cfg.output_path {
+            let file = File::create(path).map_err(|e| {
+                ConfigError::InvalidConfig(format!("couldn't create {}: {}", path.display(), e))
+            })?;
+            cfg.output_file = Some(Arc::new(file));
+        }
+        Ok(cfg)
+    }
+}
+
+/// A command line option, along with a test for whether it was used.
+type UsedOption = (&'static str, fn(&Config) -> bool);
+
+/// Pairs of options that make no sense together. Each pair lists the flag
+/// names, as shown to the user, along with a test for whether the option
+/// is in effect.
+const INCOMPATIBLE_OPTIONS: &[(UsedOption, UsedOption)] = &[
+    (
+        ("--poll-file", |c| c.poll_file.is_some()),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--poll-file", |c| c.poll_file.is_some()),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--poll-file", |c| c.poll_file.is_some()),
+        ("--burst-length", |c| c.burst_length != 4),
+    ),
+    (
+        ("--burst-source", |c| c.burst_source.is_some()),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--hexdump", |c| c.hexdump),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (("--compact", |c| c.compact), ("--hexdump", |c| c.hexdump)),
+    (
+        ("--compact", |c| c.compact),
+        ("--format csv, json or jsonl", |c| {
+            c.output_format != OutputFormat::Text
+        }),
+    ),
+    (
+        ("--group-by-region", |c| c.group_by_region),
+        ("--compact", |c| c.compact),
+    ),
+    (
+        ("--group-by-region", |c| c.group_by_region),
+        ("--format csv, json or jsonl", |c| {
+            c.output_format != OutputFormat::Text
+        }),
+    ),
+    (
+        ("VALUE", |c| c.memory_value.is_some()),
+        ("--burst-length", |c| c.burst_length != 4),
+    ),
+    (
+        ("--double-read", |c| c.double_read),
+        ("--burst-length", |c| c.burst_length != 4),
+    ),
+    (
+        ("--pulse", |c| c.pulse_value.is_some()),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--dump-registers", |c| c.dump_registers),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--dump-registers", |c| c.dump_registers),
+        ("--pulse", |c| c.pulse_value.is_some()),
+    ),
+    (
+        ("--dump-registers", |c| c.dump_registers),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--pulse", |c| c.pulse_value.is_some()),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+        ("--dump-registers", |c| c.dump_registers),
+    ),
+    (
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+        ("--pulse", |c| c.pulse_value.is_some()),
+    ),
+    (
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--pulse", |c| c.pulse_value.is_some()),
+        ("--burst-length", |c| c.burst_length != 4),
+    ),
+    (
+        ("--pulse", |c| c.pulse_value.is_some()),
+        ("--poll-file", |c| c.poll_file.is_some()),
+    ),
+    (
+        ("--histogram", |c| c.histogram),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--histogram", |c| c.histogram),
+        ("--pulse", |c| c.pulse_value.is_some()),
+    ),
+    (
+        ("--histogram", |c| c.histogram),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--histogram", |c| c.histogram),
+        ("--burst-length", |c| c.burst_length != 4),
+    ),
+    (
+        ("--histogram", |c| c.histogram),
+        ("--poll-file", |c| c.poll_file.is_some()),
+    ),
+    (
+        ("--histogram", |c| c.histogram),
+        ("--dump-registers", |c| c.dump_registers),
+    ),
+    (
+        ("--histogram", |c| c.histogram),
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+    ),
+    (
+        ("--raw-command", |c| c.raw_command.is_some()),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--pattern", |c| c.fill_pattern.is_some()),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--pattern", |c| c.fill_pattern.is_some()),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--pattern", |c| c.fill_pattern.is_some()),
+        ("--pulse", |c| c.pulse_value.is_some()),
+    ),
+    (
+        ("--pattern", |c| c.fill_pattern.is_some()),
+        ("--poll-file", |c| c.poll_file.is_some()),
+    ),
+    (
+        ("--pattern", |c| c.fill_pattern.is_some()),
+        ("--histogram", |c| c.histogram),
+    ),
+    (
+        ("--pattern", |c| c.fill_pattern.is_some()),
+        ("--dump-registers", |c| c.dump_registers),
+    ),
+    (
+        ("--pattern", |c| c.fill_pattern.is_some()),
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+    ),
+    (
+        ("--memtest", |c| c.memtest),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--memtest", |c| c.memtest),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--memtest", |c| c.memtest),
+        ("--pulse", |c| c.pulse_value.is_some()),
+    ),
+    (
+        ("--memtest", |c| c.memtest),
+        ("--poll-file", |c| c.poll_file.is_some()),
+    ),
+    (
+        ("--memtest", |c| c.memtest),
+        ("--histogram", |c| c.histogram),
+    ),
+    (
+        ("--memtest", |c| c.memtest),
+        ("--dump-registers", |c| c.dump_registers),
+    ),
+    (
+        ("--memtest", |c| c.memtest),
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--burst-length", |c| c.burst_length != 4),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--pattern", |c| c.fill_pattern.is_some()),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--memtest", |c| c.memtest),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--pulse", |c| c.pulse_value.is_some()),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--poll-file", |c| c.poll_file.is_some()),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--histogram", |c| c.histogram),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--dump-registers", |c| c.dump_registers),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("--pattern", |c| c.fill_pattern.is_some()),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("--memtest", |c| c.memtest),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("--pulse", |c| c.pulse_value.is_some()),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("--poll-file", |c| c.poll_file.is_some()),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("--histogram", |c| c.histogram),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("--expect", |c| c.expect_value.is_some()),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("--reverse", |c| c.reverse),
+    ),
+    (
+        ("--reverse", |c| c.reverse),
+        ("VALUE", |c| c.memory_value.is_some()),
+    ),
+    (
+        ("--reverse", |c| c.reverse),
+        ("--burst-source", |c| c.burst_source.is_some()),
+    ),
+    (
+        ("--reverse", |c| c.reverse),
+        ("--pattern", |c| c.fill_pattern.is_some()),
+    ),
+    (("--reverse", |c| c.reverse), ("--memtest", |c| c.memtest)),
+    (
+        ("--reverse", |c| c.reverse),
+        ("--pulse", |c| c.pulse_value.is_some()),
+    ),
+    (
+        ("--reverse", |c| c.reverse),
+        ("--poll-file", |c| c.poll_file.is_some()),
+    ),
+    (
+        ("--reverse", |c| c.reverse),
+        ("--histogram", |c| c.histogram),
+    ),
+    (
+        ("--raw-command", |c| c.raw_command.is_some()),
+        ("--dump-registers", |c| c.dump_registers),
+    ),
+    (
+        ("--raw-command", |c| c.raw_command.is_some()),
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+    ),
+    (
+        ("--watch-expr", |c| c.watch_expr.is_some()),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--watch-expr", |c| c.watch_expr.is_some()),
+        ("--dump-registers", |c| c.dump_registers),
+    ),
+    (
+        ("--watch-expr", |c| c.watch_expr.is_some()),
+        ("--compare-registers", |c| c.compare_registers.is_some()),
+    ),
+    (
+        ("--watch-expr", |c| c.watch_expr.is_some()),
+        ("--raw-command", |c| c.raw_command.is_some()),
+    ),
+];
+
+/// Options that only work when another option is given too. Each entry is
+/// an option and the option it requires, checked once the server kinds
+/// have been worked out.
+const DEPENDENT_OPTIONS: &[(UsedOption, UsedOption)] = &[
+    (
+        ("--histogram", |c| c.histogram),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--pulse", |c| c.pulse_value.is_some()),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--pattern", |c| c.fill_pattern.is_some()),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--memtest", |c| c.memtest),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--expect", |c| c.expect_value.is_some()),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--value-file", |c| c.value_file.is_some()),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--reverse", |c| c.reverse),
+        ("ADDRESS or --dump-registers", |c| {
+            c.memory_address.is_some() || c.dump_registers
+        }),
+    ),
+    (
+        ("--mask", |c| c.expect_mask.is_some()),
+        ("--expect", |c| c.expect_value.is_some()),
+    ),
+    (
+        ("--group-by-region", |c| c.group_by_region),
+        ("--dump-registers", |c| c.dump_registers),
+    ),
+    (
+        ("--trace-changes-only", |c| c.trace_changes_only),
+        ("--trace or --server logic", |c| {
+            c.trace_file.is_some() || c.server_kind.contains(&ServerKind::Logic)
+        }),
+    ),
+    (
+        ("--poll-file", |c| c.poll_file.is_some()),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--server logic", |c| {
+            c.server_kind.contains(&ServerKind::Logic)
+        }),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--server logic", |c| {
+            c.server_kind.contains(&ServerKind::Logic)
+        }),
+        ("--out", |c| c.logic_file.is_some()),
+    ),
+    (
+        ("--server file", |c| {
+            c.server_kind.contains(&ServerKind::FileService)
+        }),
+        ("--file-region", |c| c.file_region.is_some()),
+    ),
+    (
+        ("--server replay", |c| {
+            c.server_kind.contains(&ServerKind::Replay)
+        }),
+        ("--replay-log", |c| c.replay_file.is_some()),
+    ),
+    (
+        ("--server load-file", |c| {
+            c.server_kind.contains(&ServerKind::LoadFile)
+        }),
+        ("--load-name", |c| c.load_name.is_some()),
+    ),
+    (
+        ("--load-flash", |c| c.load_flash),
+        ("--load-name", |c| c.load_name.is_some()),
+    ),
+    (
+        ("--load-flash", |c| c.load_flash),
+        ("--load-address", |c| c.load_addr.is_some()),
+    ),
+    (
+        ("--load-flash", |c| c.load_flash),
+        ("--confirm-size", |c| c.confirm_size.is_some()),
+    ),
+    (
+        ("--confirm-size", |c| c.confirm_size.is_some()),
+        ("--load-flash", |c| c.load_flash),
+    ),
+    (
+        ("--poll-count", |c| c.poll_count.is_some()),
+        ("--poll-file or --server logic", |c| {
+            c.poll_file.is_some() || c.server_kind.contains(&ServerKind::Logic)
+        }),
+    ),
+    (
+        ("--explain-address", |c| c.explain_address),
+        ("ADDRESS", |c| c.memory_address.is_some()),
+    ),
+    (
+        ("--keepalive", |c| c.keepalive != 0),
+        ("--keepalive-address", |c| c.keepalive_address.is_some()),
+    ),
+    (
+        ("--keepalive", |c| c.keepalive != 0),
+        ("--server gdb, wishbone or file", |c| {
+            c.server_kind.iter().any(|k| k.listens())
+        }),
+    ),
+    (
+        ("--preflight", |c| c.preflight),
+        ("--preflight-address or --keepalive-address", |c| {
+            c.preflight_address.is_some() || c.keepalive_address.is_some()
+        }),
+    ),
+    (
+        ("--preflight", |c| c.preflight),
+        ("--server gdb, wishbone or file", |c| {
+            c.server_kind.iter().any(|k| k.listens())
+        }),
+    ),
+    (
+        ("--preflight-address", |c| c.preflight_address.is_some()),
+        ("--preflight", |c| c.preflight),
+    ),
+    (
+        ("--abort-after-failures", |c| {
+            c.abort_after_failures.is_some()
+        }),
+        ("--server random-test", |c| {
+            c.server_kind.contains(&ServerKind::RandomTest)
+        }),
+    ),
+    (
+        ("--min-success-rate", |c| c.min_success_rate.is_some()),
+        ("--server random-test or --memtest", |c| {
+            c.server_kind.contains(&ServerKind::RandomTest) || c.memtest
+        }),
+    ),
+    (
+        // An endless random test never finishes, so the rate is never checked
+        ("--min-success-rate", |c| {
+            c.min_success_rate.is_some() && c.server_kind.contains(&ServerKind::RandomTest)
+        }),
+        ("--random-loops or --deadline", |c| {
+            c.random_loops.is_some() || c.deadline.is_some()
+        }),
+    ),
+];
+
+/// Options that look things up in the register map, and so can't be used
+/// unless one was loaded.
+const MAP_DEPENDENT_OPTIONS: &[UsedOption] = &[
+    ("--load-flash", |c| c.load_flash),
+    ("--file-region", |c| c.file_region.is_some()),
+    ("--dump-registers", |c| c.dump_registers),
+    ("--compare-registers", |c| c.compare_registers.is_some()),
+    ("--safe", |c| c.safe_writes),
+];
+
+/// Make sure every option in `MAP_DEPENDENT_OPTIONS` that was used has a
+/// register map to work with.
+fn check_register_map(cfg: &Config) -> Result<(), ConfigError> {
+    let has_map = cfg.csr_csv.is_some()
+        || !cfg.register_mapping.is_empty()
+        || !cfg.memory_regions.is_empty()
+        || !cfg.csr_registers.is_empty();
+    if has_map {
+        return Ok(());
+    }
+    match MAP_DEPENDENT_OPTIONS.iter().find(|(_, used)| used(cfg)) {
+        Some((name, _)) => Err(ConfigError::RegisterMapRequired(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Return an error for the first option in `DEPENDENT_OPTIONS` that was
+/// used without what it requires.
+fn check_dependencies(cfg: &Config) -> Result<(), ConfigError> {
+    for ((option, used), (requires, present)) in DEPENDENT_OPTIONS {
+        if used(cfg) && !present(cfg) {
+            return Err(ConfigError::MissingDependentOption { option, requires });
+        }
+    }
+    Ok(())
+}
+
+/// Reject any combination of options listed in `INCOMPATIBLE_OPTIONS`,
+/// naming every conflicting option.
+fn check_compatibility(cfg: &Config) -> Result<(), ConfigError> {
+    let mut conflicts: Vec<String> = vec![];
+    for ((a, a_used), (b, b_used)) in INCOMPATIBLE_OPTIONS {
+        if a_used(cfg) && b_used(cfg) {
+            for name in &[a, b] {
+                if !conflicts.iter().any(|c| c == *name) {
+                    conflicts.push(name.to_string());
+                }
+            }
+        }
+    }
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::IncompatibleOptions(conflicts))
+    }
+}
+
+impl Config {
+    /// Create a `ConfigBuilder` for constructing a `Config` programmatically.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
+    /// How long to wait before retry number `attempt`, counting from 1.
+    pub fn retry_delay_for(&self, attempt: u32) -> Duration {
+        self.retry_backoff
+            .delay(attempt, self.retry_delay, self.retry_max_delay)
+    }
+
+    /// The address that servers listening on `port` should bind to.
+    pub fn bind_socket_addr(&self, port: u16) -> String {
+        join_host_port(&self.bind_addr, port)
+    }
+
+    /// The number of bytes range operations move per bridge call.
+    pub fn chunk_bytes(&self) -> u32 {
+        self.chunk_size
+            .unwrap_or_else(|| BridgeDefaults::for_kind(self.bridge_kind).chunk_size)
+            .saturating_mul(4)
+    }
+
+    /// Prefix `line` with the configured label, if any.
+    pub fn labelled(&self, line: &str) -> String {
+        match &self.label {
+            Some(label) => format!("[{}] {}", label, line),
+            None => line.to_owned(),
+        }
+    }
+
+    /// `address` in hex, zero-padded to `--addr-width`, or to the 32-bit
+    /// bus width if that wasn't given. Addresses too large for the width
+    /// are shown in full.
+    pub fn address_text(&self, address: u64) -> String {
+        let digits = usize::from(self.address_width.unwrap_or(4)) * 2;
+        format!("{:0digits$x}", address, digits = digits)
+    }
+
+    /// Whether `deadline` has passed, meaning long-running operations
+    /// should stop.
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| SystemTime::now() >= deadline)
+    }
+
+    /// What to put in front of hex values in text and CSV output.
+    pub fn hex_prefix(&self) -> &'static str {
+        if self.hex_prefix {
+            "0x"
+        } else {
+            ""
+        }
+    }
+
+    /// Apply `--scale` and `--offset` to a value that was read, or return
+    /// `None` if neither was given.
+    pub fn scaled(&self, raw: u32) -> Option<f64> {
+        if self.read_scale.is_none() && self.read_offset.is_none() {
+            return None;
+        }
+        Some(f64::from(raw) * self.read_scale.unwrap_or(1.0) + self.read_offset.unwrap_or(0.0))
+    }
+
+    /// Write the results of an operation to `--output`, or to stdout if
+    /// no file was given. Diagnostics always go to stderr.
+    pub fn write_result(&self, data: &[u8]) -> io::Result<()> {
+        use std::io::Write;
+        match &self.output_file {
+            Some(file) => (&**file).write_all(data),
+            None => io::stdout().write_all(data),
+        }
+    }
+
+    /// Whether the operation talks to the device at all. If not, there is
+    /// no bridge to create.
+    pub fn needs_bridge(&self) -> bool {
+        self.bridge_kind != BridgeKind::None
+    }
+
+    /// How many words the operation will read or write, counting each word
+    /// of a burst. This is `None` when it isn't known in advance: servers
+    /// that run until they are stopped, operations that depend on the
+    /// length of a file, and more than one server at once. Retries and
+    /// `--double-read` aren't counted.
+    pub fn transaction_count(&self) -> Option<u64> {
+        let kind = match self.server_kind.as_slice() {
+            [kind] => kind,
+            _ => return None,
+        };
+        let words = u64::from(self.burst_length.div_ceil(4));
+        match kind {
+            ServerKind::RandomTest => self.random_loops.map(|loops| 2 * u64::from(loops)),
+            ServerKind::Logic => self.logic_max_samples.or(self.poll_count).map(u64::from),
+            ServerKind::MemoryAccess => {
+                if self.poll_file.is_some() {
+                    self.poll_count.map(u64::from)
+                } else if self.dump_registers || self.compare_registers.is_some() {
+                    Some(self.csr_registers.iter().filter(|r| !r.write_only).count() as u64)
+                } else if self.raw_command.is_some() {
+                    Some(1)
+                } else if self.watch_expr.is_some() {
+                    // Polled until it's true
+                    None
+                } else if self.memory_address.is_none() {
+                    Some(0)
+                } else if self.memtest {
+                    let patterns = match self.fill_pattern {
+                        Some(_) => 1,
+                        None => FillPattern::ALL.len() as u64,
+                    };
+                    // Each pattern is written and then read back
+                    Some(2 * patterns * words)
+                } else if self.histogram {
+                    Some(self.histogram_samples.into())
+                } else if self.pulse_value.is_some() {
+                    // Read the original, write the pulse, then restore
+                    Some(3)
+                } else if self.memory_value.is_some() {
+                    Some(1)
+                } else if self.burst_source.is_some() {
+                    None
+                } else if self.value_file.is_some() {
+                    Some(self.values.len() as u64)
+                } else {
+                    // A fill or a read of `burst_length` bytes
+                    Some(words)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// How many words a range read or register dump will read, which is
+    /// what `max_read_size` limits. Other operations, such as polling,
+    /// histograms, random tests and memory tests, run for as long as they
+    /// were asked to and give `None`.
+    fn read_size(&self) -> Option<u64> {
+        if self.server_kind != [ServerKind::MemoryAccess] {
+            return None;
+        }
+        if self.dump_registers || self.compare_registers.is_some() {
+            return Some(self.csr_registers.iter().filter(|r| !r.write_only).count() as u64);
+        }
+        let range_read = self.memory_address.is_some()
+            && self.poll_file.is_none()
+            && self.raw_command.is_none()
+            && self.watch_expr.is_none()
+            && !self.memtest
+            && !self.histogram
+            && self.pulse_value.is_none()
+            && self.memory_value.is_none()
+            && self.burst_source.is_none()
+            && self.value_file.is_none()
+            && self.fill_pattern.is_none();
+        if range_read {
+            Some(u64::from(self.burst_length.div_ceil(4)))
+        } else {
+            None
+        }
+    }
+
+    /// Create the bridge described by this configuration. The bridge
+    /// is not yet connected.
+    pub fn create_bridge(&self) -> Result<Bridge, ConfigError> {
+        match self.bridge_kind {
+            BridgeKind::None => Err(ConfigError::InvalidConfig(
+                "no bridge is needed for this operation".to_owned(),
+            )),
+
+            BridgeKind::Spi => {
+                let pins = self.spi_pins.as_deref().unwrap_or("");
+                SpiBridge::new(pins)
+                    .map_err(ConfigError::SpiParseError)?
+                    .create()
+                    .map_err(|e| {
+                        ConfigError::InvalidConfig(format!("unable to create spi bridge: {}", e))
+                    })
+            }
+
+            BridgeKind::Uart => {
+                let port = self.serial_port.as_deref().unwrap_or("");
+                // Strip off the trailing ":" on Windows, since it's confusing
+                let serial_port = if cfg!(windows) && port.ends_with(':') {
+                    port.get(0..port.len() - 1).unwrap_or("")
+                } else {
+                    port
+                };
+                let mut uart_config = UartBridge::new(serial_port).map_err(|e| {
+                    ConfigError::InvalidConfig(format!("invalid serial port: {}", e))
+                })?;
+
+                if let Some(baud) = self.serial_baud {
+                    uart_config.baud(baud);
                 }
+                if let Some(ms) = self.bridge_timeout {
+                    uart_config.timeout(Duration::from_millis(ms.into()));
+                }
+                if let Some(ms) = self.serial_timeout {
+                    uart_config.read_timeout(Duration::from_millis(ms.into()));
+                }
+                uart_config.flow_control(self.serial_flow_control);
+                uart_config.reconnect(self.serial_reconnect);
+
+                uart_config.create().map_err(|e| {
+                    ConfigError::InvalidConfig(format!("unable to create uart bridge: {}", e))
+                })
             }
-            if server_kind.contains(&ServerKind::FlashProgram) {
-                if !(register_mapping.contains_key("spinor")) {
-                    return Err(ConfigError::InvalidConfig(
-                        "Flash programming requested, but no spinor block present in csv file"
-                            .to_owned(),
-                    ));
+
+            // PCIe BAR-as-a-file
+            BridgeKind::PCIe => {
+                let pcie_bar = self.pcie_bar.as_deref().unwrap_or("");
+                PCIeBridge::new(pcie_bar)
+                    .map_err(|e| ConfigError::InvalidConfig(format!("invalid pcie bar: {}", e)))?
+                    .create()
+                    .map_err(|e| {
+                        ConfigError::InvalidConfig(format!("unable to create pcie bridge: {}", e))
+                    })
+            }
+
+            // Ethernet (TCP or UDP)
+            BridgeKind::Ethernet => {
+                let host = self.ethernet_host.as_deref().unwrap_or("");
+                let ethernet_port = self
+                    .ethernet_port
+                    .or(BridgeDefaults::for_kind(BridgeKind::Ethernet).port)
+                    .unwrap_or_default();
+                let mut ebc =
+                    EthernetBridge::new(join_host_port(host, ethernet_port)).map_err(|e| {
+                        ConfigError::InvalidConfig(format!("invalid ethernet address: {}", e))
+                    })?;
+                ebc.protocol(if self.ethernet_tcp {
+                    EthernetBridgeProtocol::TCP
+                } else {
+                    EthernetBridgeProtocol::UDP
+                })
+                .port(ethernet_port);
+                if let Some(ms) = self.bridge_timeout {
+                    ebc.timeout(Duration::from_millis(ms.into()));
                 }
+                ebc.create().map_err(|e| {
+                    ConfigError::InvalidConfig(format!("unable to create ethernet bridge: {}", e))
+                })
             }
-        }
 
-        let terminal_mouse = matches.is_present("terminal-mouse") || cfg!(windows);
-        let hexdump = matches.is_present("hexdump");
-        let flash_no_reset = matches.is_present("flash-no-reset");
-        let careful_flashing = matches.is_present("careful-flashing");
-
-        let burst_source = matches.value_of("burst-source").map(|n| n.to_owned());
-
-        let bridge = Self::create_bridge(&matches)?;
-
-        Ok((
-            Config {
-                memory_address,
-                memory_value,
-                server_kind,
-                bind_port,
-                bind_addr,
-                gdb_port,
-                random_loops,
-                random_address,
-                random_range,
-                messible_address,
-                register_mapping,
-                debug_offset,
-                load_name,
-                load_addr,
-                load_flash,
-                terminal_mouse,
-                burst_length,
-                hexdump,
-                burst_source,
-                flash_no_reset,
-                careful_flashing,
-            },
-            bridge,
-        ))
+            BridgeKind::Usb => {
+                let mut usb_config = UsbBridge::new();
+                if let Some(vid) = self.usb_vid {
+                    usb_config.vid(vid);
+                }
+                if let Some(pid) = self.usb_pid {
+                    usb_config.pid(pid);
+                }
+                if let Some(bus) = self.usb_bus {
+                    usb_config.bus(bus);
+                }
+                if let Some(device) = self.usb_device {
+                    usb_config.device(device);
+                }
+                usb_config.detach_kernel_driver(self.usb_detach_kernel_driver);
+                if let Some(speed) = self.usb_require_speed {
+                    usb_config.require_speed(speed);
+                }
+                if let Some(bytes) = self.usb_buffer_size {
+                    usb_config.buffer_size(bytes);
+                }
+                if self.usb_pick && (self.usb_bus.is_none() || self.usb_device.is_none()) {
+                    let candidates = usb_config.matching_devices().map_err(|e| {
+                        ConfigError::InvalidConfig(format!("unable to list usb devices: {}", e))
+                    })?;
+                    if candidates.len() > 1 {
+                        use std::io::IsTerminal;
+                        // Waiting on a pipe or /dev/null would hang or fail
+                        // confusingly, so only ask someone who can answer.
+                        if !io::stdin().is_terminal() {
+                            return Err(ConfigError::InvalidConfig(format!(
+                                "{} usb devices match, choose one with --bus and --device",
+                                candidates.len()
+                            )));
+                        }
+                        let chosen =
+                            pick_usb_device(&candidates, io::stdin().lock(), io::stderr())?;
+                        usb_config.bus(chosen.bus).device(chosen.device);
+                    }
+                }
+                usb_config.create().map_err(|e| {
+                    ConfigError::InvalidConfig(format!("unable to create usb bridge: {}", e))
+                })
+            }
+
+            BridgeKind::Mock => {
+                let mut mock_config = MockBridge::new();
+                if let Some(path) = &self.mock_init {
+                    for (addr, value) in parse_mock_init(&std::fs::read_to_string(path)?)? {
+                        mock_config.value(addr, value);
+                    }
+                }
+                mock_config.create().map_err(|e| {
+                    ConfigError::InvalidConfig(format!("unable to create mock bridge: {}", e))
+                })
+            }
+        }
     }
 
-    fn parse_csr_csv(
-        filename: Option<&str>,
+    /// Load register mappings from a `csr.csv` file, applying `offset_str`
+    /// (either a number or the name of a region in the file) to every entry.
+    /// Returns the mapping along with the numeric offset that was applied.
+    pub fn parse_csr_csv(
+        source: Option<&CsvSource>,
         offset_str: Option<&str>,
-    ) -> Result<(HashMap<String, Option<u32>>, u32), ConfigError> {
+    ) -> Result<CsrCsvContents, ConfigError> {
         let mut map = HashMap::new();
-        let file = match filename {
+        let mut regions = HashMap::new();
+        let mut bases = HashMap::new();
+        let mut registers = vec![];
+        let contents = match source {
             None => {
-                if let Some(offset_str) = offset_str {
-                    return Ok((map, parse_u32(offset_str)?));
-                } else {
-                    return Ok((map, 0));
-                }
+                return Ok(CsrCsvContents {
+                    offset: offset_str.map(parse_u32).transpose()?.unwrap_or(0),
+                    ..Default::default()
+                });
             }
-            Some(s) => File::open(s)?,
+            Some(s) => s.read()?,
         };
 
         let mut offset = 0;
 
-        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(&contents[..]);
         for result in rdr.records() {
             if let Ok(r) = result {
                 match &r[0] {
@@ -436,6 +3356,19 @@ impl Config {
                         let reg_name = &r[1];
                         let base_addr = parse_u32(&r[2])?;
                         let num_regs = parse_u32(&r[3])?;
+                        let write_only = r.get(4) == Some("wo");
+                        for logical_reg in 0..num_regs {
+                            let name = if num_regs == 1 {
+                                reg_name.to_lowercase()
+                            } else {
+                                format!("{}{}", reg_name.to_lowercase(), num_regs - logical_reg - 1)
+                            };
+                            registers.push(CsrRegister {
+                                name,
+                                address: base_addr + logical_reg * 4,
+                                write_only,
+                            });
+                        }
 
                         // If there's only one register, add it to the map.
                         // However, CSRs can span multiple registers, and do so in reverse.
@@ -463,11 +3396,21 @@ impl Config {
                         let region = &r[1];
                         let base_addr = parse_u32(&r[2])?;
                         map.insert(region.to_string().to_lowercase(), Some(base_addr));
+                        if let Some(length) = r.get(3) {
+                            regions.insert(
+                                region.to_string().to_lowercase(),
+                                MemoryRegion {
+                                    base: base_addr,
+                                    length: parse_u32(length)?,
+                                },
+                            );
+                        }
                     }
                     "csr_base" => {
                         let region = &r[1];
                         let base_addr = parse_u32(&r[2])?;
                         map.insert(region.to_string().to_lowercase(), Some(base_addr));
+                        bases.insert(region.to_string().to_lowercase(), base_addr);
                     }
                     _ => (),
                 };
@@ -492,7 +3435,1241 @@ impl Config {
                     *val.as_mut().unwrap() -= offset;
                 }
             }
+            regions.retain(|_, region| region.base >= offset);
+            for region in regions.values_mut() {
+                region.base -= offset;
+            }
+            bases.retain(|_, base| *base >= offset);
+            for base in bases.values_mut() {
+                *base -= offset;
+            }
+            registers.retain(|register| register.address >= offset);
+            for register in registers.iter_mut() {
+                register.address -= offset;
+            }
+        }
+        Ok(CsrCsvContents {
+            register_mapping: map,
+            offset,
+            memory_regions: regions,
+            csr_bases: bases,
+            registers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aligned_address_is_accepted() {
+        assert!(check_alignment(0x1000_0004, false).is_ok());
+    }
+
+    #[test]
+    fn unaligned_address_is_rejected() {
+        match check_alignment(0x1000_0002, false) {
+            Err(ConfigError::UnalignedAddress(0x1000_0002)) => (),
+            e => panic!("unexpected result: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unaligned_address_is_allowed_with_override() {
+        assert!(check_alignment(0x1000_0002, true).is_ok());
+    }
+
+    #[test]
+    fn label_prefixes_result_line() {
+        let mut cfg = Config::default();
+        let line = "Value at 10000000: 12345678";
+        assert_eq!(cfg.labelled(line), line);
+
+        cfg.label = Some("board0".to_owned());
+        assert_eq!(cfg.labelled(line), "[board0] Value at 10000000: 12345678");
+    }
+
+    #[test]
+    fn mock_init_lists_address_value_pairs() {
+        let values =
+            parse_mock_init("# initial memory\n0x10,1\n\n0x14, 0xff # trailing\n").unwrap();
+        assert_eq!(values, vec![(0x10, 1), (0x14, 0xff)]);
+        assert!(parse_mock_init("0x10").is_err());
+    }
+
+    #[test]
+    fn mock_bridge_reads_back_writes() {
+        let cfg = Config::builder()
+            .bridge_kind(BridgeKind::Mock)
+            .memory_address(0x1000_0000)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.bridge_kind, BridgeKind::Mock);
+        let bridge = cfg.create_bridge().unwrap();
+        bridge.connect().unwrap();
+        bridge.poke(0x1000_0000, 0x1234_5678).unwrap();
+        assert_eq!(bridge.peek(0x1000_0000).unwrap(), 0x1234_5678);
+    }
+
+    #[test]
+    fn mock_bridge_conflicts_with_other_bridges() {
+        let result = Config::builder()
+            .bridge_kind(BridgeKind::Mock)
+            .serial_port("/dev/ttyUSB0")
+            .memory_address(0x1000_0000)
+            .build();
+        assert!(matches!(result, Err(ConfigError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn config_constructed_without_arg_matches() {
+        let cfg = Config::builder()
+            .ethernet_host("10.0.0.2")
+            .ethernet_port(1234)
+            .server_kind(ServerKind::Wishbone)
+            .bind_port(2345)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.bridge_kind, BridgeKind::Ethernet);
+        assert_eq!(cfg.server_kind, vec![ServerKind::Wishbone]);
+        assert_eq!(cfg.bind_port, 2345);
+    }
+
+    #[test]
+    fn bool_values_map_to_bits() {
+        assert_eq!(parse_bool_value("on").unwrap(), 1);
+        assert_eq!(parse_bool_value("True").unwrap(), 1);
+        assert_eq!(parse_bool_value("FALSE").unwrap(), 0);
+        assert_eq!(parse_bool_value("off").unwrap(), 0);
+        assert_eq!(parse_bool_value("1").unwrap(), 1);
+        assert_eq!(parse_bool_value("0x10").unwrap(), 0x10);
+    }
+
+    #[test]
+    fn unrecognized_bool_value_is_rejected() {
+        assert!(matches!(
+            parse_bool_value("maybe"),
+            Err(ConfigError::UnrecognizedValue(_))
+        ));
+        assert!(matches!(
+            parse_bool_value("0x1g"),
+            Err(ConfigError::NumberParseError(_, _))
+        ));
+    }
+
+    #[test]
+    fn flow_control_names() {
+        assert_eq!(parse_flow_control("none").unwrap(), FlowControl::None);
+        assert_eq!(parse_flow_control("rtscts").unwrap(), FlowControl::Hardware);
+        assert_eq!(
+            parse_flow_control("xonxoff").unwrap(),
+            FlowControl::Software
+        );
+        assert!(matches!(
+            parse_flow_control("dtrdsr"),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn retry_backoff_follows_strategy() {
+        let ms = |backoff: RetryBackoff, attempt| backoff.delay(attempt, 10, 50).as_millis();
+        assert_eq!(ms(RetryBackoff::None, 3), 0);
+        assert_eq!(ms(RetryBackoff::Fixed, 1), 10);
+        assert_eq!(ms(RetryBackoff::Fixed, 3), 10);
+        assert_eq!(ms(RetryBackoff::Exponential, 1), 10);
+        assert_eq!(ms(RetryBackoff::Exponential, 2), 20);
+        assert_eq!(ms(RetryBackoff::Exponential, 3), 40);
+        assert_eq!(ms(RetryBackoff::Exponential, 4), 50);
+        assert_eq!(ms(RetryBackoff::Exponential, 40), 50);
+    }
+
+    #[test]
+    fn host_port_accepts_ipv6() {
+        assert_eq!(split_host_port("::1").unwrap(), ("::1".to_owned(), None));
+        assert_eq!(
+            split_host_port("[::1]:3333").unwrap(),
+            ("::1".to_owned(), Some(3333))
+        );
+        assert_eq!(
+            split_host_port("[fe80::1]").unwrap(),
+            ("fe80::1".to_owned(), None)
+        );
+        assert_eq!(
+            split_host_port("10.0.0.2:1234").unwrap(),
+            ("10.0.0.2".to_owned(), Some(1234))
+        );
+        assert_eq!(join_host_port("::1", 3333), "[::1]:3333");
+        assert_eq!(join_host_port("127.0.0.1", 3333), "127.0.0.1:3333");
+    }
+
+    #[test]
+    fn host_port_rejects_malformed_brackets() {
+        for bad in &[
+            "[::1",
+            "[::1]3333",
+            "[::1]:port",
+            "[not-ipv6]:1",
+            "host:port",
+        ] {
+            assert!(
+                matches!(split_host_port(bad), Err(ConfigError::MalformedAddress(_))),
+                "{} should be rejected",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn builder_accepts_bracketed_ethernet_host() {
+        let cfg = Config::builder()
+            .ethernet_host("[::1]:3333")
+            .bind_addr("[::]")
+            .memory_address(0x1000_0000)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.ethernet_host.as_deref(), Some("::1"));
+        assert_eq!(cfg.ethernet_port, Some(3333));
+        assert_eq!(cfg.bind_socket_addr(1234), "[::]:1234");
+    }
+
+    #[test]
+    fn address_offset_can_be_negative() {
+        assert_eq!(parse_address_offset("0x100").unwrap(), 0x100);
+        assert_eq!(parse_address_offset("-0x100").unwrap(), -0x100);
+        assert_eq!(offset_address(0x1000, -0x100).unwrap(), 0xf00);
+        assert!(offset_address(0xffff_ff00, 0x100).is_err());
+        assert!(offset_address(0x10, -0x20).is_err());
+    }
+
+    #[test]
+    fn builder_applies_address_offset_to_single_read() {
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .address_offset(0x8000_0000)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.memory_address, Some(0x9000_0000));
+
+        let result = Config::builder()
+            .memory_address(0x9000_0000)
+            .address_offset(0x8000_0000)
+            .build();
+        assert!(matches!(result, Err(ConfigError::AddressOutOfRange(_))));
+    }
+
+    #[test]
+    fn builder_applies_address_offset_to_range_read() {
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .burst_length(256)
+            .address_offset(-0x1000_0000)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.memory_address, Some(0));
+
+        let result = Config::builder()
+            .memory_address(0x7fff_ff00)
+            .burst_length(0x200)
+            .address_offset(0x8000_0000)
+            .build();
+        assert!(matches!(result, Err(ConfigError::AddressOutOfRange(_))));
+    }
+
+    #[test]
+    fn builder_applies_address_offset_to_register_map() {
+        let mut map = HashMap::new();
+        map.insert("messible_out".to_owned(), Some(0xe000_8000));
+        let cfg = Config::builder()
+            .server_kind(ServerKind::Messible)
+            .register_mapping(map)
+            .address_offset(0x100)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.messible_address, Some(0xe000_8100));
+        assert_eq!(cfg.register_mapping["messible_out"], Some(0xe000_8100));
+    }
+
+    #[test]
+    fn incompatible_options_are_named() {
+        let result = Config::builder()
+            .memory_address(0x1000_0000)
+            .memory_value(1)
+            .poll_file("value.txt")
+            .build();
+        match result {
+            Err(ConfigError::IncompatibleOptions(options)) => {
+                assert_eq!(options, vec!["--poll-file", "VALUE"])
+            }
+            _ => panic!("expected IncompatibleOptions"),
+        }
+
+        let result = Config::builder()
+            .memory_address(0x1000_0000)
+            .burst_length(64)
+            .double_read(true)
+            .poll_file("value.txt")
+            .build();
+        match result {
+            Err(ConfigError::IncompatibleOptions(options)) => assert_eq!(
+                options,
+                vec!["--poll-file", "--burst-length", "--double-read"]
+            ),
+            _ => panic!("expected IncompatibleOptions"),
+        }
+    }
+
+    #[test]
+    fn compatible_options_are_accepted() {
+        Config::builder()
+            .memory_address(0x1000_0000)
+            .burst_length(64)
+            .hexdump(true)
+            .build()
+            .unwrap();
+        Config::builder()
+            .memory_address(0x1000_0000)
+            .double_read(true)
+            .poll_file("value.txt")
+            .build()
+            .unwrap();
+    }
+
+    #[test]
+    fn builder_requires_an_operation() {
+        match Config::builder().build() {
+            Err(ConfigError::NoOperationSpecified) => (),
+            _ => panic!("expected NoOperationSpecified"),
+        }
+    }
+
+    #[test]
+    fn builder_resolves_memory_access() {
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.server_kind, vec![ServerKind::MemoryAccess]);
+        assert_eq!(cfg.bridge_kind, BridgeKind::Usb);
+        assert_eq!(cfg.debug_offset, 0xf00f_0000);
+    }
+
+    #[test]
+    fn builder_resolves_flash_program() {
+        let image = std::env::temp_dir().join(format!("wishbone-image-{}.bin", std::process::id()));
+        std::fs::write(&image, [0u8; 16]).unwrap();
+        let mut map = HashMap::new();
+        map.insert("spinor".to_owned(), Some(0xe000_3000));
+        let build = |confirmed| {
+            Config::builder()
+                .load_name(image.to_str().unwrap())
+                .load_addr(0)
+                .load_flash(true)
+                .confirm_size(confirmed)
+                .register_mapping(map.clone())
+                .build()
+        };
+        let matching = build(16);
+        let mismatching = build(4096);
+        std::fs::remove_file(&image).ok();
+        assert_eq!(
+            matching.unwrap().server_kind,
+            vec![ServerKind::FlashProgram]
+        );
+        assert!(matches!(
+            mismatching,
+            Err(ConfigError::ConfirmationMismatch {
+                confirmed: 4096,
+                actual: 16
+            })
+        ));
+    }
+
+    #[test]
+    fn builder_rejects_unaligned_address() {
+        let result = Config::builder().memory_address(0x1000_0001).build();
+        assert!(matches!(
+            result,
+            Err(ConfigError::UnalignedAddress(0x1000_0001))
+        ));
+    }
+
+    #[test]
+    fn builder_infers_bridge_kind() {
+        let cfg = Config::builder()
+            .serial_port("/dev/ttyUSB0")
+            .memory_address(0)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.bridge_kind, BridgeKind::Uart);
+    }
+
+    #[test]
+    fn builder_rejects_bridge_without_parameters() {
+        let result = Config::builder()
+            .bridge_kind(BridgeKind::Uart)
+            .memory_address(0)
+            .build();
+        assert!(matches!(result, Err(ConfigError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn builder_rejects_conflicting_bridge() {
+        let result = Config::builder()
+            .bridge_kind(BridgeKind::PCIe)
+            .pcie_bar("/sys/bus/pci/devices/0000:01:00.0/resource0")
+            .spi_pins("2,3,4,18")
+            .memory_address(0)
+            .build();
+        assert!(matches!(result, Err(ConfigError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn builder_checks_register_map_for_gdb() {
+        let mut map = HashMap::new();
+        map.insert("ctrl_reset".to_owned(), Some(0xe000_0000));
+        let result = Config::builder()
+            .server_kind(ServerKind::GDB)
+            .register_mapping(map)
+            .build();
+        assert!(matches!(result, Err(ConfigError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn builder_requires_output_for_logic() {
+        let result = Config::builder()
+            .server_kind(ServerKind::Logic)
+            .memory_address(0x1000_0000)
+            .build();
+        assert!(matches!(
+            result,
+            Err(ConfigError::MissingDependentOption { .. })
+        ));
+
+        let cfg = Config::builder()
+            .server_kind(ServerKind::Logic)
+            .memory_address(0x1000_0000)
+            .logic_file("capture.csv")
+            .build()
+            .unwrap();
+        assert_eq!(cfg.server_kind, vec![ServerKind::Logic]);
+    }
+
+    #[test]
+    fn builder_takes_messible_from_register_map() {
+        let mut map = HashMap::new();
+        map.insert("messible_out".to_owned(), Some(0xe000_8000));
+        let cfg = Config::builder()
+            .server_kind(ServerKind::Messible)
+            .register_mapping(map)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.messible_address, Some(0xe000_8000));
+    }
+
+    #[test]
+    fn memory_regions_come_from_csr_csv() {
+        let path =
+            std::env::temp_dir().join(format!("wishbone-regions-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "#--------\nmemory_region,sram,0x10000000,8192,cached\ncsr_base,ctrl,0xe0000000,,\n\
+             csr_register,ctrl_reset,0xe0000000,1,wo\ncsr_register,timer0_load,0xe0002000,2,rw\n",
+        )
+        .unwrap();
+        let source = CsvSource::File(path.to_string_lossy().into_owned());
+        let result = Config::parse_csr_csv(Some(&source), None);
+        std::fs::remove_file(&path).ok();
+        let contents = result.unwrap();
+        let (map, regions) = (contents.register_mapping, contents.memory_regions);
+        assert_eq!(map["sram"], Some(0x1000_0000));
+        let registers: Vec<_> = contents
+            .registers
+            .iter()
+            .map(|r| (r.name.as_str(), r.address, r.write_only))
+            .collect();
+        assert_eq!(
+            registers,
+            vec![
+                ("ctrl_reset", 0xe000_0000, true),
+                ("timer0_load1", 0xe000_2000, false),
+                ("timer0_load0", 0xe000_2004, false),
+            ]
+        );
+        assert_eq!(
+            regions["sram"],
+            MemoryRegion {
+                base: 0x1000_0000,
+                length: 8192
+            }
+        );
+        assert!(!regions.contains_key("ctrl"));
+        assert_eq!(contents.csr_bases["ctrl"], 0xe000_0000);
+    }
+
+    #[test]
+    fn file_service_needs_known_region() {
+        let mut regions = MemoryRegions::new();
+        regions.insert(
+            "sram".to_owned(),
+            MemoryRegion {
+                base: 0x1000_0000,
+                length: 8192,
+            },
+        );
+        let mut builder = Config::builder();
+        builder
+            .server_kind(ServerKind::FileService)
+            .memory_regions(regions);
+        assert!(matches!(
+            builder.build(),
+            Err(ConfigError::MissingDependentOption { .. })
+        ));
+        assert!(builder.file_region("SRAM").build().is_ok());
+        assert!(matches!(
+            builder.file_region("rom").build(),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn undefined_variable_is_named() {
+        let mut defines = HashMap::new();
+        defines.insert("BASE".to_owned(), "0x10000000".to_owned());
+        assert_eq!(
+            substitute_defines("${BASE}+${BASE}", &defines).unwrap(),
+            "0x10000000+0x10000000"
+        );
+        match substitute_defines("0,read,${BASE},${VALUE}", &defines) {
+            Err(ConfigError::UndefinedVariable(key)) => assert_eq!(key, "VALUE"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn define_needs_key_and_value() {
+        assert_eq!(
+            parse_define("BASE=0x10000000").unwrap(),
+            ("BASE".to_owned(), "0x10000000".to_owned())
+        );
+        assert!(parse_define("BASE").is_err());
+        assert!(parse_define("=1").is_err());
+    }
+
+    #[test]
+    fn pulse_conflicts_with_value() {
+        let result = Config::builder()
+            .memory_address(0x1000_0000)
+            .memory_value(1)
+            .pulse_value(1)
+            .build();
+        match result {
+            Err(ConfigError::IncompatibleOptions(options)) => {
+                assert_eq!(options, vec!["--pulse", "VALUE"])
+            }
+            _ => panic!("expected IncompatibleOptions"),
+        }
+    }
+
+    #[test]
+    fn chunk_size_defaults_per_bridge() {
+        assert_eq!(Config::default().chunk_bytes(), 4096);
+        let cfg = Config {
+            bridge_kind: BridgeKind::Uart,
+            ..Default::default()
+        };
+        assert_eq!(cfg.chunk_bytes(), 4);
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .chunk_size(16)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.chunk_bytes(), 64);
+        assert!(matches!(
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .chunk_size(0)
+                .build(),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn named_address_needs_register_map() {
+        let mut map = HashMap::new();
+        assert_eq!(
+            parse_named_address("0x10000000", &map, 0).unwrap(),
+            0x1000_0000
+        );
+        match parse_named_address("ctrl_scratch", &map, 0) {
+            Err(ConfigError::RegisterMapRequired(feature)) => {
+                assert!(feature.contains("ctrl_scratch"))
+            }
+            other => panic!("expected RegisterMapRequired, got {:?}", other),
+        }
+        assert!(matches!(
+            parse_named_address("0x1000zz", &map, 0),
+            Err(ConfigError::NumberParseError(_, _))
+        ));
+
+        map.insert("ctrl_scratch".to_owned(), Some(0xe000_0004));
+        assert_eq!(
+            parse_named_address("CTRL_SCRATCH", &map, 0).unwrap(),
+            0xe000_0004
+        );
+        assert!(matches!(
+            parse_named_address("ctrl_reset", &map, 0),
+            Err(ConfigError::NumberParseError(_, _))
+        ));
+    }
+
+    #[test]
+    fn region_needs_register_map() {
+        let result = Config::builder()
+            .server_kind(ServerKind::FileService)
+            .file_region("sram")
+            .build();
+        match result {
+            Err(ConfigError::RegisterMapRequired(feature)) => assert_eq!(feature, "--file-region"),
+            _ => panic!("expected RegisterMapRequired"),
+        }
+    }
+
+    #[test]
+    fn omitted_bridge_settings_come_from_defaults() {
+        let dev_null = if cfg!(windows) { "NUL" } else { "/dev/null" };
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .serial_port(dev_null)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.serial_baud, Some(115_200));
+        assert_eq!(cfg.bridge_timeout, Some(1000));
+        assert_eq!(cfg.chunk_bytes(), 4);
+
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .ethernet_host("10.0.0.5")
+            .build()
+            .unwrap();
+        assert_eq!(cfg.ethernet_port, Some(1234));
+        assert_eq!(cfg.serial_baud, None);
+        assert_eq!(cfg.bridge_timeout, Some(1000));
+
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.bridge_kind, BridgeKind::Usb);
+        assert_eq!(cfg.serial_baud, None);
+        assert_eq!(cfg.bridge_timeout, None);
+        assert_eq!(cfg.chunk_bytes(), 4096);
+
+        for kind in &[BridgeKind::PCIe, BridgeKind::Spi] {
+            assert_eq!(BridgeDefaults::for_kind(*kind).timeout, None);
+            assert_eq!(BridgeDefaults::for_kind(*kind).chunk_size, 1);
+        }
+    }
+
+    #[test]
+    fn explicit_bridge_settings_win_over_defaults() {
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .ethernet_host("10.0.0.5")
+            .ethernet_port(1235)
+            .bridge_timeout(250)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.ethernet_port, Some(1235));
+        assert_eq!(cfg.bridge_timeout, Some(250));
+
+        assert!(matches!(
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .bridge_timeout(250)
+                .build(),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+
+        // With --watch-expr, --timeout still limits the wait
+        let cfg = Config::builder()
+            .bridge_kind(BridgeKind::Mock)
+            .watch_expr(WatchExpr::parse("reg(0x100) == 1", parse_u32).unwrap())
+            .bridge_timeout(250)
+            .watch_timeout(250)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.bridge_timeout, None);
+        assert_eq!(cfg.watch_timeout, Some(250));
+    }
+
+    #[test]
+    fn serial_timeout_is_uart_only() {
+        let dev_null = if cfg!(windows) { "NUL" } else { "/dev/null" };
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .serial_port(dev_null)
+            .serial_timeout(20)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.serial_timeout, Some(20));
+        assert_eq!(cfg.bridge_timeout, Some(1000));
+
+        assert!(matches!(
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .ethernet_host("10.0.0.5")
+                .serial_timeout(20)
+                .build(),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .serial_port(dev_null)
+                .serial_timeout(0)
+                .build(),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn required_speed_is_usb_only() {
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .usb_require_speed(parse_usb_speed("high").unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(cfg.usb_require_speed, Some(UsbSpeed::High));
+        assert!(parse_usb_speed("ludicrous").is_err());
+        assert!(matches!(
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .ethernet_host("10.0.0.5")
+                .usb_require_speed(UsbSpeed::High)
+                .build(),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn usb_buffer_size_is_checked() {
+        let build = |bytes| {
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .usb_buffer_size(bytes)
+                .build()
+        };
+        assert_eq!(build(16384).unwrap().usb_buffer_size, Some(16384));
+        assert!(matches!(build(0), Err(ConfigError::InvalidConfig(_))));
+        assert!(matches!(build(65536), Err(ConfigError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn transactions_are_counted() {
+        let count = |builder: &mut ConfigBuilder| {
+            builder
+                .memory_address(0x1000_0000)
+                .build()
+                .unwrap()
+                .transaction_count()
+        };
+        assert_eq!(count(&mut Config::builder()), Some(1));
+        assert_eq!(count(Config::builder().memory_value(5)), Some(1));
+        assert_eq!(count(Config::builder().burst_length(64)), Some(16));
+        assert_eq!(
+            count(
+                Config::builder()
+                    .burst_length(32)
+                    .fill_pattern(FillPattern::Walking)
+            ),
+            Some(8)
+        );
+        assert_eq!(
+            count(
+                Config::builder()
+                    .server_kind(ServerKind::RandomTest)
+                    .random_loops(100)
+            ),
+            Some(200)
+        );
+        assert_eq!(
+            count(Config::builder().server_kind(ServerKind::RandomTest)),
+            None
+        );
+        assert_eq!(
+            count(Config::builder().server_kind(ServerKind::Wishbone)),
+            None
+        );
+    }
+
+    #[test]
+    fn unaligned_lengths_follow_policy() {
+        let build = |bytes, policy| {
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .burst_length(bytes)
+                .length_policy(policy)
+                .build()
+        };
+        assert_eq!(build(16, LengthPolicy::Reject).unwrap().burst_length, 16);
+        match build(14, LengthPolicy::Reject) {
+            Err(e @ ConfigError::UnalignedLength(14)) => assert_eq!(
+                e.to_string(),
+                "a length of 14 bytes is not a whole number of 32-bit words (use --warn-unaligned-length to drop the partial word)"
+            ),
+            other => panic!("expected UnalignedLength, got {:?}", other.err()),
+        }
+        assert_eq!(build(14, LengthPolicy::RoundDown).unwrap().burst_length, 12);
+        assert!(matches!(
+            build(3, LengthPolicy::RoundDown),
+            Err(ConfigError::UnalignedLength(3))
+        ));
+        // The length isn't checked when writing a single value, so the
+        // only complaint is that the two options don't go together
+        assert!(matches!(
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .memory_value(1)
+                .burst_length(3)
+                .build(),
+            Err(ConfigError::IncompatibleOptions(_))
+        ));
+    }
+
+    #[test]
+    fn value_list_is_parsed() {
+        assert_eq!(
+            parse_value_list("# boot vector\n0x10\n\n  42 # answer\n0b101\n").unwrap(),
+            vec![0x10, 42, 5]
+        );
+        match parse_value_list("1\n2\nthree\n") {
+            Err(ConfigError::ValueFileParseError(line, _)) => assert_eq!(line, 3),
+            other => panic!("expected ValueFileParseError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deadlines_are_rfc3339() {
+        let at = |seconds, nanos| UNIX_EPOCH + Duration::new(seconds, nanos);
+        assert_eq!(
+            parse_rfc3339("2024-05-01T22:00:00Z").unwrap(),
+            at(1_714_600_800, 0)
+        );
+        assert_eq!(
+            parse_rfc3339("2024-05-01t23:30:00.25+01:30").unwrap(),
+            at(1_714_600_800, 250_000_000)
+        );
+        assert_eq!(
+            parse_rfc3339("2000-02-29 00:00:00-00:00").unwrap(),
+            at(951_782_400, 0)
+        );
+        for invalid in &[
+            "tomorrow",
+            "2024-05-01T22:00:00",
+            "2024-05-01T22:00Z",
+            "2023-02-29T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-05-01T24:00:00Z",
+            "2024-05-01T22:00:00.Z",
+            "2024-05-01T22:00:00+1:00",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert!(
+                matches!(parse_rfc3339(invalid), Err(ConfigError::InvalidConfig(_))),
+                "{} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn long_reads_are_limited() {
+        let build = |bytes, limit| {
+            Config::builder()
+                .memory_address(0x1000_0000)
+                .burst_length(bytes)
+                .max_read_size(limit)
+                .build()
+        };
+        assert!(build(0x40_0000, 0x10_0000).is_ok());
+        assert!(matches!(
+            build(0x40_0004, 0x10_0000),
+            Err(ConfigError::ReadTooLarge {
+                requested: 0x10_0001,
+                limit: 0x10_0000
+            })
+        ));
+        assert!(build(0x8000_0000, 0).is_ok());
+
+        // Dumps count every register they read
+        let registers: Vec<_> = (0..8)
+            .map(|i| CsrRegister {
+                name: format!("reg{}", i),
+                address: 0x1000 + 4 * i,
+                write_only: false,
+            })
+            .collect();
+        let dump = |limit| {
+            Config::builder()
+                .csr_registers(registers.clone())
+                .dump_registers(true)
+                .max_read_size(limit)
+                .build()
+        };
+        assert!(dump(8).is_ok());
+        assert!(matches!(
+            dump(4),
+            Err(ConfigError::ReadTooLarge {
+                requested: 8,
+                limit: 4
+            })
+        ));
+
+        // Operations that repeat for as long as they're asked to aren't
+        // limited, however many transactions they make
+        assert!(Config::builder()
+            .server_kind(ServerKind::RandomTest)
+            .random_loops(600_000)
+            .max_read_size(0x10_0000)
+            .build()
+            .is_ok());
+        assert!(Config::builder()
+            .memory_address(0x1000_0000)
+            .histogram(true)
+            .histogram_samples(2_000_000)
+            .max_read_size(0x10_0000)
+            .build()
+            .is_ok());
+        assert!(Config::builder()
+            .memory_address(0x1000_0000)
+            .burst_length(0x10_0000)
+            .memtest(true)
+            .max_read_size(0x10_0000)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn usb_device_is_picked_from_list() {
+        let device = |bus, device, serial: Option<&str>| UsbDeviceInfo {
+            bus,
+            device,
+            vid: 0x1209,
+            pid: 0x5bf0,
+            serial: serial.map(|s| s.to_owned()),
+        };
+        let candidates = [device(1, 4, Some("A1")), device(3, 9, None)];
+
+        let mut prompt = vec![];
+        let chosen = pick_usb_device(&candidates, &b"3\nsecond\n2\n"[..], &mut prompt).unwrap();
+        assert_eq!(chosen, &candidates[1]);
+        let prompt = String::from_utf8(prompt).unwrap();
+        assert!(prompt.contains("  1) bus 001 device 004 (1209:5bf0), serial A1\n"));
+        assert!(prompt.contains("  2) bus 003 device 009 (1209:5bf0), serial unknown\n"));
+        assert_eq!(prompt.matches("use device [1-2]: ").count(), 3);
+
+        assert!(matches!(
+            pick_usb_device(&candidates, &b"0\n"[..], io::sink()),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn min_success_rate_is_a_percentage() {
+        for percent in &[0.0, 99.5, 100.0] {
+            let cfg = Config::builder()
+                .server_kind(ServerKind::RandomTest)
+                .random_loops(100)
+                .min_success_rate(*percent)
+                .build()
+                .unwrap();
+            assert_eq!(cfg.min_success_rate, Some(*percent));
+        }
+        for percent in &[-1.0, 100.5, f64::NAN] {
+            assert!(matches!(
+                Config::builder()
+                    .server_kind(ServerKind::RandomTest)
+                    .random_loops(100)
+                    .min_success_rate(*percent)
+                    .build(),
+                Err(ConfigError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn dependent_options_name_what_is_missing() {
+        let missing = |builder: &mut ConfigBuilder| match builder.build() {
+            Err(ConfigError::MissingDependentOption { option, requires }) => (option, requires),
+            _ => panic!("missing option wasn't reported"),
+        };
+        assert_eq!(
+            missing(Config::builder().server_kind(ServerKind::Logic)),
+            ("--server logic", "ADDRESS")
+        );
+        assert_eq!(
+            missing(
+                Config::builder()
+                    .server_kind(ServerKind::Logic)
+                    .memory_address(0x1000_0000)
+            ),
+            ("--server logic", "--out")
+        );
+        assert_eq!(
+            missing(Config::builder().server_kind(ServerKind::Replay)),
+            ("--server replay", "--replay-log")
+        );
+        assert_eq!(
+            missing(
+                Config::builder()
+                    .server_kind(ServerKind::Wishbone)
+                    .abort_after_failures(3)
+            ),
+            ("--abort-after-failures", "--server random-test")
+        );
+        assert_eq!(
+            missing(
+                Config::builder()
+                    .memory_address(0x1000_0000)
+                    .min_success_rate(99.5)
+            ),
+            ("--min-success-rate", "--server random-test or --memtest")
+        );
+        assert_eq!(
+            missing(
+                Config::builder()
+                    .server_kind(ServerKind::RandomTest)
+                    .min_success_rate(99.5)
+            ),
+            ("--min-success-rate", "--random-loops or --deadline")
+        );
+        assert_eq!(
+            ConfigError::MissingDependentOption {
+                option: "--server file",
+                requires: "--file-region"
+            }
+            .to_string(),
+            "--server file requires --file-region"
+        );
+    }
+
+    #[test]
+    fn safe_mode_refuses_unknown_writes() {
+        let mut map = HashMap::new();
+        map.insert("ctrl_scratch".to_owned(), Some(0xe000_0004));
+        let mut regions = MemoryRegions::new();
+        regions.insert(
+            "sram".to_owned(),
+            MemoryRegion {
+                base: 0x1000_0000,
+                length: 0x2000,
+            },
+        );
+        let write = |addr: u32, allow: bool| {
+            Config::builder()
+                .bridge_kind(BridgeKind::Mock)
+                .register_mapping(map.clone())
+                .memory_regions(regions.clone())
+                .memory_address(addr)
+                .memory_value(1)
+                .safe_writes(true)
+                .unsafe_writes(allow)
+                .build()
+                .map(|_| ())
+        };
+        assert!(write(0xe000_0004, false).is_ok());
+        assert!(write(0x1000_1ffc, false).is_ok());
+        assert!(matches!(
+            write(0x1000_2000, false),
+            Err(ConfigError::UnsafeWrite {
+                address: 0x1000_2000
+            })
+        ));
+        assert!(matches!(
+            write(0xe000_0008, false),
+            Err(ConfigError::UnsafeWrite { .. })
+        ));
+        assert!(write(0xe000_0008, true).is_ok());
+
+        // Reads aren't restricted
+        assert!(Config::builder()
+            .bridge_kind(BridgeKind::Mock)
+            .register_mapping(map.clone())
+            .memory_address(0xe000_0008)
+            .safe_writes(true)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn safe_mode_checks_the_whole_range() {
+        let mut map = HashMap::new();
+        map.insert("ctrl_scratch".to_owned(), Some(0xe000_0004));
+        map.insert("ctrl_bus_errors".to_owned(), Some(0xe000_0008));
+        let mut regions = MemoryRegions::new();
+        regions.insert(
+            "sram".to_owned(),
+            MemoryRegion {
+                base: 0x1000_0000,
+                length: 0x2000,
+            },
+        );
+        let fill = |addr: u32, length: u32| {
+            Config::builder()
+                .bridge_kind(BridgeKind::Mock)
+                .register_mapping(map.clone())
+                .memory_regions(regions.clone())
+                .memory_address(addr)
+                .burst_length(length)
+                .fill_pattern(FillPattern::Zeros)
+                .safe_writes(true)
+                .build()
+                .map(|_| ())
+        };
+        assert!(fill(0x1000_1ff0, 16).is_ok());
+        assert!(matches!(
+            fill(0x1000_1ff0, 20),
+            Err(ConfigError::UnsafeWrite {
+                address: 0x1000_2000
+            })
+        ));
+        assert!(fill(0xe000_0004, 8).is_ok());
+        assert!(matches!(
+            fill(0xe000_0004, 12),
+            Err(ConfigError::UnsafeWrite {
+                address: 0xe000_000c
+            })
+        ));
+    }
+
+    #[test]
+    fn raw_command_is_whole_hex_bytes() {
+        assert_eq!(
+            parse_hex_bytes("0x0201cafe").unwrap(),
+            vec![2, 1, 0xca, 0xfe]
+        );
+        assert_eq!(
+            parse_hex_bytes("02 01 CA FE").unwrap(),
+            vec![2, 1, 0xca, 0xfe]
+        );
+        assert!(parse_hex_bytes("abc").is_err());
+        assert!(parse_hex_bytes("zz").is_err());
+        assert!(parse_hex_bytes("").is_err());
+    }
+
+    #[test]
+    fn retry_on_takes_error_kinds() {
+        assert_eq!(
+            parse_retry_on("timeout, pipe").unwrap(),
+            vec![BridgeErrorKind::Timeout, BridgeErrorKind::Pipe]
+        );
+        assert!(parse_retry_on("timeout,bus-fault").is_err());
+        assert_eq!(Config::default().retry_on, vec![BridgeErrorKind::Timeout]);
+    }
+
+    #[test]
+    fn address_explanation_shows_each_step() {
+        let mut map = HashMap::new();
+        map.insert("uart_rxtx".to_owned(), Some(0xe000_1000));
+        let resolution = explain_named_address("UART_RXTX", &map, 0x800).unwrap();
+        assert_eq!(
+            resolution,
+            "UART_RXTX (csv) = 0xe0001800, - register offset 0x00000800 = 0xe0001000"
+        );
+        assert_eq!(
+            explain_named_address("0x10", &map, 0).unwrap(),
+            "0x10 (number) = 0x00000010, - register offset 0x00000000 = 0x00000010"
+        );
+
+        let cfg = ConfigBuilder::new()
+            .memory_address(0xe000_1000)
+            .address_offset(0x10)
+            .explain_address(true)
+            .address_resolution(resolution)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.memory_address, Some(0xe000_1010));
+        assert_eq!(
+            cfg.address_resolution.unwrap(),
+            "UART_RXTX (csv) = 0xe0001800, - register offset 0x00000800 = 0xe0001000, \
+             + address offset 0x10 = 0xe0001010"
+        );
+    }
+
+    #[test]
+    fn fill_patterns_produce_expected_words() {
+        let words = |pattern: FillPattern, base: u32| -> Vec<u32> {
+            (0..4).map(|i| pattern.word(base, i)).collect()
+        };
+        assert_eq!(words(FillPattern::Zeros, 0x1000_0000), vec![0; 4]);
+        assert_eq!(words(FillPattern::Ones, 0x1000_0000), vec![0xffff_ffff; 4]);
+        assert_eq!(words(FillPattern::Walking, 0), vec![1, 2, 4, 8]);
+        assert_eq!(FillPattern::Walking.word(0, 31), 0x8000_0000);
+        assert_eq!(FillPattern::Walking.word(0, 32), 1);
+        assert_eq!(
+            words(FillPattern::Checker, 0),
+            vec![0x5555_5555, 0xaaaa_aaaa, 0x5555_5555, 0xaaaa_aaaa]
+        );
+        assert_eq!(
+            words(FillPattern::Incrementing, 0x1000_0000),
+            vec![0x1000_0000, 0x1000_0001, 0x1000_0002, 0x1000_0003]
+        );
+        assert_eq!(
+            words(FillPattern::Incrementing, 0xffff_fffe),
+            vec![0xffff_fffe, 0xffff_ffff, 0, 1]
+        );
+        assert_eq!(FillPattern::Walking.bytes(0, 6), vec![1, 0, 0, 0, 2, 0]);
+        assert!(FillPattern::from_string("stripes").is_err());
+    }
+
+    #[test]
+    fn pattern_conflicts_with_value() {
+        let result = Config::builder()
+            .memory_address(0x1000_0000)
+            .memory_value(1)
+            .fill_pattern(FillPattern::Checker)
+            .build();
+        match result {
+            Err(ConfigError::IncompatibleOptions(options)) => {
+                assert_eq!(options, vec!["--pattern", "VALUE"])
+            }
+            _ => panic!("expected IncompatibleOptions"),
         }
-        Ok((map, offset))
+        assert!(matches!(
+            Config::builder().fill_pattern(FillPattern::Ones).build(),
+            Err(ConfigError::NoOperationSpecified)
+        ));
+    }
+
+    #[test]
+    fn explaining_an_address_opens_no_bridge() {
+        let dev_null = if cfg!(windows) { "NUL" } else { "/dev/null" };
+        let cfg = Config::builder()
+            .serial_port(dev_null)
+            .memory_address(0x1000_0000)
+            .explain_address(true)
+            .address_resolution("0x10000000 (number) = 0x10000000".to_owned())
+            .build()
+            .unwrap();
+        assert_eq!(cfg.bridge_kind, BridgeKind::None);
+        assert!(cfg.server_kind.is_empty());
+        assert!(!cfg.needs_bridge());
+        assert!(cfg.create_bridge().is_err());
+
+        let cfg = Config::builder()
+            .memory_address(0x1000_0000)
+            .build()
+            .unwrap();
+        assert!(cfg.needs_bridge());
+
+        assert!(matches!(
+            Config::builder()
+                .bridge_kind(BridgeKind::None)
+                .memory_address(0x1000_0000)
+                .build(),
+            Err(ConfigError::InvalidConfig(_))
+        ));
     }
 }
@@ -0,0 +1,105 @@
+//! The exit status of the process. Scripts may rely on these codes to tell
+//! failures apart without having to parse the error message:
+//!
+//! | Code | Meaning                                                       |
+//! |------|---------------------------------------------------------------|
+//! | 0    | Success                                                       |
+//! | 1    | The command line or a file it names couldn't be used          |
+//! | 2    | The bridge or an I/O operation failed                         |
+//! | 3    | A verification failed, e.g. a checksum or read-back mismatch  |
+//! | 4    | The device stopped responding                                 |
+//! | 5    | A server stopped unexpectedly                                 |
+
+use wishbone_bridge::BridgeError;
+
+use crate::config::ConfigError;
+use crate::server::ServerError;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExitStatus {
+    Success = 0,
+    ConfigError = 1,
+    BridgeError = 2,
+    Mismatch = 3,
+    Timeout = 4,
+    Internal = 5,
+}
+
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(status: ExitStatus) -> std::process::ExitCode {
+        std::process::ExitCode::from(status as u8)
+    }
+}
+
+impl From<&ConfigError> for ExitStatus {
+    fn from(_: &ConfigError) -> ExitStatus {
+        ExitStatus::ConfigError
+    }
+}
+
+impl From<&BridgeError> for ExitStatus {
+    fn from(e: &BridgeError) -> ExitStatus {
+        if e.is_timeout() {
+            ExitStatus::Timeout
+        } else {
+            ExitStatus::BridgeError
+        }
+    }
+}
+
+impl From<&ServerError> for ExitStatus {
+    fn from(e: &ServerError) -> ExitStatus {
+        match e {
+            ServerError::BridgeError(e) => e.into(),
+            ServerError::IoError(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                ExitStatus::Timeout
+            }
+            ServerError::UnmappableAddress(_)
+            | ServerError::ReplayParseError(_, _)
+            | ServerError::SnapshotParseError(_, _)
+            | ServerError::Unsupported(_) => ExitStatus::ConfigError,
+            ServerError::RandomValueError(_, _, _)
+            | ServerError::RandomTestFailed(_, _)
+            | ServerError::FlashError(_, _)
+            | ServerError::ReadMismatch(_, _, _)
+            | ServerError::ChecksumMismatch(_, _)
+            | ServerError::MemtestFailed(_)
+            | ServerError::SuccessRateTooLow(_, _)
+            | ServerError::UnexpectedValue(_, _, _) => ExitStatus::Mismatch,
+            ServerError::IoError(_)
+            | ServerError::WishboneError(_)
+            | ServerError::GdbError(_)
+            | ServerError::RiscvCpuError(_)
+            | ServerError::TerminalError(_) => ExitStatus::BridgeError,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn errors_map_to_documented_codes() {
+        let status = |e: ServerError| ExitStatus::from(&e) as u8;
+        assert_eq!(
+            ExitStatus::from(&ConfigError::NoOperationSpecified) as u8,
+            1
+        );
+        assert_eq!(
+            status(ServerError::BridgeError(BridgeError::NotConnected)),
+            2
+        );
+        assert_eq!(status(io::Error::from(io::ErrorKind::NotFound).into()), 2);
+        assert_eq!(status(ServerError::ChecksumMismatch(1, 2)), 3);
+        assert_eq!(status(ServerError::ReadMismatch(0, 1, 2)), 3);
+        assert_eq!(status(ServerError::BridgeError(BridgeError::Timeout)), 4);
+        assert_eq!(
+            status(ServerError::BridgeError(BridgeError::IoError(
+                io::ErrorKind::TimedOut.into()
+            ))),
+            4
+        );
+    }
+}
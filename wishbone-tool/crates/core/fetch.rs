@@ -0,0 +1,118 @@
+//! A minimal HTTP client, used to download a `csr.csv` file that is
+//! published on a web server. Only plain `http://` with a `200` response is
+//! supported, since there is no TLS implementation available to the tool.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::config::{split_host_port, ConfigError};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Download the contents of `url`.
+pub fn fetch_url(url: &str) -> Result<Vec<u8>, ConfigError> {
+    let error = |reason: String| ConfigError::CsvFetchError(url.to_owned(), reason);
+
+    if url.starts_with("https://") {
+        return Err(error(
+            "https is not supported, serve the file over http instead".to_owned(),
+        ));
+    }
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| error("not an http:// url".to_owned()))?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+    let (host, port) = split_host_port(authority).map_err(|e| error(e.to_string()))?;
+    let port = port.unwrap_or(80);
+
+    let addr = (host.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|e| error(e.to_string()))?
+        .next()
+        .ok_or_else(|| error(format!("no address found for {}", host)))?;
+    let mut stream =
+        TcpStream::connect_timeout(&addr, FETCH_TIMEOUT).map_err(|e| error(e.to_string()))?;
+    stream
+        .set_read_timeout(Some(FETCH_TIMEOUT))
+        .map_err(|e| error(e.to_string()))?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, authority
+    )
+    .map_err(|e| error(e.to_string()))?;
+    let mut response = vec![];
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| error(e.to_string()))?;
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| error("incomplete response from server".to_owned()))?;
+    let headers = String::from_utf8_lossy(&response[..header_end]);
+    let status = headers.lines().next().unwrap_or("");
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(response.split_off(header_end + 4)),
+        _ => Err(error(format!("server responded with \"{}\"", status))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve a single request with `response`, returning the URL to fetch.
+    fn serve_once(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            use std::io::{BufRead, BufReader};
+            let (mut connection, _) = listener.accept().unwrap();
+            // Wait for the blank line that ends the request headers
+            let reader = BufReader::new(connection.try_clone().unwrap());
+            for line in reader.lines() {
+                if line.unwrap().is_empty() {
+                    break;
+                }
+            }
+            connection.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://127.0.0.1:{}/csr.csv", port)
+    }
+
+    #[test]
+    fn body_is_returned() {
+        let url = serve_once(
+            "HTTP/1.0 200 OK\r\nContent-Type: text/csv\r\n\r\ncsr_base,ctrl,0xe0000000,,\n",
+        );
+        assert_eq!(
+            fetch_url(&url).unwrap(),
+            b"csr_base,ctrl,0xe0000000,,\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn error_status_names_the_url() {
+        let url = serve_once("HTTP/1.0 404 Not Found\r\n\r\n");
+        match fetch_url(&url) {
+            Err(ConfigError::CsvFetchError(u, _)) => assert_eq!(u, url),
+            _ => panic!("expected CsvFetchError"),
+        }
+    }
+
+    #[test]
+    fn https_is_refused() {
+        assert!(matches!(
+            fetch_url("https://example.com/csr.csv"),
+            Err(ConfigError::CsvFetchError(_, _))
+        ));
+    }
+}
@@ -0,0 +1,264 @@
+//! Where diagnostics from the `log` macros end up. Stderr is handled by
+//! flexi_logger itself. Syslog and the systemd journal are reached through
+//! their local datagram sockets, so neither needs a client library.
+
+use std::path::Path;
+
+use crate::config::LogTarget;
+
+/// Start the global logger, sending everything to `target`. `socket`
+/// replaces the usual syslog or journal socket, and is ignored for stderr.
+/// This can only be done once.
+pub fn start(target: LogTarget, socket: Option<&Path>) -> Result<(), String> {
+    let logger = flexi_logger::Logger::with_env_or_str("wishbone_tool=info");
+    let logger = match target {
+        LogTarget::Stderr => logger.format_for_stderr(|write, now, record| {
+            if let Some(label) = crate::LABEL.get() {
+                write!(write, "[{}] ", label)?;
+            }
+            flexi_logger::colored_default_format(write, now, record)?;
+            write!(write, "\r")
+        }),
+        LogTarget::Syslog | LogTarget::Journald => {
+            let writer = socket::SocketWriter::connect(target, socket)
+                .map_err(|e| format!("couldn't log to {}: {}", target.name(), e))?;
+            logger.log_target(flexi_logger::LogTarget::Writer(Box::new(writer)))
+        }
+    };
+    logger
+        .start()
+        .map(|_| ())
+        .map_err(|e| format!("couldn't start logging: {}", e))
+}
+
+/// How severe a record is to syslog and the journal, which share the
+/// severities in RFC 5424.
+fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+/// The text of a record, prefixed with the `--label` if there is one.
+fn message(record: &log::Record) -> String {
+    match crate::LABEL.get() {
+        Some(label) => format!("[{}] {}", label, record.args()),
+        None => record.args().to_string(),
+    }
+}
+
+/// One syslog datagram, from the "user" facility.
+fn syslog_datagram(level: log::Level, message: &str) -> Vec<u8> {
+    const FACILITY_USER: u8 = 1;
+    format!(
+        "<{}>{}[{}]: {}",
+        FACILITY_USER * 8 + severity(level),
+        crate_name!(),
+        std::process::id(),
+        message
+    )
+    .into_bytes()
+}
+
+/// One journal entry, in the journal's native protocol. Fields holding a
+/// newline must be sent with an explicit length instead of after `=`.
+fn journald_datagram(level: log::Level, target: &str, message: &str) -> Vec<u8> {
+    let mut datagram = vec![];
+    let fields = [
+        ("PRIORITY", severity(level).to_string()),
+        ("SYSLOG_IDENTIFIER", crate_name!().to_owned()),
+        ("TARGET", target.to_owned()),
+        ("MESSAGE", message.to_owned()),
+    ];
+    for (name, value) in fields.iter() {
+        datagram.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+    datagram
+}
+
+#[cfg(unix)]
+mod socket {
+    use super::{journald_datagram, message, syslog_datagram};
+    use crate::config::LogTarget;
+    use flexi_logger::{writers::LogWriter, DeferredNow};
+    use std::io;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::Path;
+
+    /// Sends each record to a syslog or journal socket.
+    pub struct SocketWriter {
+        target: LogTarget,
+        socket: UnixDatagram,
+    }
+
+    impl SocketWriter {
+        /// Connect to `path`, or the usual socket for `target` if there's
+        /// none.
+        pub fn connect(target: LogTarget, path: Option<&Path>) -> io::Result<SocketWriter> {
+            let usual = match target {
+                LogTarget::Journald => "/run/systemd/journal/socket",
+                _ => "/dev/log",
+            };
+            SocketWriter::connect_to(target, path.unwrap_or_else(|| Path::new(usual)))
+        }
+
+        pub fn connect_to(target: LogTarget, path: &Path) -> io::Result<SocketWriter> {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(path)?;
+            Ok(SocketWriter { target, socket })
+        }
+
+        pub fn send(&self, record: &log::Record) -> io::Result<()> {
+            let message = message(record);
+            let datagram = match self.target {
+                LogTarget::Journald => journald_datagram(record.level(), record.target(), &message),
+                _ => syslog_datagram(record.level(), &message),
+            };
+            self.socket.send(&datagram).map(|_| ())
+        }
+    }
+
+    impl LogWriter for SocketWriter {
+        fn write(&self, _now: &mut DeferredNow, record: &log::Record) -> io::Result<()> {
+            self.send(record)
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn max_log_level(&self) -> log::LevelFilter {
+            log::LevelFilter::Trace
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod socket {
+    use crate::config::LogTarget;
+    use flexi_logger::{writers::LogWriter, DeferredNow};
+    use std::io;
+    use std::path::Path;
+
+    /// Neither syslog nor the journal exist here, so this can't be created.
+    pub struct SocketWriter;
+
+    impl SocketWriter {
+        pub fn connect(_target: LogTarget, _path: Option<&Path>) -> io::Result<SocketWriter> {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "only supported on unix systems",
+            ))
+        }
+    }
+
+    impl LogWriter for SocketWriter {
+        fn write(&self, _now: &mut DeferredNow, _record: &log::Record) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn max_log_level(&self) -> log::LevelFilter {
+            log::LevelFilter::Off
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn levels_map_to_syslog_severities() {
+        assert_eq!(
+            String::from_utf8(syslog_datagram(log::Level::Error, "no bridge")).unwrap(),
+            format!("<11>wishbone-tool[{}]: no bridge", std::process::id())
+        );
+        assert_eq!(severity(log::Level::Warn), 4);
+        assert_eq!(severity(log::Level::Info), 6);
+        assert_eq!(severity(log::Level::Trace), 7);
+    }
+
+    #[test]
+    fn journal_fields_with_newlines_have_lengths() {
+        let datagram = journald_datagram(log::Level::Info, "wishbone_tool", "a\nb");
+        let mut expected = b"PRIORITY=6\nSYSLOG_IDENTIFIER=wishbone-tool\n".to_vec();
+        expected.extend_from_slice(b"TARGET=wishbone_tool\nMESSAGE\n");
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(datagram, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn records_are_sent_to_the_chosen_target() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = std::env::temp_dir().join(format!("wishbone-log-{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let server = UnixDatagram::bind(&path).unwrap();
+        let writer = socket::SocketWriter::connect_to(LogTarget::Journald, &path).unwrap();
+        writer
+            .send(
+                &log::Record::builder()
+                    .level(log::Level::Warn)
+                    .target("wishbone_tool")
+                    .args(format_args!("slow"))
+                    .build(),
+            )
+            .unwrap();
+        let mut buf = [0; 256];
+        let len = server.recv(&mut buf).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(
+            &buf[..len],
+            &b"PRIORITY=4\nSYSLOG_IDENTIFIER=wishbone-tool\nTARGET=wishbone_tool\nMESSAGE=slow\n"[..]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn started_logger_writes_to_syslog() {
+        use std::os::unix::net::UnixDatagram;
+        use std::time::Duration;
+
+        let path = std::env::temp_dir().join(format!("wishbone-syslog-{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let server = UnixDatagram::bind(&path).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        start(LogTarget::Syslog, Some(&path)).unwrap();
+        log::warn!("started logger test");
+
+        // Other tests may log at the same time, so look for this one
+        let expected = format!(
+            "<12>wishbone-tool[{}]: started logger test",
+            std::process::id()
+        );
+        let mut buf = [0; 256];
+        let found = loop {
+            match server.recv(&mut buf) {
+                Ok(len) if buf[..len] == *expected.as_bytes() => break true,
+                Ok(_) => (),
+                Err(_) => break false,
+            }
+        };
+        std::fs::remove_file(&path).ok();
+        assert!(found);
+    }
+}
@@ -5,25 +5,39 @@ extern crate clap;
 
 extern crate indicatif;
 
-use log::debug;
+use log::{debug, error, info};
 
+mod cli;
 mod config;
+mod exit;
+mod fetch;
 mod gdb;
+mod logging;
+mod output;
 mod riscv;
 mod server;
+mod watch;
 mod wishbone;
 
 use clap::{App, Arg, Shell};
-use config::Config;
+use exit::ExitStatus;
 use server::ServerKind;
 
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+
+/// Label used to tag diagnostic lines, set from `--label`.
+static LABEL: OnceLock<String> = OnceLock::new();
 
 fn clap_app<'a, 'b>() -> App<'a, 'b> {
     App::new("Wishbone Tool")
         .version(crate_version!())
         .author("Sean Cross <sean@xobs.io>")
         .about("Work with Wishbone devices over various bridges")
+        .after_help(
+            "EXIT STATUS:\n    0  success\n    1  invalid command line or input file\n    \
+             2  bridge or I/O error\n    3  verification mismatch\n    \
+             4  the device stopped responding\n    5  a server stopped unexpectedly",
+        )
         .arg(
             Arg::with_name("completion")
             .group("command")
@@ -88,8 +102,7 @@ fn clap_app<'a, 'b>() -> App<'a, 'b> {
                 .short("b")
                 .long("baud")
                 .value_name("RATE")
-                .default_value("115200")
-                .help("SERIAL: baudrate to use for serial port")
+                .help("SERIAL: baudrate to use for serial port [default: 115200]")
                 .display_order(5)
                 .takes_value(true),
         )
@@ -106,8 +119,7 @@ fn clap_app<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("ethernet-port")
                 .long("ethernet-port")
                 .value_name("PORT")
-                .help("ETHERNET: port to use for Ethernet bridge")
-                .default_value("1234")
+                .help("ETHERNET: port to use for Ethernet bridge [default: 1234]")
                 .display_order(7)
                 .takes_value(true)
         )
@@ -149,13 +161,13 @@ fn clap_app<'a, 'b>() -> App<'a, 'b> {
                 .index(2)
                 .required(false)
                 .display_order(12)
-                .help("value to write"),
+                .help("value to write (on/off and true/false are accepted as 1 and 0)"),
         )
 
         .arg(
             Arg::with_name("csr-csv")
                 .long("csr-csv")
-                .help("csr.csv file containing register mappings")
+                .help("csr.csv file containing register mappings, either a path or an http:// URL")
                 .display_order(13)
                 .takes_value(true),
         )
@@ -178,7 +190,29 @@ fn clap_app<'a, 'b>() -> App<'a, 'b> {
                 .multiple(true)
                 .help("which server to run (if any)")
                 .display_order(15)
-                .possible_values(&["gdb", "wishbone", "random-test", "load-file", "terminal", "messible"]),
+                // memory-access and flash-program follow from ADDRESS and
+                // --load-flash, so they can't be asked for by name
+                .possible_values(&ServerKind::ALL
+                    .iter()
+                    .filter(|kind| !matches!(kind, ServerKind::MemoryAccess | ServerKind::FlashProgram))
+                    .map(|kind| kind.name())
+                    .collect::<Vec<_>>()),
+        )
+
+        .arg(
+            Arg::with_name("list-server-kinds")
+                .group("command")
+                .long("list-server-kinds")
+                .help("Print the name of every kind of server, then exit")
+                .display_order(111),
+        )
+
+        .arg(
+            Arg::with_name("list-bridge-kinds")
+                .group("command")
+                .long("list-bridge-kinds")
+                .help("Print the name of every kind of bridge, then exit")
+                .display_order(112),
         )
 
         .arg(
@@ -283,7 +317,7 @@ fn clap_app<'a, 'b>() -> App<'a, 'b> {
         .arg(
             Arg::with_name("burst-length")
             .long("burst-length")
-            .help("Number of bytes in a burst (implies burst operation)")
+            .help("Number of bytes in a burst (implies burst operation). Must be a multiple of 4 unless --warn-unaligned-length is given")
             .default_value("4")
             .display_order(28)
             .takes_value(true),
@@ -320,19 +354,681 @@ fn clap_app<'a, 'b>() -> App<'a, 'b> {
             .display_order(32)
             .takes_value(false),
         )
+
+        .arg(
+            Arg::with_name("allow-unaligned")
+            .long("allow-unaligned")
+            .help("Permit addresses that are not aligned to a 32-bit word, for buses that support it")
+            .display_order(33)
+            .takes_value(false),
+        )
+
+        .arg(
+            Arg::with_name("label")
+            .long("label")
+            .value_name("LABEL")
+            .help("Prefix every result and diagnostic line with [LABEL], e.g. for log aggregation")
+            .display_order(34)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("poll-file")
+            .long("poll-file")
+            .value_name("PATH")
+            .help("Keep reading the address and rewrite PATH with the latest value after each read. With --format jsonl, add a line to PATH for each read instead")
+            .display_order(35)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("poll-interval")
+            .long("poll-interval")
+            .value_name("MS")
+            .help("Number of milliseconds to wait between polled reads")
+            .default_value("1000")
+            .display_order(36)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("double-read")
+            .long("double-read")
+            .help("Read each word twice and only accept it if both reads agree. Halves read throughput.")
+            .display_order(37)
+            .takes_value(false),
+        )
+
+        .arg(
+            Arg::with_name("retry-count")
+            .long("retry-count")
+            .value_name("COUNT")
+            .help("Number of times to retry a read whose --double-read results disagree, or an operation that fails with an error listed in --retry-on")
+            .default_value("3")
+            .display_order(38)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("out")
+            .long("out")
+            .value_name("PATH")
+            .help("LOGIC: CSV file to append timestamp,value samples to")
+            .display_order(39)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("max-samples")
+            .long("max-samples")
+            .value_name("COUNT")
+            .help("LOGIC: stop after recording this many samples")
+            .display_order(40)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("max-clients")
+            .long("max-clients")
+            .value_name("COUNT")
+            .help("Maximum number of GDB and Wishbone clients connected at once (0 for no limit)")
+            .default_value("8")
+            .display_order(41)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("retry-backoff")
+            .long("retry-backoff")
+            .value_name("STRATEGY")
+            .help("How to wait between retries: not at all, a fixed delay, or a doubling delay")
+            .default_value("fixed")
+            .possible_values(&["none", "fixed", "exponential"])
+            .display_order(42)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("retry-delay")
+            .long("retry-delay")
+            .value_name("MS")
+            .help("Milliseconds to wait before the first retry")
+            .default_value("10")
+            .display_order(43)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("retry-max-delay")
+            .long("retry-max-delay")
+            .value_name("MS")
+            .help("Longest delay between retries when using exponential backoff")
+            .default_value("1000")
+            .display_order(44)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("replay-log")
+            .long("replay-log")
+            .value_name("PATH")
+            .help("REPLAY: transaction log to play back, either logic output or timestamp,read|write,address,value rows")
+            .display_order(45)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("preserve-timing")
+            .long("preserve-timing")
+            .help("REPLAY: wait between transactions as long as the original capture did")
+            .display_order(46)
+            .takes_value(false),
+        )
+
+        .arg(
+            Arg::with_name("address-offset")
+            .long("address-offset")
+            .value_name("OFFSET")
+            .help("Add OFFSET (which may be negative, e.g. -0x1000) to every address, including ones from the csr.csv file")
+            .allow_hyphen_values(true)
+            .display_order(47)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("mock")
+            .long("mock")
+            .help("MOCK: don't connect to a device, and keep all memory on the host instead")
+            .display_order(48)
+            .takes_value(false),
+        )
+
+        .arg(
+            Arg::with_name("mock-init")
+            .long("mock-init")
+            .value_name("PATH")
+            .help("MOCK: file of address,value lines giving the initial memory contents (implies --mock)")
+            .display_order(49)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("How to print the results of reads and writes")
+            .default_value("text")
+            .possible_values(&["text", "csv", "json", "jsonl"])
+            .display_order(50)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("flow-control")
+            .long("flow-control")
+            .value_name("MODE")
+            .help("SERIAL: flow control to use on the serial port")
+            .default_value("none")
+            .possible_values(&["none", "rtscts", "xonxoff"])
+            .display_order(51)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("file-region")
+            .long("file-region")
+            .value_name("REGION")
+            .help("FILE: name of the memory region from the csv file to serve")
+            .display_order(52)
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("file-port")
+            .long("file-port")
+            .value_name("PORT_NUMBER")
+            .help("FILE: port to listen on for file requests")
+            .default_value("6969")
+            .display_order(53)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("slow-threshold")
+            .long("slow-threshold")
+            .value_name("MS")
+            .help("Warn about any transaction that takes longer than this many milliseconds")
+            .display_order(54)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("detach-kernel-driver")
+            .long("detach-kernel-driver")
+            .help("USB: detach any kernel driver bound to the device's interface (Linux only)")
+            .display_order(55),
+        )
+
+        .arg(
+            Arg::with_name("define")
+            .long("define")
+            .value_name("KEY=VALUE")
+            .help("REPLAY: replace ${KEY} in the replay log with VALUE. May be given more than once")
+            .multiple(true)
+            .number_of_values(1)
+            .display_order(56)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("verify-checksum")
+            .long("verify-checksum")
+            .help("LOAD_FILE: after loading, read the region back and compare its CRC32 with the file's")
+            .display_order(57),
+        )
+
+        .arg(
+            Arg::with_name("pulse")
+            .long("pulse")
+            .value_name("VALUE")
+            .help("Write VALUE to the address, then write back the value that was there before")
+            .display_order(58)
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pulse-width")
+            .long("pulse-width")
+            .value_name("MS")
+            .help("How long to hold the --pulse value before restoring the original")
+            .default_value("0")
+            .display_order(59)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("reconnect")
+            .long("reconnect")
+            .help("SERIAL: if the serial port disappears, fall back to the only other serial port, retrying with a growing delay")
+            .display_order(60),
+        )
+
+        .arg(
+            Arg::with_name("seed")
+            .long("seed")
+            .value_name("SEED")
+            .help("RANDOM_TEST: seed for the random values and addresses, to repeat an earlier run")
+            .display_order(61)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("dump-registers")
+            .long("dump-registers")
+            .help("Read every register listed in the csr.csv file and print its value")
+            .display_order(62),
+        )
+
+        .arg(
+            Arg::with_name("chunk-size")
+            .long("chunk-size")
+            .value_name("WORDS")
+            .help("How many words burst reads, writes and loads move per bridge call (default depends on the bridge)")
+            .display_order(63)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("timeout")
+            .long("timeout")
+            .value_name("MS")
+            .help("SERIAL/ETHERNET: how long to wait for the device to answer [default: 1000]. With --watch-expr, also how long to wait for the expression to become true")
+            .display_order(64)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("compare-registers")
+            .long("compare-registers")
+            .value_name("SNAPSHOT")
+            .help("Read every register and print the ones that differ from SNAPSHOT, a file saved from --dump-registers")
+            .display_order(65)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("require-speed")
+            .long("require-speed")
+            .value_name("SPEED")
+            .help("USB: refuse to connect unless the device enumerated at least at this speed")
+            .display_order(66)
+            .possible_values(&["full", "high", "super"])
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("output")
+            .long("output")
+            .value_name("PATH")
+            .help("Write the results of reads and writes to PATH instead of stdout")
+            .display_order(67)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("histogram")
+            .long("histogram")
+            .help("Read ADDRESS repeatedly and print how often each value was seen, with the mean and standard deviation")
+            .display_order(68),
+        )
+
+        .arg(
+            Arg::with_name("samples")
+            .long("samples")
+            .value_name("COUNT")
+            .help("HISTOGRAM: number of reads to take")
+            .default_value("1000")
+            .display_order(69)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("bins")
+            .long("bins")
+            .value_name("COUNT")
+            .help("HISTOGRAM: group values into at most this many buckets")
+            .default_value("16")
+            .display_order(70)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("abort-after-failures")
+            .long("abort-after-failures")
+            .value_name("COUNT")
+            .help("RANDOM_TEST: log failures and keep going, stopping once COUNT have been seen")
+            .display_order(73)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("scale")
+            .long("scale")
+            .value_name("FACTOR")
+            .help("Also print single reads multiplied by FACTOR, e.g. 0.000805664 for millivolts from a 12-bit 3.3V ADC")
+            .allow_hyphen_values(true)
+            .display_order(74)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("offset")
+            .long("offset")
+            .value_name("AMOUNT")
+            .help("Also print single reads with AMOUNT added, after any --scale")
+            .allow_hyphen_values(true)
+            .display_order(75)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("usb-buffer-size")
+            .long("usb-buffer-size")
+            .value_name("BYTES")
+            .help("USB: bytes to move in each transfer of a burst read or write [default: 4096]")
+            .display_order(76)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("safe")
+            .long("safe")
+            .help("Refuse to write to an address that isn't a register or memory region in the --csr-csv file")
+            .display_order(77),
+        )
+
+        .arg(
+            Arg::with_name("unsafe")
+            .long("unsafe")
+            .help("Write to ADDRESS even though --safe would refuse")
+            .display_order(78),
+        )
+
+        .arg(
+            Arg::with_name("poll-count")
+            .long("poll-count")
+            .value_name("COUNT")
+            .help("Stop --poll-file or logic capture after COUNT reads instead of waiting for Ctrl-C")
+            .display_order(79)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("raw-command")
+            .long("raw-command")
+            .value_name("HEX")
+            .help("ADVANCED: send HEX bytes to the bridge as they are and print the reply, for bringing up custom bridge commands (mock and serial bridges only)")
+            .display_order(80)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("addr-width")
+            .long("addr-width")
+            .value_name("BYTES")
+            .help("Pad addresses in dumps and listings to this many bytes (default: 4)")
+            .display_order(83)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("retry-on")
+            .long("retry-on")
+            .value_name("KINDS")
+            .help("Comma-separated bridge errors worth retrying: timeout, pipe, disconnected, protocol, address, unsupported or io (default: timeout)")
+            .display_order(84)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("no-hex-prefix")
+            .long("no-hex-prefix")
+            .help("Print hex values in text and CSV output without a leading 0x")
+            .display_order(85),
+        )
+
+        .arg(
+            Arg::with_name("keepalive")
+            .long("keepalive")
+            .value_name("MS")
+            .help("Read --keepalive-address whenever the bridge has been idle this long while serving, to keep the link warm (0 disables it)")
+            .default_value("0")
+            .display_order(86)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("keepalive-address")
+            .long("keepalive-address")
+            .value_name("ADDRESS")
+            .help("Address or register that --keepalive reads, which must be harmless to read")
+            .display_order(87)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("explain-address")
+            .long("explain-address")
+            .help("Print how ADDRESS is resolved from the register map and offsets, then exit without touching the device")
+            .display_order(88),
+        )
+
+        .arg(
+            Arg::with_name("pattern")
+            .long("pattern")
+            .value_name("PATTERN")
+            .help("Write PATTERN across --burst-length bytes at ADDRESS instead of a single VALUE")
+            .possible_values(&["zeros", "ones", "walking", "checker", "incrementing"])
+            .display_order(89)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("memtest")
+            .long("memtest")
+            .help("Write each --pattern (or every pattern) across --burst-length bytes at ADDRESS, read it back and report every word that differs")
+            .display_order(90),
+        )
+
+        .arg(
+            Arg::with_name("trace")
+            .long("trace")
+            .value_name("PATH")
+            .help("Append every word read or written to PATH, in the format --server replay plays back")
+            .display_order(91)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("expect")
+            .long("expect")
+            .value_name("VALUE")
+            .help("Read ADDRESS and exit with an error unless it equals VALUE")
+            .display_order(92)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("mask")
+            .long("mask")
+            .value_name("MASK")
+            .help("Only compare the bits set in MASK with --expect")
+            .display_order(93)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("pick")
+            .long("pick")
+            .help("USB: list the devices and ask which to use when more than one matches")
+            .display_order(94),
+        )
+
+        .arg(
+            Arg::with_name("max-read-size")
+            .long("max-read-size")
+            .value_name("WORDS")
+            .help("Refuse range reads and register dumps of more than WORDS words (0 for no limit)")
+            .default_value("1048576")
+            .display_order(95)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("log-target")
+            .long("log-target")
+            .value_name("TARGET")
+            .help("Where to send diagnostics: stderr, or syslog or the systemd journal when running as a service")
+            .default_value("stderr")
+            .possible_values(&["stderr", "syslog", "journald"])
+            .display_order(96)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("reverse")
+            .long("reverse")
+            .help("Read --burst-length bytes at ADDRESS, or --dump-registers, from the highest address down")
+            .display_order(97),
+        )
+
+        .arg(
+            Arg::with_name("value-file")
+            .long("value-file")
+            .value_name("PATH")
+            .help("Write the values in PATH, one per line, to consecutive words from ADDRESS")
+            .display_order(98)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("warn-unaligned-length")
+            .long("warn-unaligned-length")
+            .help("Drop the partial word at the end of a --burst-length that isn't a multiple of 4, with a warning, rather than refusing it")
+            .display_order(99),
+        )
+
+        .arg(
+            Arg::with_name("human")
+            .long("human")
+            .help("Also show values read as a size, such as 1.5 MiB, in text output")
+            .display_order(100),
+        )
+
+        .arg(
+            Arg::with_name("watch-expr")
+            .long("watch-expr")
+            .value_name("EXPR")
+            .help("Read registers every --poll-interval until EXPR is true, e.g. \"(reg(0x1000) & 0x3) == 0x2 && reg(0x1004) != 0\". Supports &, |, ==, !=, &&, || and parentheses")
+            .display_order(101)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("preflight")
+            .long("preflight")
+            .help("Read --preflight-address (or --keepalive-address) once before a server starts listening, and stop if the device doesn't answer")
+            .display_order(102),
+        )
+
+        .arg(
+            Arg::with_name("preflight-address")
+            .long("preflight-address")
+            .value_name("ADDRESS")
+            .help("Address or register that --preflight reads, which must be harmless to read")
+            .display_order(103)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("compact")
+            .long("compact")
+            .help("Print the words of a multi-word read on one line, as ADDRESS=VALUE pairs separated by spaces")
+            .display_order(104),
+        )
+
+        .arg(
+            Arg::with_name("confirm-size")
+            .long("confirm-size")
+            .value_name("BYTES")
+            .help("Size of the file given to --load-flash, which must match before the flash is erased and written")
+            .display_order(105)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("deadline")
+            .long("deadline")
+            .value_name("TIME")
+            .help("Stop servers, random tests, polling and logic capture cleanly at this RFC 3339 time, such as 2024-05-01T22:00:00Z")
+            .display_order(106)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("group-by-region")
+            .long("group-by-region")
+            .help("Print --dump-registers under the name of each peripheral or memory region in the --csr-csv file, with \"unmapped\" for the rest")
+            .display_order(107),
+        )
+
+        .arg(
+            Arg::with_name("trace-changes-only")
+            .long("trace-changes-only")
+            .help("Only add a read to --trace or the logic capture when its value differs from the last one recorded for that address")
+            .display_order(108),
+        )
+
+        .arg(
+            Arg::with_name("serial-timeout")
+            .long("serial-timeout")
+            .value_name("MS")
+            .help("SERIAL: how long each read from the port may wait before --timeout is checked again, and how long a --raw-command reply must be quiet to end [default: picked from the baud rate]")
+            .display_order(109)
+            .takes_value(true),
+        )
+
+        .arg(
+            Arg::with_name("min-success-rate")
+            .long("min-success-rate")
+            .value_name("PERCENT")
+            .help("RANDOM_TEST/MEMTEST: log failures and keep going, then pass if at least PERCENT of loops or words succeeded, e.g. 99.5. A random test needs --random-loops or --deadline to finish")
+            .display_order(110)
+            .takes_value(true),
+        )
 }
 
-fn main() -> Result<(), String> {
-    flexi_logger::Logger::with_env_or_str("wishbone_tool=info")
-        .format_for_stderr(|write, now, record| {
-            flexi_logger::colored_default_format(write, now, record)?;
-            write!(write, "\r")
-        })
-        .start()
-        .unwrap();
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(()) => ExitStatus::Success.into(),
+        Err((status, message)) => {
+            eprintln!("Error: {}", message);
+            status.into()
+        }
+    }
+}
 
+fn run() -> Result<(), (ExitStatus, String)> {
     let matches = clap_app().get_matches();
 
+    if let Some(label) = matches.value_of("label") {
+        LABEL.set(label.to_owned()).ok();
+    }
+    // Logging starts before the rest of the configuration is parsed, so
+    // that warnings from parsing it reach the right place.
+    // unwrap() is safe because there is a default value
+    let log_target = config::LogTarget::from_string(matches.value_of("log-target").unwrap())
+        .map_err(|e| (ExitStatus::from(&e), e.to_string()))?;
+    logging::start(log_target, None).map_err(|e| (ExitStatus::ConfigError, e))?;
+
     // If they specify a "--completion", print it to stdout and exit without error.
     if let Some(shell_str) = matches.value_of("completion") {
         use std::io;
@@ -343,24 +1039,112 @@ fn main() -> Result<(), String> {
         return Ok(());
     }
 
-    let (cfg, bridge) = Config::parse(matches).map_err(|e| match e {
-        config::ConfigError::NumberParseError(num, e) => {
-            format!("unable to parse the number \"{}\": {}", num, e)
+    if matches.is_present("list-server-kinds") {
+        for kind in ServerKind::ALL {
+            println!("{}", kind.name());
+        }
+        return Ok(());
+    }
+    if matches.is_present("list-bridge-kinds") {
+        for kind in config::BridgeKind::ALL {
+            println!("{}", kind.name());
         }
-        config::ConfigError::NoOperationSpecified => format!("no operation was specified"),
-        config::ConfigError::UnknownServerKind(s) => format!("unknown server '{}', see --help", s),
-        config::ConfigError::SpiParseError(s) => format!("couldn't parse spi pins: {}", s),
-        config::ConfigError::IoError(s) => format!("file error: {}", s),
-        config::ConfigError::InvalidConfig(s) => format!("invalid configuration: {}", s),
-        config::ConfigError::AddressOutOfRange(s) => {
-            format!("address was not in mappable range: {}", s)
+        return Ok(());
+    }
+
+    let cfg = cli::parse_config(&matches, log_target)
+        .map_err(|e| (ExitStatus::from(&e), e.to_string()))?;
+    if !cfg.needs_bridge() {
+        // --explain-address is the only operation that runs without a
+        // bridge
+        return match &cfg.address_resolution {
+            Some(resolution) => {
+                println!("{}", cfg.labelled(resolution));
+                Ok(())
+            }
+            None => {
+                let e = config::ConfigError::InvalidConfig(
+                    "only --explain-address can run without a bridge".to_owned(),
+                );
+                Err((ExitStatus::from(&e), e.to_string()))
+            }
+        };
+    }
+    if let Some(count) = cfg.transaction_count() {
+        debug!("this operation makes {} transactions", count);
+    }
+    let mut bridge = cfg
+        .create_bridge()
+        .map_err(|e| (ExitStatus::from(&e), e.to_string()))?;
+    let trace = match &cfg.trace_file {
+        Some(path) => {
+            let trace = Arc::new(
+                server::TraceLog::open(path, cfg.trace_changes_only).map_err(|e| {
+                    (
+                        ExitStatus::ConfigError,
+                        format!("couldn't open {}: {}", path, e),
+                    )
+                })?,
+            );
+            let observer = trace.clone();
+            bridge.observe(move |access| observer.record(access));
+            Some(trace)
         }
+        None => None,
+    };
+    bridge.connect().map_err(|e| {
+        (
+            ExitStatus::from(&e),
+            format!("unable to connect to bridge: {}", e),
+        )
+    })?;
+
+    server::preflight(&cfg, &bridge).map_err(|e| {
+        (
+            ExitStatus::from(&e),
+            format!("preflight check failed: {:?}", e),
+        )
     })?;
-    bridge
-        .connect()
-        .map_err(|e| format!("unable to connect to bridge: {}", e))?;
 
     let cfg = Arc::new(cfg);
+    if cfg.keepalive != 0 {
+        let bridge = bridge.clone();
+        let cfg = cfg.clone();
+        // Not joined: it only stops if the bridge does
+        std::thread::spawn(move || {
+            if let Err(e) = server::keepalive(&cfg, bridge) {
+                error!("keepalive stopped: {:?}", e);
+            }
+        });
+    }
+    if cfg.server_kind.iter().any(|kind| kind.listens()) {
+        server::shutdown().install().map_err(|e| {
+            (
+                ExitStatus::Internal,
+                format!("unable to handle Ctrl-C: {}", e),
+            )
+        })?;
+        // Not joined: it ends the process once requests have drained
+        let trace = trace.clone();
+        std::thread::spawn(move || {
+            server::shutdown().wait_for_drain();
+            if let Some(trace) = trace {
+                trace.flush().ok();
+            }
+            info!("shutting down");
+            std::process::exit(ExitStatus::Success as i32);
+        });
+        if let Some(deadline) = cfg.deadline {
+            // Not joined: it only asks the servers to stop
+            std::thread::spawn(move || {
+                if let Ok(remaining) = deadline.duration_since(std::time::SystemTime::now()) {
+                    std::thread::sleep(remaining);
+                }
+                info!("deadline reached, shutting down");
+                server::shutdown().request();
+            });
+        }
+    }
     let mut threads = vec![];
     for server_kind in cfg.server_kind.iter() {
         use std::thread;
@@ -368,7 +1152,7 @@ fn main() -> Result<(), String> {
         let cfg = cfg.clone();
         let server_kind = *server_kind;
         let thr_handle = thread::spawn(move || {
-            match server_kind {
+            let result = match server_kind {
                 ServerKind::GDB => server::gdb_server(&cfg, bridge),
                 ServerKind::Wishbone => server::wishbone_server(&cfg, bridge),
                 ServerKind::RandomTest => server::random_test(&cfg, bridge),
@@ -377,15 +1161,43 @@ fn main() -> Result<(), String> {
                 ServerKind::MemoryAccess => server::memory_access(&cfg, bridge),
                 ServerKind::Messible => server::messible_client(&cfg, bridge),
                 ServerKind::FlashProgram => server::flash_program(&cfg, bridge),
-            }
-            .expect("couldn't start server");
+                ServerKind::Logic => server::logic_capture(&cfg, bridge),
+                ServerKind::Replay => server::replay(&cfg, bridge),
+                ServerKind::FileService => server::file_server(&cfg, bridge),
+            };
             debug!("Exited {:?} thread", server_kind);
+            result
         });
-        threads.push(thr_handle);
-    }
-    for handle in threads {
-        handle.join().ok();
+        threads.push((server_kind, thr_handle));
     }
 
-    Ok(())
+    // Wait for every server, but report the first one to fail so the
+    // exit status doesn't depend on which thread finished first.
+    let mut failure = None;
+    for (server_kind, handle) in threads {
+        let error = match handle.join() {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => (
+                ExitStatus::from(&e),
+                format!("{:?} server failed: {:?}", server_kind, e),
+            ),
+            Err(_) => (
+                ExitStatus::Internal,
+                format!("{:?} server stopped unexpectedly", server_kind),
+            ),
+        };
+        failure.get_or_insert(error);
+    }
+    if let Some(trace) = trace {
+        trace.flush().map_err(|e| {
+            (
+                ExitStatus::BridgeError,
+                format!("couldn't write trace: {}", e),
+            )
+        })?;
+    }
+    match failure {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
 }
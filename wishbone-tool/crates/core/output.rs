@@ -0,0 +1,1115 @@
+//! Results of memory operations, and how they are shown to the user.
+//! Operations return an `OperationResult` rather than printing, so the
+//! same result may be rendered as text, CSV or JSON.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config::{Config, ConfigError, MemoryRegions};
+
+/// What a memory operation did.
+#[derive(Debug, PartialEq, Clone)]
+pub enum OperationResult {
+    /// A single word was read
+    ReadValue { address: u32, value: u32 },
+
+    /// A single word was written
+    Wrote { address: u32, value: u32 },
+
+    /// A block of memory was read
+    RangeRead { start: u32, data: Vec<u8> },
+
+    /// Words were read one at a time, as `(address, value)` in the order
+    /// they were read
+    WordsRead(Vec<(u32, u32)>),
+
+    /// A block of memory was written
+    RangeWrote { start: u32, length: usize },
+
+    /// A value was written and then the original value put back
+    Pulsed {
+        address: u32,
+        value: u32,
+        original: u32,
+    },
+
+    /// Every readable register was read. Registers that couldn't be read
+    /// hold the error instead of a value.
+    Registers(Vec<RegisterValue>),
+
+    /// Registers were compared against a snapshot. Only registers that
+    /// differ, or that are missing on one side, are listed.
+    RegisterChanges(Vec<RegisterChange>),
+
+    /// One address was read repeatedly
+    Histogram { address: u32, histogram: Histogram },
+
+    /// Bytes were sent straight to the bridge
+    RawCommand { sent: Vec<u8>, received: Vec<u8> },
+
+    /// There was nothing to do because no address was given
+    NoOperation,
+}
+
+/// The value of a single named register.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RegisterValue {
+    pub name: String,
+    pub address: u32,
+    pub value: Result<u32, String>,
+}
+
+impl RegisterValue {
+    fn text(&self, cfg: &Config) -> String {
+        match &self.value {
+            Ok(value) => format!("{} = {}{:08x}", self.name, cfg.hex_prefix(), value),
+            Err(e) => format!("{} = error: {}", self.name, e),
+        }
+    }
+}
+
+/// A register whose value isn't what a snapshot recorded.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RegisterChange {
+    pub name: String,
+
+    /// The value in the snapshot, or `None` if the snapshot doesn't have
+    /// the register
+    pub old: Option<u32>,
+
+    /// The value read now, or `None` if the register map doesn't have it
+    pub new: Option<Result<u32, String>>,
+}
+
+impl RegisterChange {
+    fn old_text(&self, prefix: &str) -> String {
+        match self.old {
+            Some(value) => format!("{}{:08x}", prefix, value),
+            None => "(not in snapshot)".to_owned(),
+        }
+    }
+
+    fn new_text(&self, prefix: &str) -> String {
+        match &self.new {
+            Some(Ok(value)) => format!("{}{:08x}", prefix, value),
+            Some(Err(e)) => format!("error: {}", e),
+            None => "(not on device)".to_owned(),
+        }
+    }
+}
+
+/// The distribution of values seen over repeated reads of one address.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Histogram {
+    pub samples: usize,
+    pub min: u32,
+    pub max: u32,
+    pub mean: f64,
+
+    /// The population standard deviation
+    pub std_dev: f64,
+
+    /// Buckets in ascending order. If there are no more distinct values
+    /// than bins, each value gets its own bucket. Otherwise `min..=max` is
+    /// split into equal-width buckets, some of which may be empty.
+    pub buckets: Vec<Bucket>,
+}
+
+/// The number of samples that fell within `low..=high`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Bucket {
+    pub low: u32,
+    pub high: u32,
+    pub count: usize,
+}
+
+impl Histogram {
+    /// Summarize `values`, using at most `bins` buckets.
+    pub fn from_samples(values: &[u32], bins: u32) -> Histogram {
+        use std::collections::BTreeMap;
+
+        let min = values.iter().copied().min().unwrap_or(0);
+        let max = values.iter().copied().max().unwrap_or(0);
+        let n = values.len().max(1) as f64;
+        let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+        let variance = values
+            .iter()
+            .map(|&v| (v as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+
+        let mut counts = BTreeMap::new();
+        for &value in values {
+            *counts.entry(value).or_insert(0) += 1;
+        }
+        let buckets = if counts.len() <= bins as usize {
+            counts
+                .into_iter()
+                .map(|(value, count)| Bucket {
+                    low: value,
+                    high: value,
+                    count,
+                })
+                .collect()
+        } else {
+            let width = (u64::from(max - min) + 1).div_ceil(u64::from(bins));
+            let mut buckets: Vec<Bucket> = (0..u64::from(bins))
+                .map(|i| u64::from(min) + i * width)
+                .take_while(|&low| low <= u64::from(max))
+                .map(|low| Bucket {
+                    low: low as u32,
+                    high: (low + width - 1).min(u64::from(max)) as u32,
+                    count: 0,
+                })
+                .collect();
+            for (value, count) in counts {
+                buckets[(u64::from(value - min) / width) as usize].count += count;
+            }
+            buckets
+        };
+
+        Histogram {
+            samples: values.len(),
+            min,
+            max,
+            mean,
+            std_dev: variance.sqrt(),
+            buckets,
+        }
+    }
+}
+
+impl Bucket {
+    fn range_text(&self) -> String {
+        if self.low == self.high {
+            format!("{:08x}", self.low)
+        } else {
+            format!("{:08x}-{:08x}", self.low, self.high)
+        }
+    }
+}
+
+/// How results are printed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    /// Human-readable text. Range reads are written as raw binary unless
+    /// `--hexdump` is given.
+    Text,
+
+    /// Comma-separated values with a header row
+    Csv,
+
+    /// One JSON object per result
+    Json,
+
+    /// Newline-delimited JSON. Polling modes write one object per sample
+    /// and flush it straight away; other results are the same as `Json`.
+    Jsonl,
+}
+
+impl OutputFormat {
+    pub fn from_string(item: &str) -> Result<OutputFormat, ConfigError> {
+        match item {
+            "text" => Ok(OutputFormat::Text),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            unknown => Err(ConfigError::InvalidConfig(format!(
+                "unknown output format '{}'",
+                unknown
+            ))),
+        }
+    }
+}
+
+impl OperationResult {
+    /// Render the result in the format selected by `cfg`, ready to be
+    /// written to stdout.
+    pub fn render(&self, cfg: &Config) -> Vec<u8> {
+        if *self == OperationResult::NoOperation {
+            return format!(
+                "{}\n{}\n",
+                cfg.labelled("No operation and no address specified!"),
+                cfg.labelled(
+                    "Try specifying an address such as \"0x10000000\".  See --help for more information"
+                )
+            )
+            .into_bytes();
+        }
+        match cfg.output_format {
+            OutputFormat::Text => self.render_text(cfg),
+            OutputFormat::Csv => self.render_csv(cfg).into_bytes(),
+            OutputFormat::Json | OutputFormat::Jsonl => self.render_json(cfg).into_bytes(),
+        }
+    }
+
+    fn render_text(&self, cfg: &Config) -> Vec<u8> {
+        match self {
+            OperationResult::ReadValue { address, value } => {
+                let mut line = format!(
+                    "Value at {}: {:08x}",
+                    cfg.address_text((*address).into()),
+                    value
+                );
+                if let Some(scaled) = cfg.scaled(*value) {
+                    line.push_str(&format!(" ({})", scaled));
+                }
+                if cfg.human_readable {
+                    line.push_str(&format!(" ({})", human_size(*value)));
+                }
+                format!("{}\n", cfg.labelled(&line)).into_bytes()
+            }
+            OperationResult::RangeRead { start, data } if cfg.hexdump => {
+                let mut out = String::new();
+                for (i, byte) in data.iter().enumerate() {
+                    if (i % 16) == 0 {
+                        out.push('\n');
+                        out.push_str(&cfg.labelled(&format!(
+                            "{}: ",
+                            cfg.address_text(u64::from(*start) + i as u64)
+                        )));
+                    }
+                    out.push_str(&format!("{:02x} ", byte));
+                }
+                out.push('\n');
+                out.into_bytes()
+            }
+            OperationResult::RangeRead { start, data } if cfg.compact => {
+                let words: Vec<(u32, u32)> = data
+                    .chunks(4)
+                    .enumerate()
+                    .map(|(i, word)| {
+                        let mut bytes = [0; 4];
+                        bytes[..word.len()].copy_from_slice(word);
+                        (start.wrapping_add(i as u32 * 4), u32::from_le_bytes(bytes))
+                    })
+                    .collect();
+                compact_line(cfg, &words).into_bytes()
+            }
+            OperationResult::RangeRead { data, .. } => data.clone(),
+            OperationResult::WordsRead(words) if cfg.compact => {
+                compact_line(cfg, words).into_bytes()
+            }
+            OperationResult::WordsRead(words) => {
+                let mut out = String::new();
+                for (address, value) in words {
+                    let mut line = format!(
+                        "Value at {}: {:08x}",
+                        cfg.address_text((*address).into()),
+                        value
+                    );
+                    if cfg.human_readable {
+                        line.push_str(&format!(" ({})", human_size(*value)));
+                    }
+                    out.push_str(&cfg.labelled(&line));
+                    out.push('\n');
+                }
+                out.into_bytes()
+            }
+            OperationResult::Registers(registers) if cfg.compact => {
+                let pairs: Vec<String> = registers
+                    .iter()
+                    .map(|register| match &register.value {
+                        Ok(value) => {
+                            format!("{}={}{:08x}", register.name, cfg.hex_prefix(), value)
+                        }
+                        Err(_) => format!("{}=error", register.name),
+                    })
+                    .collect();
+                format!("{}\n", cfg.labelled(&pairs.join(" "))).into_bytes()
+            }
+            OperationResult::Registers(registers) if cfg.group_by_region => {
+                let mut out = String::new();
+                for (region, mut group) in
+                    group_by_region(registers, &cfg.csr_bases, &cfg.memory_regions)
+                {
+                    group.sort_by_key(|register| register.address);
+                    if cfg.reverse {
+                        group.reverse();
+                    }
+                    out.push_str(&cfg.labelled(&format!("{}:", region)));
+                    out.push('\n');
+                    for register in group {
+                        out.push_str(&cfg.labelled(&format!("  {}", register.text(cfg))));
+                        out.push('\n');
+                    }
+                }
+                out.into_bytes()
+            }
+            OperationResult::Registers(registers) => {
+                let mut out = String::new();
+                for register in registers {
+                    out.push_str(&cfg.labelled(&register.text(cfg)));
+                    out.push('\n');
+                }
+                out.into_bytes()
+            }
+            OperationResult::RegisterChanges(changes) => {
+                let mut out = String::new();
+                for change in changes {
+                    let line = format!(
+                        "{}: {} -> {}",
+                        change.name,
+                        change.old_text(cfg.hex_prefix()),
+                        change.new_text(cfg.hex_prefix())
+                    );
+                    out.push_str(&cfg.labelled(&line));
+                    out.push('\n');
+                }
+                out.into_bytes()
+            }
+            OperationResult::Histogram { address, histogram } => {
+                const BAR_WIDTH: usize = 50;
+                let mut out = cfg.labelled(&format!(
+                    "{} reads of {}: mean {:.3}, std dev {:.3}, min {:08x}, max {:08x}",
+                    histogram.samples,
+                    cfg.address_text((*address).into()),
+                    histogram.mean,
+                    histogram.std_dev,
+                    histogram.min,
+                    histogram.max
+                ));
+                out.push('\n');
+                let largest = histogram.buckets.iter().map(|b| b.count).max().unwrap_or(0);
+                for bucket in &histogram.buckets {
+                    let bar = (bucket.count * BAR_WIDTH).div_ceil(largest.max(1));
+                    let line = format!(
+                        "{:<17} {:>8} {}",
+                        bucket.range_text(),
+                        bucket.count,
+                        "#".repeat(bar)
+                    );
+                    out.push_str(&cfg.labelled(line.trim_end()));
+                    out.push('\n');
+                }
+                out.into_bytes()
+            }
+            OperationResult::RawCommand { received, .. } => {
+                let bytes: Vec<String> = received.iter().map(|b| format!("{:02x}", b)).collect();
+                format!(
+                    "{}\n",
+                    cfg.labelled(
+                        format!("Received {} bytes: {}", received.len(), bytes.join(" "))
+                            .trim_end()
+                    )
+                )
+                .into_bytes()
+            }
+            // Writes are silent, as they have always been
+            OperationResult::Wrote { .. }
+            | OperationResult::RangeWrote { .. }
+            | OperationResult::Pulsed { .. }
+            | OperationResult::NoOperation => vec![],
+        }
+    }
+
+    fn render_csv(&self, cfg: &Config) -> String {
+        let prefix = cfg.hex_prefix();
+        match self {
+            OperationResult::ReadValue { address, value } if cfg.scaled(*value).is_some() => {
+                format!(
+                    "address,value,scaled\n{prefix}{},{prefix}{:08x},{}\n",
+                    cfg.address_text((*address).into()),
+                    value,
+                    cfg.scaled(*value).unwrap()
+                )
+            }
+            OperationResult::ReadValue { address, value }
+            | OperationResult::Wrote { address, value } => {
+                format!(
+                    "address,value\n{prefix}{},{prefix}{:08x}\n",
+                    cfg.address_text((*address).into()),
+                    value
+                )
+            }
+            OperationResult::RangeRead { start, data } => {
+                let mut out = "address,value\n".to_owned();
+                for (i, byte) in data.iter().enumerate() {
+                    out.push_str(&format!(
+                        "{prefix}{},{prefix}{:02x}\n",
+                        cfg.address_text(u64::from(*start) + i as u64),
+                        byte
+                    ));
+                }
+                out
+            }
+            OperationResult::WordsRead(words) => {
+                let mut out = "address,value\n".to_owned();
+                for (address, value) in words {
+                    out.push_str(&format!(
+                        "{prefix}{},{prefix}{:08x}\n",
+                        cfg.address_text((*address).into()),
+                        value
+                    ));
+                }
+                out
+            }
+            OperationResult::RangeWrote { start, length } => {
+                format!(
+                    "address,length\n{prefix}{},{}\n",
+                    cfg.address_text((*start).into()),
+                    length
+                )
+            }
+            OperationResult::Pulsed {
+                address,
+                value,
+                original,
+            } => format!(
+                "address,value,original\n{prefix}{},{prefix}{:08x},{prefix}{:08x}\n",
+                cfg.address_text((*address).into()),
+                value,
+                original
+            ),
+            OperationResult::Registers(registers) => {
+                let mut out = "name,address,value,error\n".to_owned();
+                for register in registers {
+                    match &register.value {
+                        Ok(value) => out.push_str(&format!(
+                            "{},{prefix}{},{prefix}{:08x},\n",
+                            register.name,
+                            cfg.address_text(register.address.into()),
+                            value
+                        )),
+                        Err(e) => out.push_str(&format!(
+                            "{},{prefix}{},,\"{}\"\n",
+                            register.name,
+                            cfg.address_text(register.address.into()),
+                            e.replace('"', "\"\"")
+                        )),
+                    }
+                }
+                out
+            }
+            OperationResult::RegisterChanges(changes) => {
+                let mut out = "name,old,new,error\n".to_owned();
+                for change in changes {
+                    let old = change.old.map(|v| format!("{prefix}{:08x}", v));
+                    let (new, error) = match &change.new {
+                        Some(Ok(value)) => (format!("{prefix}{:08x}", value), String::new()),
+                        Some(Err(e)) => (String::new(), format!("\"{}\"", e.replace('"', "\"\""))),
+                        None => (String::new(), String::new()),
+                    };
+                    out.push_str(&format!(
+                        "{},{},{},{}\n",
+                        change.name,
+                        old.unwrap_or_default(),
+                        new,
+                        error
+                    ));
+                }
+                out
+            }
+            OperationResult::Histogram { histogram, .. } => {
+                let mut out = "low,high,count\n".to_owned();
+                for bucket in &histogram.buckets {
+                    out.push_str(&format!(
+                        "{prefix}{:08x},{prefix}{:08x},{}\n",
+                        bucket.low, bucket.high, bucket.count
+                    ));
+                }
+                out
+            }
+            OperationResult::RawCommand { sent, received } => {
+                format!(
+                    "sent,received\n{},{}\n",
+                    hex_string(sent),
+                    hex_string(received)
+                )
+            }
+            OperationResult::NoOperation => String::new(),
+        }
+    }
+
+    fn render_json(&self, cfg: &Config) -> String {
+        let fields = match self {
+            OperationResult::ReadValue { address, value } => match cfg.scaled(*value) {
+                Some(scaled) => format!(
+                    "\"operation\":\"read\",\"address\":{},\"value\":{},\"scaled\":{}",
+                    address, value, scaled
+                ),
+                None => format!(
+                    "\"operation\":\"read\",\"address\":{},\"value\":{}",
+                    address, value
+                ),
+            },
+            OperationResult::Wrote { address, value } => format!(
+                "\"operation\":\"write\",\"address\":{},\"value\":{}",
+                address, value
+            ),
+            OperationResult::RangeRead { start, data } => format!(
+                "\"operation\":\"range_read\",\"start\":{},\"data\":[{}]",
+                start,
+                data.iter()
+                    .map(|b| b.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            OperationResult::WordsRead(words) => format!(
+                "\"operation\":\"words_read\",\"words\":[{}]",
+                words
+                    .iter()
+                    .map(|(address, value)| format!(
+                        "{{\"address\":{},\"value\":{}}}",
+                        address, value
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            OperationResult::RangeWrote { start, length } => format!(
+                "\"operation\":\"range_write\",\"start\":{},\"length\":{}",
+                start, length
+            ),
+            OperationResult::Pulsed {
+                address,
+                value,
+                original,
+            } => format!(
+                "\"operation\":\"pulse\",\"address\":{},\"value\":{},\"original\":{}",
+                address, value, original
+            ),
+            OperationResult::Registers(registers) => format!(
+                "\"operation\":\"registers\",\"registers\":[{}]",
+                registers
+                    .iter()
+                    .map(|register| {
+                        let result = match &register.value {
+                            Ok(value) => format!("\"value\":{}", value),
+                            Err(e) => format!("\"error\":{}", json_string(e)),
+                        };
+                        format!(
+                            "{{\"name\":{},\"address\":{},{}}}",
+                            json_string(&register.name),
+                            register.address,
+                            result
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            OperationResult::RegisterChanges(changes) => format!(
+                "\"operation\":\"compare_registers\",\"changes\":[{}]",
+                changes
+                    .iter()
+                    .map(|change| {
+                        let old = match change.old {
+                            Some(value) => value.to_string(),
+                            None => "null".to_owned(),
+                        };
+                        let new = match &change.new {
+                            Some(Ok(value)) => format!("\"new\":{}", value),
+                            Some(Err(e)) => format!("\"new\":null,\"error\":{}", json_string(e)),
+                            None => "\"new\":null".to_owned(),
+                        };
+                        format!(
+                            "{{\"name\":{},\"old\":{},{}}}",
+                            json_string(&change.name),
+                            old,
+                            new
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            OperationResult::Histogram { address, histogram } => format!(
+                "\"operation\":\"histogram\",\"address\":{},\"samples\":{},\"min\":{},\"max\":{},\"mean\":{},\"std_dev\":{},\"buckets\":[{}]",
+                address,
+                histogram.samples,
+                histogram.min,
+                histogram.max,
+                histogram.mean,
+                histogram.std_dev,
+                histogram
+                    .buckets
+                    .iter()
+                    .map(|b| format!(
+                        "{{\"low\":{},\"high\":{},\"count\":{}}}",
+                        b.low, b.high, b.count
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            OperationResult::RawCommand { sent, received } => format!(
+                "\"operation\":\"raw_command\",\"sent\":[{}],\"received\":[{}]",
+                sent.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(","),
+                received.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(",")
+            ),
+            OperationResult::NoOperation => "\"operation\":\"none\"".to_owned(),
+        };
+        match &cfg.label {
+            Some(label) => format!("{{\"label\":{},{}}}\n", json_string(label), fields),
+            None => format!("{{{}}}\n", fields),
+        }
+    }
+}
+
+/// Sort `registers` into the CSR peripherals and memory regions that hold
+/// them, in order of their lowest register. A register goes under the nearest `csr_base`
+/// peripheral at or below it whose name it starts with, as `ctrl_reset`
+/// goes under `ctrl`. Failing that it goes in the smallest memory region
+/// around it, and registers outside every region come last, under
+/// "unmapped".
+fn group_by_region<'a>(
+    registers: &'a [RegisterValue],
+    csr_bases: &'a HashMap<String, u32>,
+    regions: &'a MemoryRegions,
+) -> Vec<(&'a str, Vec<&'a RegisterValue>)> {
+    let mut groups: Vec<(&str, Vec<&RegisterValue>)> = vec![];
+    let mut unmapped = vec![];
+    for register in registers {
+        let name = register.name.to_lowercase();
+        let address = u64::from(register.address);
+        let peripheral = csr_bases
+            .iter()
+            .filter(|(peripheral, base)| {
+                **base <= register.address && name.starts_with(&format!("{}_", peripheral))
+            })
+            .max_by_key(|(peripheral, base)| (**base, peripheral.len()))
+            .map(|(peripheral, _)| peripheral.as_str());
+        let region = || {
+            regions
+                .iter()
+                .filter(|(_, r)| {
+                    address >= u64::from(r.base)
+                        && address < u64::from(r.base) + u64::from(r.length)
+                })
+                .min_by_key(|(region, r)| (r.length, *region))
+                .map(|(region, _)| region.as_str())
+        };
+        match peripheral.or_else(region) {
+            Some(group_name) => match groups.iter_mut().find(|(g, _)| *g == group_name) {
+                Some((_, group)) => group.push(register),
+                None => groups.push((group_name, vec![register])),
+            },
+            None => unmapped.push(register),
+        }
+    }
+    groups.sort_by_key(|(_, group)| group.iter().map(|r| r.address).min());
+    if !unmapped.is_empty() {
+        groups.push(("unmapped", unmapped));
+    }
+    groups
+}
+
+/// The `--compact` form of several words, e.g. `0x00001000=0x00000001 0x00001004=0x00000002`.
+fn compact_line(cfg: &Config, words: &[(u32, u32)]) -> String {
+    let prefix = cfg.hex_prefix();
+    let pairs: Vec<String> = words
+        .iter()
+        .map(|(address, value)| {
+            format!(
+                "{prefix}{}={prefix}{:08x}",
+                cfg.address_text((*address).into()),
+                value
+            )
+        })
+        .collect();
+    format!("{}\n", cfg.labelled(&pairs.join(" ")))
+}
+
+/// One line of `jsonl` output for a value sampled at `timestamp`, which is
+/// the time since the Unix epoch.
+pub fn jsonl_sample(cfg: &Config, timestamp: Duration, address: u32, value: u32) -> String {
+    let fields = format!(
+        "\"ts\":{}.{:03},\"address\":{},\"value\":{}",
+        timestamp.as_secs(),
+        timestamp.subsec_millis(),
+        address,
+        value
+    );
+    match &cfg.label {
+        Some(label) => format!("{{\"label\":{},{}}}\n", json_string(label), fields),
+        None => format!("{{{}}}\n", fields),
+    }
+}
+
+/// `data` as hex digits with no separators.
+fn hex_string(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `bytes` as a size in the largest binary unit that keeps it at least 1,
+/// such as `1.5 MiB`, with at most two decimal places.
+pub fn human_size(bytes: u32) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = f64::from(bytes) / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    let number = format!("{:.2}", size);
+    let number = number.trim_end_matches('0').trim_end_matches('.');
+    format!("{} {}", number, UNITS[unit])
+}
+
+/// Quote `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::MemoryRegion;
+
+    fn render(result: &OperationResult, cfg: &Config) -> String {
+        String::from_utf8(result.render(cfg)).unwrap()
+    }
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!(human_size(0x18_0000), "1.5 MiB");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(4096), "4 KiB");
+        assert_eq!(human_size(u32::MAX), "4 GiB");
+
+        let read = OperationResult::ReadValue {
+            address: 0x10,
+            value: 0x18_0000,
+        };
+        let cfg = Config {
+            human_readable: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&read, &cfg),
+            "Value at 00000010: 00180000 (1.5 MiB)\n"
+        );
+        let cfg = Config {
+            output_format: OutputFormat::Csv,
+            ..cfg
+        };
+        assert_eq!(
+            render(&read, &cfg),
+            "address,value\n0x00000010,0x00180000\n"
+        );
+    }
+
+    #[test]
+    fn registers_are_grouped_by_region() {
+        let register = |name: &str, address, value| RegisterValue {
+            name: name.to_owned(),
+            address,
+            value: Ok(value),
+        };
+        let registers = OperationResult::Registers(vec![
+            register("uart_rxtx", 0xe000_1800, 0x41),
+            register("ctrl_scratch", 0xe000_0004, 0x1234_5678),
+            register("sram_canary", 0x1000_0000, 0xcafe),
+            register("ctrl_reset", 0xe000_0000, 0),
+            register("timer0_load", 0xe000_2000, 0x100),
+            register("stray", 0x8000_0000, 1),
+        ]);
+        let mut memory_regions = MemoryRegions::new();
+        memory_regions.insert(
+            "csr".to_owned(),
+            MemoryRegion {
+                base: 0xe000_0000,
+                length: 0x1_0000,
+            },
+        );
+        memory_regions.insert(
+            "sram".to_owned(),
+            MemoryRegion {
+                base: 0x1000_0000,
+                length: 0x2000,
+            },
+        );
+        let cfg = Config {
+            group_by_region: true,
+            memory_regions,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&registers, &cfg),
+            "sram:\n  sram_canary = 0x0000cafe\n\
+             csr:\n  ctrl_reset = 0x00000000\n  ctrl_scratch = 0x12345678\n  uart_rxtx = 0x00000041\n  timer0_load = 0x00000100\n\
+             unmapped:\n  stray = 0x00000001\n"
+        );
+
+        // CSRs go under their peripherals, and a CSR with no peripheral
+        // listed falls back to the region
+        let mut csr_bases = HashMap::new();
+        csr_bases.insert("ctrl".to_owned(), 0xe000_0000);
+        csr_bases.insert("uart".to_owned(), 0xe000_1800);
+        let cfg = Config { csr_bases, ..cfg };
+        assert_eq!(
+            render(&registers, &cfg),
+            "sram:\n  sram_canary = 0x0000cafe\n\
+             ctrl:\n  ctrl_reset = 0x00000000\n  ctrl_scratch = 0x12345678\n\
+             uart:\n  uart_rxtx = 0x00000041\n\
+             csr:\n  timer0_load = 0x00000100\n\
+             unmapped:\n  stray = 0x00000001\n"
+        );
+    }
+
+    #[test]
+    fn compact_puts_reads_on_one_line() {
+        let cfg = Config {
+            compact: true,
+            ..Default::default()
+        };
+        let words = OperationResult::WordsRead(vec![(0x1008, 3), (0x1004, 2), (0x1000, 1)]);
+        assert_eq!(
+            render(&words, &cfg),
+            "0x00001008=0x00000003 0x00001004=0x00000002 0x00001000=0x00000001\n"
+        );
+        let range = OperationResult::RangeRead {
+            start: 0x1000,
+            data: vec![1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0],
+        };
+        assert_eq!(
+            render(&range, &cfg),
+            "0x00001000=0x00000001 0x00001004=0x00000002 0x00001008=0x00000003\n"
+        );
+        let cfg = Config {
+            hex_prefix: false,
+            ..cfg
+        };
+        assert_eq!(
+            render(&range, &cfg),
+            "00001000=00000001 00001004=00000002 00001008=00000003\n"
+        );
+    }
+
+    #[test]
+    fn text_matches_previous_output() {
+        let cfg = Config::default();
+        let read = OperationResult::ReadValue {
+            address: 0x1000_0000,
+            value: 0xcafe,
+        };
+        assert_eq!(render(&read, &cfg), "Value at 10000000: 0000cafe\n");
+
+        let range = OperationResult::RangeRead {
+            start: 0x10,
+            data: vec![1, 2, 3],
+        };
+        assert_eq!(range.render(&cfg), vec![1, 2, 3]);
+        let cfg = Config {
+            hexdump: true,
+            ..cfg
+        };
+        assert_eq!(render(&range, &cfg), "\n00000010: 01 02 03 \n");
+    }
+
+    #[test]
+    fn addresses_are_padded_to_width() {
+        let range = OperationResult::RangeRead {
+            start: 0xfff8,
+            data: (0..24).collect(),
+        };
+        let cfg = Config {
+            hexdump: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&range, &cfg),
+            "\n0000fff8: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f \n\
+             00010008: 10 11 12 13 14 15 16 17 \n"
+        );
+        let cfg = Config {
+            address_width: Some(8),
+            ..cfg
+        };
+        assert_eq!(
+            render(&range, &cfg),
+            "\n000000000000fff8: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f \n\
+             0000000000010008: 10 11 12 13 14 15 16 17 \n"
+        );
+        let cfg = Config {
+            address_width: Some(2),
+            output_format: OutputFormat::Csv,
+            ..cfg
+        };
+        let read = OperationResult::ReadValue {
+            address: 0x10,
+            value: 1,
+        };
+        assert_eq!(render(&read, &cfg), "address,value\n0x0010,0x00000001\n");
+    }
+
+    #[test]
+    fn hex_prefix_can_be_left_out() {
+        let registers = OperationResult::Registers(vec![RegisterValue {
+            name: "ctrl_scratch".to_owned(),
+            address: 0xe000_0000,
+            value: Ok(0x1234),
+        }]);
+        let cfg = Config {
+            output_format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&registers, &cfg),
+            "name,address,value,error\nctrl_scratch,0xe0000000,0x00001234,\n"
+        );
+        let cfg = Config {
+            hex_prefix: false,
+            ..cfg
+        };
+        assert_eq!(
+            render(&registers, &cfg),
+            "name,address,value,error\nctrl_scratch,e0000000,00001234,\n"
+        );
+        let text = Config {
+            output_format: OutputFormat::Text,
+            ..cfg.clone()
+        };
+        assert_eq!(render(&registers, &text), "ctrl_scratch = 00001234\n");
+        let json = Config {
+            output_format: OutputFormat::Json,
+            ..cfg
+        };
+        assert!(render(&registers, &json).contains("\"value\":4660"));
+    }
+
+    #[test]
+    fn csv_has_header_row() {
+        let cfg = Config {
+            output_format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        let range = OperationResult::RangeRead {
+            start: 0x10,
+            data: vec![0xaa, 0xbb],
+        };
+        assert_eq!(
+            render(&range, &cfg),
+            "address,value\n0x00000010,0xaa\n0x00000011,0xbb\n"
+        );
+    }
+
+    #[test]
+    fn json_includes_label() {
+        let cfg = Config {
+            output_format: OutputFormat::Json,
+            label: Some("board \"a\"".to_owned()),
+            ..Default::default()
+        };
+        let wrote = OperationResult::Wrote {
+            address: 16,
+            value: 1,
+        };
+        assert_eq!(
+            render(&wrote, &cfg),
+            "{\"label\":\"board \\\"a\\\"\",\"operation\":\"write\",\"address\":16,\"value\":1}\n"
+        );
+    }
+
+    #[test]
+    fn register_errors_are_shown_inline() {
+        let result = OperationResult::Registers(vec![
+            RegisterValue {
+                name: "ctrl_scratch".to_owned(),
+                address: 0xe000_0000,
+                value: Ok(0x1234),
+            },
+            RegisterValue {
+                name: "ctrl_bus_errors".to_owned(),
+                address: 0xe000_0004,
+                value: Err("Timeout".to_owned()),
+            },
+        ]);
+        assert_eq!(
+            render(&result, &Config::default()),
+            "ctrl_scratch = 0x00001234\nctrl_bus_errors = error: Timeout\n"
+        );
+    }
+
+    #[test]
+    fn histogram_of_known_samples() {
+        let bucket = |low, high, count| Bucket { low, high, count };
+        let samples = [2, 4, 4, 4, 5, 5, 7, 9];
+
+        let histogram = Histogram::from_samples(&samples, 16);
+        assert_eq!((histogram.samples, histogram.min, histogram.max), (8, 2, 9));
+        assert_eq!(histogram.mean, 5.0);
+        assert_eq!(histogram.std_dev, 2.0);
+        assert_eq!(
+            histogram.buckets,
+            vec![
+                bucket(2, 2, 1),
+                bucket(4, 4, 3),
+                bucket(5, 5, 2),
+                bucket(7, 7, 1),
+                bucket(9, 9, 1),
+            ]
+        );
+
+        let histogram = Histogram::from_samples(&samples, 3);
+        assert_eq!(
+            histogram.buckets,
+            vec![bucket(2, 4, 4), bucket(5, 7, 3), bucket(8, 9, 1)]
+        );
+        let result = OperationResult::Histogram {
+            address: 0x10,
+            histogram,
+        };
+        assert_eq!(
+            render(&result, &Config::default()),
+            "8 reads of 00000010: mean 5.000, std dev 2.000, min 00000002, max 00000009\n\
+             00000002-00000004        4 ##################################################\n\
+             00000005-00000007        3 ######################################\n\
+             00000008-00000009        1 #############\n"
+        );
+    }
+
+    #[test]
+    fn reads_can_be_scaled() {
+        let read = OperationResult::ReadValue {
+            address: 0x10,
+            value: 2048,
+        };
+        let scale = Config {
+            read_scale: Some(3.3 / 4096.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&read, &scale),
+            "Value at 00000010: 00000800 (1.65)\n"
+        );
+
+        let offset = Config {
+            read_offset: Some(-48.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&read, &offset),
+            "Value at 00000010: 00000800 (2000)\n"
+        );
+
+        let both = Config {
+            read_scale: Some(0.5),
+            read_offset: Some(0.25),
+            output_format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&read, &both),
+            "address,value,scaled\n0x00000010,0x00000800,1024.25\n"
+        );
+        let both = Config {
+            output_format: OutputFormat::Json,
+            ..both
+        };
+        assert_eq!(
+            render(&read, &both),
+            "{\"operation\":\"read\",\"address\":16,\"value\":2048,\"scaled\":1024.25}\n"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! A file-like view of one memory region, so tools without a Wishbone
+//! client can fetch or replace its contents over TCP.
+//!
+//! Each request is a single line, and every response starts with a line
+//! that is either `OK <n>` or `ERR <reason>`:
+//!
+//!     SIZE                      -> OK <region length>
+//!     READ <offset> <length>    -> OK <length>, followed by <length> bytes
+//!     WRITE <offset> <length>   -> the client then sends <length> bytes,
+//!                                  and the reply is OK <length>
+//!
+//! Offsets and lengths are relative to the start of the region and may be
+//! given in decimal or hex. They must be whole words, and a length can't be
+//! zero. A request that would touch anything outside the region is refused.
+
+use std::io::{BufRead, Write};
+
+use wishbone_bridge::Bridge;
+
+use super::{ServerError, Shutdown};
+use crate::config::{check_write_allowed, parse_u32, Config, MemoryRegion};
+
+#[derive(Debug, PartialEq)]
+pub enum FileRequest {
+    Size,
+    Read { offset: u32, length: u32 },
+    Write { offset: u32, length: u32 },
+}
+
+/// Parse one request line, checking it against a region of `region_length` bytes.
+pub fn parse_request(line: &str, region_length: u32) -> Result<FileRequest, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let range = |offset: &str, length: &str| -> Result<(u32, u32), String> {
+        let offset = parse_u32(offset).map_err(|e| e.to_string())?;
+        let length = parse_u32(length).map_err(|e| e.to_string())?;
+        if length == 0 {
+            return Err("length must not be zero".to_owned());
+        }
+        if offset % 4 != 0 || length % 4 != 0 {
+            return Err(format!(
+                "offset {} and length {} must both be multiples of 4",
+                offset, length
+            ));
+        }
+        match offset.checked_add(length) {
+            Some(end) if end <= region_length => Ok((offset, length)),
+            _ => Err(format!(
+                "{} bytes at offset {} is outside the {} byte region",
+                length, offset, region_length
+            )),
+        }
+    };
+    match words.as_slice() {
+        ["SIZE"] => Ok(FileRequest::Size),
+        ["READ", offset, length] => {
+            let (offset, length) = range(offset, length)?;
+            Ok(FileRequest::Read { offset, length })
+        }
+        ["WRITE", offset, length] => {
+            let (offset, length) = range(offset, length)?;
+            Ok(FileRequest::Write { offset, length })
+        }
+        _ => Err(format!("unrecognized request \"{}\"", line.trim())),
+    }
+}
+
+/// Answer requests from `reader` until the client closes the connection,
+/// or until a request arrives after `shutdown` was requested. Writes are
+/// subject to `--safe` like any other.
+pub fn serve<R: BufRead, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    cfg: &Config,
+    bridge: &Bridge,
+    region: &MemoryRegion,
+    shutdown: &Shutdown,
+) -> Result<(), ServerError> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let _request = match shutdown.begin() {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        let request = match parse_request(&line, region.length) {
+            Ok(request) => request,
+            Err(reason) => {
+                writeln!(writer, "ERR {}", reason)?;
+                continue;
+            }
+        };
+        match request {
+            FileRequest::Size => writeln!(writer, "OK {}", region.length)?,
+            FileRequest::Read { offset, length } => {
+                match bridge.burst_read(region.base + offset, length) {
+                    Ok(data) => {
+                        writeln!(writer, "OK {}", data.len())?;
+                        writer.write_all(&data)?;
+                    }
+                    Err(e) => writeln!(writer, "ERR {}", e)?,
+                }
+            }
+            FileRequest::Write { offset, length } => {
+                let mut data = vec![0; length as usize];
+                reader.read_exact(&mut data)?;
+                if let Err(e) = check_write_allowed(cfg, region.base + offset, length) {
+                    writeln!(writer, "ERR {}", e)?;
+                    writer.flush()?;
+                    continue;
+                }
+                match bridge.burst_write(region.base + offset, &data) {
+                    Ok(()) => writeln!(writer, "OK {}", length)?,
+                    Err(e) => writeln!(writer, "ERR {}", e)?,
+                }
+            }
+        }
+        writer.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn requests_outside_region_are_refused() {
+        assert_eq!(
+            parse_request("READ 0x10 16\n", 32),
+            Ok(FileRequest::Read {
+                offset: 16,
+                length: 16
+            })
+        );
+        assert!(parse_request("READ 0x10 20\n", 32).is_err());
+        assert!(parse_request("WRITE 36 4\n", 32).is_err());
+        assert!(parse_request("READ 0xfffffffc 8\n", 32).is_err());
+        assert!(parse_request("DELETE\n", 32).is_err());
+    }
+
+    #[test]
+    fn requests_must_be_whole_words() {
+        assert_eq!(
+            parse_request("READ 2 4\n", 32),
+            Err("offset 2 and length 4 must both be multiples of 4".to_owned())
+        );
+        assert_eq!(
+            parse_request("WRITE 4 3\n", 32),
+            Err("offset 4 and length 3 must both be multiples of 4".to_owned())
+        );
+    }
+
+    #[test]
+    fn empty_requests_are_refused() {
+        assert_eq!(
+            parse_request("READ 0 0\n", 32),
+            Err("length must not be zero".to_owned())
+        );
+        assert_eq!(
+            parse_request("WRITE 4 0\n", 32),
+            Err("length must not be zero".to_owned())
+        );
+    }
+
+    #[test]
+    fn region_can_be_replaced_and_fetched() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let region = MemoryRegion {
+            base: 0x1000_0000,
+            length: 8,
+        };
+        let requests = b"WRITE 4 3\nWRITE 4 4\nabcdREAD 0 8\nSIZE\nREAD 4 8\n".to_vec();
+        let mut replies = vec![];
+        serve(
+            Cursor::new(requests),
+            &mut replies,
+            &Config::default(),
+            &bridge,
+            &region,
+            &Shutdown::new(),
+        )
+        .unwrap();
+
+        assert_eq!(bridge.peek(0x1000_0004).unwrap(), 0x6463_6261);
+        let mut expected = b"ERR offset 4 and length 3 must both be multiples of 4\n".to_vec();
+        expected.extend_from_slice(b"OK 4\nOK 8\n\0\0\0\0abcdOK 8\n");
+        expected.extend_from_slice(b"ERR 8 bytes at offset 4 is outside the 8 byte region\n");
+        assert_eq!(
+            String::from_utf8_lossy(&replies),
+            String::from_utf8_lossy(&expected)
+        );
+    }
+
+    #[test]
+    fn requests_after_shutdown_are_dropped() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let region = MemoryRegion {
+            base: 0x1000_0000,
+            length: 8,
+        };
+        let shutdown = Shutdown::new();
+        shutdown.request();
+        let mut replies = vec![];
+        serve(
+            Cursor::new(b"WRITE 0 4\nabcd".to_vec()),
+            &mut replies,
+            &Config::default(),
+            &bridge,
+            &region,
+            &shutdown,
+        )
+        .unwrap();
+        assert!(replies.is_empty());
+        assert_eq!(bridge.peek(0x1000_0000).unwrap(), 0);
+    }
+}
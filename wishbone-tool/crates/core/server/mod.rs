@@ -1,19 +1,27 @@
-use crate::config::{Config, ConfigError};
+use crate::config::{parse_u32, substitute_defines, Config, ConfigError, FillPattern};
 use crate::gdb;
+use crate::output::{
+    jsonl_sample, Histogram, OperationResult, OutputFormat, RegisterChange, RegisterValue,
+};
 use crate::riscv;
+use crate::watch::WatchExpr;
 use crate::wishbone;
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use log::{error, info};
+use log::{debug, error, info, warn};
 use rand::prelude::*;
-use wishbone_bridge::{Bridge, BridgeError};
+use wishbone_bridge::{Access, Bridge, BridgeCapabilities, BridgeError, Transaction};
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io;
-use std::net::TcpListener;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+mod file;
 mod utra;
 use indicatif::{ProgressBar, ProgressStyle};
 use utra::*;
@@ -43,6 +51,15 @@ pub enum ServerKind {
 
     /// Flash programming
     FlashProgram,
+
+    /// Record a register's value over time
+    Logic,
+
+    /// Play back a transaction log
+    Replay,
+
+    /// Serve a memory region as a file
+    FileService,
 }
 
 #[derive(Debug)]
@@ -65,6 +82,59 @@ pub enum ServerError {
         u32, // expected
         u32, // observed
     ),
+
+    /// A line of a replay log couldn't be understood
+    ReplayParseError(
+        usize, // line number
+        String,
+    ),
+
+    /// Two reads of the same address disagreed on every attempt
+    ReadMismatch(
+        u32, // address
+        u32, // first read
+        u32, // second read
+    ),
+
+    /// A line of a register snapshot couldn't be understood
+    SnapshotParseError(
+        usize, // line number
+        String,
+    ),
+
+    /// The CRC32 of a loaded region didn't match that of the file
+    ChecksumMismatch(
+        u32, // file
+        u32, // read back
+    ),
+
+    /// A random test with `--abort-after-failures` saw at least one failure
+    RandomTestFailed(
+        u32, // failures
+        u32, // loops run
+    ),
+
+    /// Words that `--memtest` read back differently from how they were written
+    MemtestFailed(
+        u32, // failures
+    ),
+
+    /// A random test or memtest with `--min-success-rate` succeeded less
+    /// often than required
+    SuccessRateTooLow(
+        f64, // percent that succeeded
+        f64, // percent required
+    ),
+
+    /// The bridge in use can't do what was asked of it
+    Unsupported(String),
+
+    /// The word read didn't match `--expect`
+    UnexpectedValue(
+        u32, // address
+        u32, // expected
+        u32, // read
+    ),
 }
 
 impl std::convert::From<io::Error> for ServerError {
@@ -100,19 +170,54 @@ impl std::convert::From<terminal::error::ErrorKind> for ServerError {
 }
 
 impl ServerKind {
-    pub fn from_string(item: &str) -> Result<ServerKind, ConfigError> {
-        match item {
-            "gdb" => Ok(ServerKind::GDB),
-            "wishbone" => Ok(ServerKind::Wishbone),
-            "random-test" => Ok(ServerKind::RandomTest),
-            "load-file" => Ok(ServerKind::LoadFile),
-            "terminal" => Ok(ServerKind::Terminal),
-            "messible" => Ok(ServerKind::Messible),
-            "memory-access" => Ok(ServerKind::MemoryAccess),
-            "flash-program" => Ok(ServerKind::FlashProgram),
-            unknown => Err(ConfigError::UnknownServerKind(unknown.to_owned())),
+    /// Every kind of server, in the order `--list-server-kinds` shows them.
+    pub const ALL: &'static [ServerKind] = &[
+        ServerKind::GDB,
+        ServerKind::Wishbone,
+        ServerKind::RandomTest,
+        ServerKind::LoadFile,
+        ServerKind::Terminal,
+        ServerKind::Messible,
+        ServerKind::MemoryAccess,
+        ServerKind::FlashProgram,
+        ServerKind::Logic,
+        ServerKind::Replay,
+        ServerKind::FileService,
+    ];
+
+    /// The name used to select this server with `--server`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ServerKind::GDB => "gdb",
+            ServerKind::Wishbone => "wishbone",
+            ServerKind::RandomTest => "random-test",
+            ServerKind::LoadFile => "load-file",
+            ServerKind::Terminal => "terminal",
+            ServerKind::Messible => "messible",
+            ServerKind::MemoryAccess => "memory-access",
+            ServerKind::FlashProgram => "flash-program",
+            ServerKind::Logic => "logic",
+            ServerKind::Replay => "replay",
+            ServerKind::FileService => "file",
         }
     }
+
+    /// Whether this server waits for clients to connect, and so may leave
+    /// the bridge idle for long periods.
+    pub fn listens(self) -> bool {
+        matches!(
+            self,
+            ServerKind::GDB | ServerKind::Wishbone | ServerKind::FileService
+        )
+    }
+
+    pub fn from_string(item: &str) -> Result<ServerKind, ConfigError> {
+        ServerKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.name() == item)
+            .ok_or_else(|| ConfigError::UnknownServerKind(item.to_owned()))
+    }
 }
 
 /// Poll the Messible at the address specified.
@@ -168,6 +273,174 @@ fn poll_uart(uart_address: u32, bridge: &Bridge) -> Result<bool, BridgeError> {
     Ok(bridge.peek(uart_address)? == 0)
 }
 
+/// Counts the clients connected to any of the TCP servers, so that
+/// `--max-clients` applies across all of them.
+struct ClientCounter {
+    active: AtomicUsize,
+}
+
+/// A connected client. The client is counted until this is dropped.
+struct ClientSlot<'a> {
+    counter: &'a ClientCounter,
+}
+
+static CLIENTS: ClientCounter = ClientCounter::new();
+
+impl ClientCounter {
+    const fn new() -> ClientCounter {
+        ClientCounter {
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Claim a slot for a new client, or return `None` if `max` clients
+    /// are already connected. A `max` of 0 means there is no limit.
+    fn try_acquire(&self, max: u32) -> Option<ClientSlot<'_>> {
+        let max = max as usize;
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                if max != 0 && active >= max {
+                    None
+                } else {
+                    Some(active + 1)
+                }
+            })
+            .ok()
+            .map(|_| ClientSlot { counter: self })
+    }
+}
+
+impl Drop for ClientSlot<'_> {
+    fn drop(&mut self) {
+        self.counter.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Lets the TCP servers shut down without cutting off a request halfway,
+/// which could leave a register half written. After the first Ctrl-C no
+/// new clients or requests are taken on, and `wait_for_drain()` returns
+/// once the requests that were already running have finished. A second
+/// Ctrl-C exits straight away.
+#[derive(Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    in_flight: AtomicUsize,
+
+    /// How many times Ctrl-C has been pressed
+    signals: Arc<AtomicUsize>,
+}
+
+/// How often a listening server checks whether it should stop while it
+/// waits for a client.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A request that is being carried out. Shutdown waits until this is dropped.
+pub struct InFlight<'a> {
+    shutdown: &'a Shutdown,
+}
+
+static SHUTDOWN: OnceLock<Shutdown> = OnceLock::new();
+
+/// The shutdown state shared by every server.
+pub fn shutdown() -> &'static Shutdown {
+    SHUTDOWN.get_or_init(Shutdown::new)
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            requested: Arc::new(AtomicBool::new(false)),
+            in_flight: AtomicUsize::new(0),
+            signals: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Request a shutdown on the first Ctrl-C, and exit on the second.
+    pub fn install(&self) -> io::Result<()> {
+        let signals = self.signals.clone();
+        // Only atomics are touched, which is safe inside a signal handler.
+        // `wait_for_drain()` does the exiting once a second signal is seen.
+        unsafe {
+            signal_hook::register(signal_hook::SIGINT, move || {
+                signals.fetch_add(1, Ordering::SeqCst);
+            })?;
+        }
+        signal_hook::flag::register(signal_hook::SIGINT, self.requested.clone())?;
+        Ok(())
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Mark the start of a request, or return `None` if a shutdown has been
+    /// requested and the request shouldn't be started.
+    pub fn begin(&self) -> Option<InFlight<'_>> {
+        // Counted before checking, so `wait_for_drain()` can't miss a
+        // request that starts while the flag is being set.
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let request = InFlight { shutdown: self };
+        if self.is_requested() {
+            return None;
+        }
+        Some(request)
+    }
+
+    /// Wait for a shutdown to be requested, then for every request in
+    /// flight to finish.
+    pub fn wait_for_drain(&self) {
+        while !self.is_requested() {
+            thread::sleep(Duration::from_millis(100));
+        }
+        let in_flight = self.in_flight.load(Ordering::SeqCst);
+        if in_flight != 0 {
+            info!(
+                "finishing {} requests, press Ctrl-C again to stop now",
+                in_flight
+            );
+        }
+        while self.in_flight.load(Ordering::SeqCst) != 0 {
+            if self.signals.load(Ordering::SeqCst) > 1 {
+                warn!("stopping without waiting for {} requests", in_flight);
+                std::process::exit(130);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Wait for the next client on `listener`, or return `None` once a
+    /// shutdown has been requested. The listener is polled rather than
+    /// blocking in `accept()`, so that an idle server still stops.
+    pub fn accept(&self, listener: &TcpListener) -> io::Result<Option<(TcpStream, SocketAddr)>> {
+        listener.set_nonblocking(true)?;
+        loop {
+            if self.is_requested() {
+                return Ok(None);
+            }
+            match listener.accept() {
+                Ok((connection, peer_addr)) => {
+                    connection.set_nonblocking(false)?;
+                    return Ok(Some((connection, peer_addr)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(ACCEPT_POLL_INTERVAL)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 pub fn gdb_server(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
     let cpu = riscv::RiscvCpu::new(&bridge, cfg.debug_offset)?;
     // Enable messible support, but only if we're not also running a messible or wishbone server.
@@ -179,8 +452,8 @@ pub fn gdb_server(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
         cfg.messible_address
     };
     loop {
-        let connection = {
-            let listener = match TcpListener::bind(format!("{}:{}", cfg.bind_addr, cfg.gdb_port)) {
+        let (connection, peer_addr) = {
+            let listener = match TcpListener::bind(cfg.bind_socket_addr(cfg.gdb_port)) {
                 Ok(o) => o,
                 Err(e) => {
                     error!("couldn't bind to address: {:?}", e);
@@ -190,11 +463,15 @@ pub fn gdb_server(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
 
             // accept connections and process them serially
             info!(
-                "accepting gdb connections on {}:{}",
-                cfg.bind_addr, cfg.gdb_port
+                "accepting gdb connections on {}",
+                cfg.bind_socket_addr(cfg.gdb_port)
             );
-            let (connection, _sockaddr) = match listener.accept() {
-                Ok(o) => o,
+            let (connection, _sockaddr) = match shutdown().accept(&listener) {
+                Ok(Some(o)) => o,
+                Ok(None) => {
+                    info!("gdb server stopped");
+                    return Ok(());
+                }
                 Err(e) => {
                     error!("couldn't accept connection: {:?}", e);
                     return Err(ServerError::IoError(e));
@@ -208,7 +485,17 @@ pub fn gdb_server(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
                 }
             };
             info!("connection from {}", peer_addr);
-            connection
+            (connection, peer_addr)
+        };
+        let _slot = match CLIENTS.try_acquire(cfg.max_clients) {
+            Some(slot) => slot,
+            None => {
+                warn!(
+                    "rejecting gdb connection from {}: {} clients already connected",
+                    peer_addr, cfg.max_clients
+                );
+                continue;
+            }
         };
 
         let mut gdb = gdb::GdbServer::new(connection).unwrap();
@@ -256,6 +543,10 @@ pub fn gdb_server(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
                 Ok(o) => o,
             };
 
+            let _request = match shutdown().begin() {
+                Some(request) => request,
+                None => break,
+            };
             if let Err(e) = gdb.process(cmd, &cpu, &bridge) {
                 match e {
                     gdb::GdbServerError::ConnectionClosed => (),
@@ -264,6 +555,7 @@ pub fn gdb_server(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
                 break;
             }
         }
+        info!("gdb client {} disconnected", peer_addr);
     }
 }
 
@@ -323,21 +615,137 @@ pub fn wishbone_server(cfg: &Config, bridge: Bridge) -> Result<(), ServerError>
 
     let mut wishbone = wishbone::WishboneServer::new(&cfg).unwrap();
     loop {
-        let mut connection = wishbone.connect().map_err(|e| {
-            error!("Unable to connect to Wishbone bridge: {:?}", e);
-            ServerError::WishboneError(e)
-        })?;
+        let (mut connection, peer_addr) = match wishbone.connect(shutdown()) {
+            Ok(Some(o)) => o,
+            Ok(None) => {
+                // Let the clients finish what they're doing first
+                shutdown().wait_for_drain();
+                info!("wishbone server stopped");
+                return Ok(());
+            }
+            Err(e) => {
+                error!("Unable to connect to Wishbone bridge: {:?}", e);
+                return Err(ServerError::WishboneError(e));
+            }
+        };
+        let slot = match CLIENTS.try_acquire(cfg.max_clients) {
+            Some(slot) => slot,
+            None => {
+                warn!(
+                    "rejecting wishbone connection from {}: {} clients already connected",
+                    peer_addr, cfg.max_clients
+                );
+                continue;
+            }
+        };
+        info!("wishbone connection from {}", peer_addr);
 
         let thread_bridge = bridge.clone();
-        std::thread::spawn(move || loop {
-            if let Err(e) = connection.process(&thread_bridge) {
-                println!("Error in Wishbone server: {:?}", e);
-                break;
+        let thread_cfg = cfg.clone();
+        std::thread::spawn(move || {
+            let _slot = slot;
+            loop {
+                let result = connection
+                    .receive()
+                    .and_then(|packet| match shutdown().begin() {
+                        Some(_request) => connection.execute(&thread_cfg, &thread_bridge, packet),
+                        None => Err(wishbone::WishboneServerError::ConnectionClosed),
+                    });
+                match result {
+                    Ok(()) => (),
+                    Err(wishbone::WishboneServerError::ConnectionClosed) => break,
+                    Err(wishbone::WishboneServerError::UnsafeWrite(e)) => {
+                        warn!("refusing write from {}: {}", peer_addr, e)
+                    }
+                    Err(e) => {
+                        println!("Error in Wishbone server: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            info!("wishbone client {} disconnected", peer_addr);
+        });
+    }
+}
+
+/// Serve the memory region named by `file_region` over TCP. See `file.rs`
+/// for the protocol.
+pub fn file_server(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
+    use std::io::BufReader;
+
+    if !bridge.capabilities().supports_burst {
+        return Err(ServerError::Unsupported(format!(
+            "the {} bridge can't read or write ranges, which the file server needs",
+            cfg.bridge_kind.name()
+        )));
+    }
+    // unwrap() is safe because the config builder checks that the region exists
+    let region = cfg.memory_regions[cfg.file_region.as_ref().unwrap()];
+
+    let listener = TcpListener::bind(cfg.bind_socket_addr(cfg.file_port))?;
+    info!(
+        "serving {} bytes at 0x{:08x} on {}",
+        region.length,
+        region.base,
+        cfg.bind_socket_addr(cfg.file_port)
+    );
+    loop {
+        let (connection, peer_addr) = match shutdown().accept(&listener)? {
+            Some(o) => o,
+            None => {
+                // Let the clients finish what they're doing first
+                shutdown().wait_for_drain();
+                info!("file server stopped");
+                return Ok(());
             }
+        };
+        let slot = match CLIENTS.try_acquire(cfg.max_clients) {
+            Some(slot) => slot,
+            None => {
+                warn!(
+                    "rejecting file connection from {}: {} clients already connected",
+                    peer_addr, cfg.max_clients
+                );
+                continue;
+            }
+        };
+        info!("file connection from {}", peer_addr);
+
+        let thread_bridge = bridge.clone();
+        let thread_cfg = cfg.clone();
+        std::thread::spawn(move || {
+            let _slot = slot;
+            let result = connection
+                .try_clone()
+                .map_err(ServerError::IoError)
+                .and_then(|reader| {
+                    file::serve(
+                        BufReader::new(reader),
+                        connection,
+                        &thread_cfg,
+                        &thread_bridge,
+                        &region,
+                        shutdown(),
+                    )
+                });
+            if let Err(e) = result {
+                error!("error in file server: {:?}", e);
+            }
+            info!("file client {} disconnected", peer_addr);
         });
     }
 }
 
+/// Pick the next value to write, and its offset within `range` bytes.
+fn random_step<R: Rng>(rng: &mut R, range: Option<u32>) -> (u32, u32) {
+    let val = rng.gen::<u32>();
+    let extra_addr = match range {
+        Some(s) => (rng.gen::<u32>() % s) & !3,
+        None => 0,
+    };
+    (extra_addr, val)
+}
+
 pub fn random_test(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
     let mut loop_counter: u32 = 0;
     let random_addr = match cfg.random_address {
@@ -348,28 +756,52 @@ pub fn random_test(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
         Some(s) => s,
         None => 0,
     };
+    let seed = cfg.random_seed.unwrap_or_else(random::<u32>);
     info!(
-        "writing random values to 0x{:08x} - 0x{:08x}",
+        "writing random values to 0x{:08x} - 0x{:08x} with seed {} (pass --seed {} to repeat)",
         random_addr,
-        random_addr + random_range
+        random_addr + random_range,
+        seed,
+        seed
     );
+    let mut rng = StdRng::seed_from_u64(seed.into());
+    let mut failures: u32 = 0;
+    // Without a failure limit or a minimum success rate, the first failure
+    // ends the test.
+    let keep_going = cfg.abort_after_failures.is_some() || cfg.min_success_rate.is_some();
     loop {
-        let val = random::<u32>();
-        let extra_addr = match cfg.random_range {
-            Some(s) => (random::<u32>() % s) & !3,
-            None => 0,
-        };
-        bridge.poke(random_addr + extra_addr, val)?;
-        let cmp = bridge.peek(random_addr + extra_addr)?;
-        if cmp != val {
-            error!(
-                "loop {} @ 0x{:08x}: expected 0x{:08x}, got 0x{:08x}",
-                loop_counter,
-                random_addr + extra_addr,
-                val,
-                cmp
-            );
-            return Err(ServerError::RandomValueError(loop_counter, val, cmp));
+        if cfg.deadline_passed() {
+            info!("deadline reached after {} loops", loop_counter);
+            return random_test_finished(cfg, failures, loop_counter);
+        }
+        let (extra_addr, val) = random_step(&mut rng, cfg.random_range);
+        let addr = random_addr + extra_addr;
+        let failed = match bridge.poke(addr, val).and_then(|_| bridge.peek(addr)) {
+            Ok(cmp) if cmp == val => false,
+            Ok(cmp) => {
+                error!(
+                    "loop {} @ 0x{:08x}: expected 0x{:08x}, got 0x{:08x}",
+                    loop_counter, addr, val, cmp
+                );
+                if !keep_going {
+                    return Err(ServerError::RandomValueError(loop_counter, val, cmp));
+                }
+                true
+            }
+            Err(e) if keep_going => {
+                error!("loop {} @ 0x{:08x}: {}", loop_counter, addr, e);
+                true
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if failed {
+            failures += 1;
+            if let Some(limit) = cfg.abort_after_failures {
+                if failures >= limit {
+                    error!("giving up after {} failures", failures);
+                    return Err(ServerError::RandomTestFailed(failures, loop_counter + 1));
+                }
+            }
         }
         if (loop_counter % 1000) == 0 {
             info!(
@@ -382,94 +814,1154 @@ pub fn random_test(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
         loop_counter = loop_counter.wrapping_add(1);
         if let Some(max_loops) = cfg.random_loops {
             if loop_counter > max_loops {
-                info!("no errors encountered");
-                return Ok(());
+                return random_test_finished(cfg, failures, loop_counter);
             }
         }
     }
 }
 
-pub fn memory_access(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
-    if let Some(addr) = cfg.memory_address {
-        if let Some(value) = cfg.memory_value {
-            if cfg.burst_length == 4 {
-                bridge.poke(addr, value)?;
-            }
-        } else if let Some(file_name) = &cfg.burst_source {
-            use std::io::Read;
-            info!("Loading contents of {} to 0x{:08x}", file_name, addr);
-            let mut f = File::open(file_name)?;
-            let mut data: Vec<u8> = vec![];
-            f.read_to_end(&mut data)?;
-            info!("Sending {} bytes", data.len());
-            bridge.burst_write(addr, &data)?;
-        } else {
-            if cfg.burst_length == 4 {
-                let val = bridge.peek(addr)?;
-                println!("Value at {:08x}: {:08x}", addr, val);
+/// The outcome of a random test that ran to the end without giving up.
+fn random_test_finished(cfg: &Config, failures: u32, loops: u32) -> Result<(), ServerError> {
+    if failures == 0 {
+        info!("no errors encountered");
+        return Ok(());
+    }
+    error!("{} failures in {} loops", failures, loops);
+    match cfg.min_success_rate {
+        Some(required) => check_success_rate(required, failures, loops),
+        None => Err(ServerError::RandomTestFailed(failures, loops)),
+    }
+}
+
+/// Pass a run in which `failures` of `attempts` went wrong if at least
+/// `required` percent of them succeeded.
+fn check_success_rate(required: f64, failures: u32, attempts: u32) -> Result<(), ServerError> {
+    let rate = if attempts == 0 {
+        100.0
+    } else {
+        100.0 * f64::from(attempts.saturating_sub(failures)) / f64::from(attempts)
+    };
+    if rate < required {
+        error!("{:.2}% succeeded, below the {}% required", rate, required);
+        return Err(ServerError::SuccessRateTooLow(rate, required));
+    }
+    info!("{:.2}% succeeded, meeting the {}% required", rate, required);
+    Ok(())
+}
+
+pub fn memory_access(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
+    if let (Some(addr), Some(poll_file)) = (cfg.memory_address, &cfg.poll_file) {
+        return poll_to_file(cfg, &bridge, addr, Path::new(poll_file));
+    }
+    if let (true, Some(addr)) = (cfg.memtest, cfg.memory_address) {
+        return run_memtest(cfg, &bridge, addr);
+    }
+
+    let result = memory_operation(cfg, &bridge)?;
+    cfg.write_result(&result.render(cfg))?;
+    if let OperationResult::ReadValue { address, value } = result {
+        check_expected(cfg, address, value)?;
+    }
+    Ok(())
+}
+
+/// Compare `value`, read from `address`, with `expect_value` in the bits
+/// set in `expect_mask`.
+fn check_expected(cfg: &Config, address: u32, value: u32) -> Result<(), ServerError> {
+    let expected = match cfg.expect_value {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    let mask = cfg.expect_mask.unwrap_or(0xffff_ffff);
+    if value & mask == expected & mask {
+        return Ok(());
+    }
+    error!(
+        "0x{:08x} read 0x{:08x}, expected 0x{:08x} (mask 0x{:08x})",
+        address, value, expected, mask
+    );
+    Err(ServerError::UnexpectedValue(address, expected, value))
+}
+
+/// Evaluate `expr` every `poll_interval` ms until it's true, returning the
+/// registers read by the final evaluation. Fails with a timeout once
+/// `watch_timeout` has passed.
+fn watch(cfg: &Config, bridge: &Bridge, expr: &WatchExpr) -> Result<Vec<(u32, u32)>, ServerError> {
+    info!("waiting until {} is true", expr);
+    let start = Instant::now();
+    let mut polls: u32 = 0;
+    loop {
+        let mut values = vec![];
+        let result = expr.evaluate(&mut |addr| {
+            let value = read_word(cfg, bridge, addr)?;
+            values.push((addr, value));
+            Ok::<u32, ServerError>(value)
+        })?;
+        polls += 1;
+        if result != 0 {
+            debug!("{} became true after {} polls", expr, polls);
+            return Ok(values);
+        }
+        if let Some(ms) = cfg.watch_timeout {
+            if start.elapsed() >= Duration::from_millis(ms.into()) {
+                error!("{} was still false after {} ms", expr, ms);
+                return Err(ServerError::IoError(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("--watch-expr still false after {} polls", polls),
+                )));
+            }
+        }
+        thread::sleep(Duration::from_millis(cfg.poll_interval.into()));
+    }
+}
+
+/// Read the current value, write `value`, wait for `width`, and then write
+/// the original value back. Returns the original value.
+fn pulse<R, W>(value: u32, width: Duration, read: R, mut write: W) -> Result<u32, ServerError>
+where
+    R: FnOnce() -> Result<u32, ServerError>,
+    W: FnMut(u32) -> Result<(), ServerError>,
+{
+    let original = read()?;
+    write(value)?;
+    if width > Duration::from_millis(0) {
+        thread::sleep(width);
+    }
+    write(original)?;
+    Ok(original)
+}
+
+/// Split `len` bytes starting at `addr` into `(address, length)` pieces of
+/// at most `chunk` bytes.
+fn chunks(addr: u32, len: u32, chunk: u32) -> impl Iterator<Item = (u32, u32)> {
+    (0..len)
+        .step_by(chunk as usize)
+        .map(move |offset| (addr + offset, chunk.min(len - offset)))
+}
+
+/// Read `len` bytes from `addr`, in bursts of `chunk_bytes()` if the bridge
+/// supports them and a word at a time otherwise.
+fn read_range(cfg: &Config, bridge: &Bridge, addr: u32, len: u32) -> Result<Vec<u8>, ServerError> {
+    let mut data = Vec::with_capacity(len as usize);
+    if bridge.capabilities().supports_burst {
+        for (chunk_addr, chunk_len) in chunks(addr, len, cfg.chunk_bytes()) {
+            data.extend(timed(cfg, "burst read", chunk_addr, || {
+                bridge.burst_read(chunk_addr, chunk_len)
+            })?);
+        }
+    } else {
+        for (word_addr, _) in chunks(addr, len, 4) {
+            data.extend_from_slice(&read_word(cfg, bridge, word_addr)?.to_le_bytes());
+        }
+        data.truncate(len as usize);
+    }
+    Ok(data)
+}
+
+/// Refuse a memory operation that the bridge can't carry out, before any
+/// of it is attempted.
+fn check_capabilities(cfg: &Config, capabilities: &BridgeCapabilities) -> Result<(), ServerError> {
+    if cfg.raw_command.is_some() && !capabilities.supports_raw_command {
+        return Err(ServerError::Unsupported(format!(
+            "the {} bridge can't send raw commands",
+            cfg.bridge_kind.name()
+        )));
+    }
+    if let Some(addr) = cfg.memory_address {
+        let last = u64::from(addr) + u64::from(cfg.burst_length.max(1)) - 1;
+        let bits = 8 * u32::from(capabilities.address_width);
+        if last.checked_shr(bits).unwrap_or(0) != 0 {
+            return Err(ServerError::Unsupported(format!(
+                "the {} bridge only has {}-bit addresses, too few for 0x{:x}",
+                cfg.bridge_kind.name(),
+                bits,
+                last
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Read the words covering `len` bytes from `addr` a word at a time,
+/// starting with the last, and return each address with its value.
+fn read_descending(
+    cfg: &Config,
+    bridge: &Bridge,
+    addr: u32,
+    len: u32,
+) -> Result<Vec<(u32, u32)>, ServerError> {
+    (0..len.div_ceil(4))
+        .rev()
+        .map(|i| {
+            let word_addr = addr + 4 * i;
+            Ok((word_addr, read_word(cfg, bridge, word_addr)?))
+        })
+        .collect()
+}
+
+/// Write `data` to `addr`, in bursts of `chunk_bytes()` if the bridge
+/// supports them and a word at a time otherwise. A trailing partial word
+/// is merged with what is already in memory.
+fn write_range(cfg: &Config, bridge: &Bridge, addr: u32, data: &[u8]) -> Result<(), ServerError> {
+    let len = data.len() as u32;
+    if bridge.capabilities().supports_burst {
+        for (chunk_addr, chunk_len) in chunks(addr, len, cfg.chunk_bytes()) {
+            let start = (chunk_addr - addr) as usize;
+            let chunk = data[start..start + chunk_len as usize].to_vec();
+            timed(cfg, "burst write", chunk_addr, || {
+                bridge.burst_write(chunk_addr, &chunk)
+            })?;
+        }
+        return Ok(());
+    }
+    for (word_addr, word_len) in chunks(addr, len, 4) {
+        let start = (word_addr - addr) as usize;
+        let mut word = if word_len < 4 {
+            read_word(cfg, bridge, word_addr)?.to_le_bytes()
+        } else {
+            [0; 4]
+        };
+        word[..word_len as usize].copy_from_slice(&data[start..start + word_len as usize]);
+        let value = u32::from_le_bytes(word);
+        timed(cfg, "write", word_addr, || bridge.poke(word_addr, value))?;
+    }
+    Ok(())
+}
+
+/// A word that didn't read back what `--memtest` wrote to it.
+#[derive(Debug, PartialEq)]
+struct MemtestFailure {
+    pattern: FillPattern,
+    address: u32,
+    expected: u32,
+    actual: u32,
+}
+
+/// Run `memtest()` over `burst_length` bytes at `addr`, with just
+/// `fill_pattern` if it was given and every pattern otherwise.
+fn run_memtest(cfg: &Config, bridge: &Bridge, addr: u32) -> Result<(), ServerError> {
+    let patterns = match cfg.fill_pattern {
+        Some(pattern) => vec![pattern],
+        None => FillPattern::ALL.to_vec(),
+    };
+    info!(
+        "testing {} bytes at 0x{:08x} with {} patterns",
+        cfg.burst_length,
+        addr,
+        patterns.len()
+    );
+    let failures = memtest(cfg, bridge, addr, cfg.burst_length, &patterns)?;
+    for failure in &failures {
+        error!(
+            "{:?} @ 0x{:08x}: expected 0x{:08x}, got 0x{:08x}",
+            failure.pattern, failure.address, failure.expected, failure.actual
+        );
+    }
+    if failures.is_empty() {
+        info!("no errors encountered");
+        return Ok(());
+    }
+    error!("{} words failed", failures.len());
+    let words = cfg.burst_length.div_ceil(4) * patterns.len() as u32;
+    match cfg.min_success_rate {
+        Some(required) => check_success_rate(required, failures.len() as u32, words),
+        None => Err(ServerError::MemtestFailed(failures.len() as u32)),
+    }
+}
+
+/// Write each of `patterns` across `len` bytes at `addr` and read the whole
+/// range back before moving on to the next, listing every word that differs.
+fn memtest(
+    cfg: &Config,
+    bridge: &Bridge,
+    addr: u32,
+    len: u32,
+    patterns: &[FillPattern],
+) -> Result<Vec<MemtestFailure>, ServerError> {
+    // A trailing partial word is compared as if the rest of it were zero
+    let word = |bytes: &[u8]| {
+        let mut word = [0; 4];
+        word[..bytes.len()].copy_from_slice(bytes);
+        u32::from_le_bytes(word)
+    };
+    let mut failures = vec![];
+    for &pattern in patterns {
+        let expected = pattern.bytes(addr, len);
+        write_range(cfg, bridge, addr, &expected)?;
+        let actual = read_range(cfg, bridge, addr, len)?;
+        for (i, (expected, actual)) in expected.chunks(4).zip(actual.chunks(4)).enumerate() {
+            if expected != actual {
+                failures.push(MemtestFailure {
+                    pattern,
+                    address: addr + i as u32 * 4,
+                    expected: word(expected),
+                    actual: word(actual),
+                });
+            }
+        }
+    }
+    Ok(failures)
+}
+
+/// Read every register in `csr_registers` that isn't write-only. A register
+/// that fails to read is reported alongside the others rather than
+/// stopping the dump.
+fn read_registers(cfg: &Config, bridge: &Bridge) -> Vec<RegisterValue> {
+    let mut registers: Vec<_> = cfg
+        .csr_registers
+        .iter()
+        .filter(|register| !register.write_only)
+        .collect();
+    if cfg.reverse {
+        registers.sort_by_key(|r| std::cmp::Reverse(r.address));
+    }
+    registers
+        .into_iter()
+        .map(|register| RegisterValue {
+            name: register.name.clone(),
+            address: register.address,
+            value: read_word(cfg, bridge, register.address).map_err(|e| format!("{:?}", e)),
+        })
+        .collect()
+}
+
+/// Parse a register snapshot, as printed by `--dump-registers`. Each line
+/// is `name = value`; blank lines, `#` comments and registers that
+/// couldn't be read when the snapshot was taken are skipped.
+fn parse_register_snapshot(contents: &str) -> Result<Vec<(String, u32)>, ServerError> {
+    let mut registers = vec![];
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason: String| ServerError::SnapshotParseError(idx + 1, reason);
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected \"name = value\"".to_owned()))?;
+        let value = value.trim();
+        if value.starts_with("error:") {
+            continue;
+        }
+        let value = parse_u32(value).map_err(|e| error(e.to_string()))?;
+        registers.push((name.trim().to_lowercase(), value));
+    }
+    Ok(registers)
+}
+
+/// List the registers in `current` whose values differ from `snapshot`,
+/// followed by any that only `snapshot` has.
+fn compare_registers(snapshot: &[(String, u32)], current: &[RegisterValue]) -> Vec<RegisterChange> {
+    let old_value = |name: &str| {
+        snapshot
+            .iter()
+            .find(|(old_name, _)| old_name == name)
+            .map(|(_, value)| *value)
+    };
+    let mut changes: Vec<RegisterChange> = current
+        .iter()
+        .filter_map(|register| {
+            let old = old_value(&register.name);
+            if old.is_some() && register.value.as_ref().ok() == old.as_ref() {
+                return None;
+            }
+            Some(RegisterChange {
+                name: register.name.clone(),
+                old,
+                new: Some(register.value.clone()),
+            })
+        })
+        .collect();
+    for (name, value) in snapshot {
+        if !current.iter().any(|register| &register.name == name) {
+            changes.push(RegisterChange {
+                name: name.clone(),
+                old: Some(*value),
+                new: None,
+            });
+        }
+    }
+    changes
+}
+
+/// Perform the single read or write described by `cfg` and report what was done.
+pub fn memory_operation(cfg: &Config, bridge: &Bridge) -> Result<OperationResult, ServerError> {
+    check_capabilities(cfg, &bridge.capabilities())?;
+    if cfg.dump_registers {
+        return Ok(OperationResult::Registers(read_registers(cfg, bridge)));
+    }
+    if let Some(path) = &cfg.compare_registers {
+        let snapshot = parse_register_snapshot(&std::fs::read_to_string(path)?)?;
+        return Ok(OperationResult::RegisterChanges(compare_registers(
+            &snapshot,
+            &read_registers(cfg, bridge),
+        )));
+    }
+    if let Some(sent) = &cfg.raw_command {
+        warn!("sending {} raw bytes to the bridge", sent.len());
+        return Ok(OperationResult::RawCommand {
+            sent: sent.clone(),
+            received: bridge.raw_command(sent)?,
+        });
+    }
+    if let Some(expr) = &cfg.watch_expr {
+        return Ok(OperationResult::WordsRead(watch(cfg, bridge, expr)?));
+    }
+    let addr = match cfg.memory_address {
+        Some(addr) => addr,
+        None => return Ok(OperationResult::NoOperation),
+    };
+    if cfg.histogram {
+        let values = (0..cfg.histogram_samples)
+            .map(|_| read_word(cfg, bridge, addr))
+            .collect::<Result<Vec<u32>, ServerError>>()?;
+        return Ok(OperationResult::Histogram {
+            address: addr,
+            histogram: Histogram::from_samples(&values, cfg.histogram_bins),
+        });
+    }
+    if let Some(value) = cfg.pulse_value {
+        let original = pulse(
+            value,
+            Duration::from_millis(cfg.pulse_width.into()),
+            || read_word(cfg, bridge, addr),
+            |v| Ok(write_word(cfg, bridge, addr, v)?),
+        )?;
+        Ok(OperationResult::Pulsed {
+            address: addr,
+            value,
+            original,
+        })
+    } else if let Some(value) = cfg.memory_value {
+        write_word(cfg, bridge, addr, value)?;
+        Ok(OperationResult::Wrote {
+            address: addr,
+            value,
+        })
+    } else if let Some(file_name) = &cfg.burst_source {
+        use std::io::Read;
+        info!("Loading contents of {} to 0x{:08x}", file_name, addr);
+        let mut f = File::open(file_name)?;
+        let mut data: Vec<u8> = vec![];
+        f.read_to_end(&mut data)?;
+        info!("Sending {} bytes", data.len());
+        write_range(cfg, bridge, addr, &data)?;
+        Ok(OperationResult::RangeWrote {
+            start: addr,
+            length: data.len(),
+        })
+    } else if cfg.value_file.is_some() {
+        info!("Writing {} values to 0x{:08x}", cfg.values.len(), addr);
+        let data: Vec<u8> = cfg.values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        write_range(cfg, bridge, addr, &data)?;
+        Ok(OperationResult::RangeWrote {
+            start: addr,
+            length: data.len(),
+        })
+    } else if let Some(pattern) = cfg.fill_pattern {
+        info!(
+            "Filling {} bytes at 0x{:08x} with {:?} pattern",
+            cfg.burst_length, addr, pattern
+        );
+        let data = pattern.bytes(addr, cfg.burst_length);
+        write_range(cfg, bridge, addr, &data)?;
+        Ok(OperationResult::RangeWrote {
+            start: addr,
+            length: data.len(),
+        })
+    } else if cfg.burst_length == 4 {
+        Ok(OperationResult::ReadValue {
+            address: addr,
+            value: read_word(cfg, bridge, addr)?,
+        })
+    } else if cfg.reverse {
+        Ok(OperationResult::WordsRead(read_descending(
+            cfg,
+            bridge,
+            addr,
+            cfg.burst_length,
+        )?))
+    } else {
+        Ok(OperationResult::RangeRead {
+            start: addr,
+            data: read_range(cfg, bridge, addr, cfg.burst_length)?,
+        })
+    }
+}
+
+/// Sample `memory_address` every `poll_interval` milliseconds and append
+/// `timestamp,value` rows to `logic_file` until Ctrl-C is pressed or
+/// `logic_max_samples` (or failing that, `poll_count`) rows have been
+/// recorded.
+pub fn logic_capture(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
+    use std::fs::OpenOptions;
+    use std::io::{BufWriter, Write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    // unwrap() is safe because the config builder requires both of these for Logic
+    let addr = cfg.memory_address.unwrap();
+    let path = cfg.logic_file.as_ref().unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::SIGINT, stop.clone())?;
+
+    let f = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_new = f.metadata()?.len() == 0;
+    let mut out = BufWriter::new(f);
+    let jsonl = cfg.output_format == OutputFormat::Jsonl;
+    if is_new && !jsonl {
+        writeln!(out, "timestamp,value")?;
+    }
+
+    info!(
+        "recording 0x{:08x} to {} every {} ms, press Ctrl-C to stop",
+        addr, path, cfg.poll_interval
+    );
+    let mut samples: u32 = 0;
+    let mut recorded: u32 = 0;
+    let mut changes = ChangeFilter::new();
+    let mut last_flush = Instant::now();
+    while !stop.load(Ordering::Relaxed) && !cfg.deadline_passed() {
+        let val = read_word(cfg, &bridge, addr)?;
+        // A clock before 1970 is not worth failing a capture over.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if !cfg.trace_changes_only || changes.is_new(addr, val) {
+            if jsonl {
+                // Each line is flushed on its own so readers never see half a row
+                out.write_all(jsonl_sample(cfg, now, addr, val).as_bytes())?;
+                out.flush()?;
+            } else {
+                out.write_all(logic_row(now, val).as_bytes())?;
+            }
+            recorded += 1;
+        }
+        samples += 1;
+
+        if let Some(max) = cfg.logic_max_samples.or(cfg.poll_count) {
+            if samples >= max {
+                break;
+            }
+        }
+        if last_flush.elapsed() >= Duration::from_secs(1) {
+            out.flush()?;
+            last_flush = Instant::now();
+        }
+        thread::sleep(Duration::from_millis(cfg.poll_interval.into()));
+    }
+    out.flush()?;
+    info!("recorded {} of {} samples to {}", recorded, samples, path);
+    Ok(())
+}
+
+/// The last value seen at each address, for `--trace-changes-only`.
+struct ChangeFilter {
+    last: HashMap<u32, u32>,
+}
+
+impl ChangeFilter {
+    fn new() -> ChangeFilter {
+        ChangeFilter {
+            last: HashMap::new(),
+        }
+    }
+
+    /// Whether `value` differs from the last value seen at `addr`, which
+    /// is always the case the first time. `value` is remembered either way.
+    fn is_new(&mut self, addr: u32, value: u32) -> bool {
+        self.last.insert(addr, value) != Some(value)
+    }
+}
+
+/// Format a single row of a logic capture.
+fn logic_row(timestamp: Duration, value: u32) -> String {
+    format!(
+        "{}.{:03},0x{:08x}\n",
+        timestamp.as_secs(),
+        timestamp.subsec_millis(),
+        value
+    )
+}
+
+/// Every word read or written, appended to `--trace` as
+/// `timestamp,read|write,address,value` rows that `replay` can play back.
+/// Rows are flushed at least once a second, and by `flush()`.
+pub struct TraceLog {
+    out: Mutex<(io::BufWriter<File>, std::time::Instant)>,
+
+    /// With `--trace-changes-only`, the last value read from each address
+    changes: Option<Mutex<ChangeFilter>>,
+}
+
+impl TraceLog {
+    /// Open `path` for appending. If `changes_only` is set, a read is only
+    /// recorded when its value differs from the last read of that address.
+    /// Writes are always recorded.
+    pub fn open(path: &str, changes_only: bool) -> io::Result<TraceLog> {
+        use std::fs::OpenOptions;
+        use std::io::Write;
+
+        let f = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new = f.metadata()?.len() == 0;
+        let mut out = io::BufWriter::new(f);
+        if is_new {
+            writeln!(out, "timestamp,operation,address,value")?;
+        }
+        Ok(TraceLog {
+            out: Mutex::new((out, std::time::Instant::now())),
+            changes: if changes_only {
+                Some(Mutex::new(ChangeFilter::new()))
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Append a row for `access`. A row that can't be written is reported
+    /// but doesn't stop the operation being traced.
+    pub fn record(&self, access: Access) {
+        use std::io::Write;
+
+        if let (Some(changes), Access::Read(addr, value)) = (&self.changes, access) {
+            if !changes.lock().unwrap().is_new(addr, value) {
+                return;
+            }
+        }
+
+        // A clock before 1970 is not worth failing a trace over.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut out = self.out.lock().unwrap();
+        let (writer, last_flush) = &mut *out;
+        let mut result = writer.write_all(trace_row(now, access).as_bytes());
+        if last_flush.elapsed() >= Duration::from_secs(1) {
+            result = result.and_then(|_| writer.flush());
+            *last_flush = std::time::Instant::now();
+        }
+        if let Err(e) = result {
+            warn!("couldn't write to trace: {}", e);
+        }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        use std::io::Write;
+        self.out.lock().unwrap().0.flush()
+    }
+}
+
+/// Format a single row of a trace.
+fn trace_row(timestamp: Duration, access: Access) -> String {
+    let (operation, addr, value) = match access {
+        Access::Read(addr, value) => ("read", addr, value),
+        Access::Write(addr, value) => ("write", addr, value),
+    };
+    format!(
+        "{}.{:03},{},0x{:08x},0x{:08x}\n",
+        timestamp.as_secs(),
+        timestamp.subsec_millis(),
+        operation,
+        addr,
+        value
+    )
+}
+
+#[derive(Debug, PartialEq)]
+enum ReplayOp {
+    /// Read an address, expecting the recorded value
+    Read(u32 /* addr */, u32 /* expected */),
+
+    /// Write a value to an address
+    Write(u32 /* addr */, u32 /* value */),
+}
+
+#[derive(Debug, PartialEq)]
+struct ReplayEntry {
+    timestamp: Duration,
+    op: ReplayOp,
+}
+
+impl ReplayEntry {
+    fn transaction(&self) -> Transaction {
+        match self.op {
+            ReplayOp::Read(addr, _) => Transaction::Read(addr),
+            ReplayOp::Write(addr, value) => Transaction::Write(addr, value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ReplayStep {
+    /// A single read or write
+    Single(ReplayEntry),
+
+    /// Entries between `txn {` and `}`, which run with nothing else in
+    /// between
+    Txn(Vec<ReplayEntry>),
+}
+
+/// Parse a whole replay log. Besides the rows `parse_replay_line()`
+/// understands, rows between a `txn {` line and a `}` line are grouped
+/// into a single transaction.
+fn parse_replay_log(
+    contents: &str,
+    defines: &HashMap<String, String>,
+    default_addr: Option<u32>,
+) -> Result<Vec<ReplayStep>, ServerError> {
+    let mut steps = vec![];
+    let mut txn: Option<(usize, Vec<ReplayEntry>)> = None;
+    for (idx, line) in contents.lines().enumerate() {
+        let error = |reason: String| ServerError::ReplayParseError(idx + 1, reason);
+        let line = substitute_defines(line, defines).map_err(|e| error(e.to_string()))?;
+        match (line.trim(), txn.take()) {
+            ("txn {", Some(_)) => {
+                return Err(error("a txn block can't contain another".to_owned()))
+            }
+            ("txn {", None) => txn = Some((idx + 1, vec![])),
+            ("}", Some((_, entries))) => steps.push(ReplayStep::Txn(entries)),
+            ("}", None) => return Err(error("\"}\" without a \"txn {\"".to_owned())),
+            (_, open) => {
+                txn = open;
+                if let Some(entry) = parse_replay_line(&line, default_addr).map_err(error)? {
+                    match &mut txn {
+                        Some((_, entries)) => entries.push(entry),
+                        None => steps.push(ReplayStep::Single(entry)),
+                    }
+                }
+            }
+        }
+    }
+    match txn {
+        Some((start, _)) => Err(ServerError::ReplayParseError(
+            start,
+            "\"txn {\" is never closed".to_owned(),
+        )),
+        None => Ok(steps),
+    }
+}
+
+/// Parse one line of a replay log. Rows are either `timestamp,value`, as
+/// written by the logic server, which are reads of `default_addr`, or
+/// `timestamp,read|write,address,value`. Blank lines and the header row
+/// return `None`.
+fn parse_replay_line(line: &str, default_addr: Option<u32>) -> Result<Option<ReplayEntry>, String> {
+    let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+    if line.trim().is_empty() || fields[0] == "timestamp" {
+        return Ok(None);
+    }
+    let timestamp =
+        parse_timestamp(fields[0]).ok_or_else(|| format!("invalid timestamp \"{}\"", fields[0]))?;
+    let number = |s: &str| parse_u32(s).map_err(|e| e.to_string());
+    let op = match fields.as_slice() {
+        [_, value] => {
+            let addr = default_addr.ok_or("no address given for a timestamp,value row")?;
+            ReplayOp::Read(addr, number(value)?)
+        }
+        [_, "read", addr, value] => ReplayOp::Read(number(addr)?, number(value)?),
+        [_, "write", addr, value] => ReplayOp::Write(number(addr)?, number(value)?),
+        [_, op, _, _] => return Err(format!("unknown operation \"{}\"", op)),
+        _ => return Err(format!("expected 2 or 4 fields, found {}", fields.len())),
+    };
+    Ok(Some(ReplayEntry { timestamp, op }))
+}
+
+/// Parse a `seconds[.fraction]` timestamp without going through floating
+/// point, so recorded milliseconds survive exactly.
+fn parse_timestamp(s: &str) -> Option<Duration> {
+    let (secs, frac) = match s.split_once('.') {
+        Some((secs, frac)) => (secs, frac),
+        None => (s, ""),
+    };
+    if frac.len() > 9 || !frac.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = if frac.is_empty() {
+        0
+    } else {
+        frac.parse::<u32>().ok()? * 10u32.pow(9 - frac.len() as u32)
+    };
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+/// Execute each transaction in `replay_file` in order, reporting any read
+/// that doesn't return the recorded value. With `preserve_timing`, the gaps
+/// between the recorded timestamps are reproduced, except inside a `txn`
+/// block, which runs as one uninterrupted sequence.
+pub fn replay(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
+    // unwrap() is safe because the config builder requires this for Replay
+    let path = cfg.replay_file.as_ref().unwrap();
+    let steps = parse_replay_log(
+        &std::fs::read_to_string(path)?,
+        &cfg.defines,
+        cfg.memory_address,
+    )?;
+
+    let count: usize = steps
+        .iter()
+        .map(|step| match step {
+            ReplayStep::Single(_) => 1,
+            ReplayStep::Txn(entries) => entries.len(),
+        })
+        .sum();
+    info!("replaying {} transactions from {}", count, path);
+    let mut divergences = 0;
+    let mut check = |entry: &ReplayEntry, actual: u32| {
+        if let ReplayOp::Read(addr, expected) = entry.op {
+            if actual != expected {
+                divergences += 1;
+                error!(
+                    "read of 0x{:08x} at {:.3}s returned 0x{:08x}, recorded 0x{:08x}",
+                    addr,
+                    entry.timestamp.as_secs_f64(),
+                    actual,
+                    expected
+                );
+            }
+        }
+    };
+    let mut last_timestamp = None;
+    for step in &steps {
+        let entries = match step {
+            ReplayStep::Single(entry) => std::slice::from_ref(entry),
+            ReplayStep::Txn(entries) => entries.as_slice(),
+        };
+        if let (true, Some(first), Some(last)) =
+            (cfg.preserve_timing, entries.first(), entries.last())
+        {
+            if let Some(previous) = last_timestamp {
+                if first.timestamp > previous {
+                    thread::sleep(first.timestamp - previous);
+                }
+            }
+            last_timestamp = Some(last.timestamp);
+        }
+        match step {
+            ReplayStep::Single(entry) => match entry.op {
+                ReplayOp::Write(addr, value) => bridge.poke(addr, value)?,
+                ReplayOp::Read(addr, _) => check(entry, read_word(cfg, &bridge, addr)?),
+            },
+            ReplayStep::Txn(entries) => {
+                let transactions: Vec<Transaction> =
+                    entries.iter().map(ReplayEntry::transaction).collect();
+                let values = bridge.transaction(&transactions)?;
+                let reads = entries
+                    .iter()
+                    .filter(|entry| matches!(entry.op, ReplayOp::Read(_, _)));
+                for (entry, actual) in reads.zip(values) {
+                    check(entry, actual);
+                }
+            }
+        }
+    }
+    if divergences == 0 {
+        info!("replay complete, all reads matched");
+    } else {
+        warn!("replay complete, {} reads diverged", divergences);
+    }
+    Ok(())
+}
+
+/// Keep the bridge from going idle for longer than `--keepalive` by reading
+/// `--keepalive-address` whenever nothing else has used it. This runs until
+/// the bridge fails.
+pub fn keepalive(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
+    let interval = Duration::from_millis(cfg.keepalive.into());
+    // The address is required whenever --keepalive is set
+    let addr = cfg.keepalive_address.unwrap();
+    loop {
+        thread::sleep(keepalive_step(&bridge, interval, addr)?);
+    }
+}
+
+/// Make sure the device answers before any server starts listening, by
+/// reading `--preflight-address` once. Does nothing unless `--preflight`
+/// was given.
+pub fn preflight(cfg: &Config, bridge: &Bridge) -> Result<(), ServerError> {
+    if !cfg.preflight {
+        return Ok(());
+    }
+    // One of these is required whenever --preflight is set
+    let addr = cfg.preflight_address.or(cfg.keepalive_address).unwrap();
+    match bridge.peek_once(addr) {
+        Ok(value) => {
+            debug!("preflight read of 0x{:08x} returned 0x{:08x}", addr, value);
+            Ok(())
+        }
+        Err(e) => {
+            error!(
+                "preflight read of 0x{:08x} failed, is the device powered and connected? ({})",
+                addr, e
+            );
+            Err(e.into())
+        }
+    }
+}
+
+/// Read `addr` if the bridge has been idle for `interval`, and return how
+/// long to wait before checking again.
+fn keepalive_step(bridge: &Bridge, interval: Duration, addr: u32) -> Result<Duration, BridgeError> {
+    let idle = bridge.idle_time();
+    if idle < interval {
+        return Ok(interval - idle);
+    }
+    debug!(
+        "bridge idle for {} ms, reading 0x{:08x} to keep it awake",
+        idle.as_millis(),
+        addr
+    );
+    bridge.peek(addr)?;
+    Ok(interval)
+}
+
+/// Read a single word, confirming it with a second read if `--double-read`
+/// was requested.
+fn read_word(cfg: &Config, bridge: &Bridge, addr: u32) -> Result<u32, ServerError> {
+    let peek = || {
+        retried(cfg, "read", addr, || {
+            timed(cfg, "read", addr, || bridge.peek(addr))
+        })
+    };
+    if cfg.double_read {
+        confirmed_read(cfg, addr, peek)
+    } else {
+        Ok(peek()?)
+    }
+}
+
+fn write_word(cfg: &Config, bridge: &Bridge, addr: u32, value: u32) -> Result<(), BridgeError> {
+    retried(cfg, "write", addr, || {
+        timed(cfg, "write", addr, || bridge.poke(addr, value))
+    })
+}
+
+/// Run `op`, trying again up to `retry_count` more times if it fails with
+/// one of the `retry_on` error kinds. Other errors are returned at once.
+fn retried<T, F>(cfg: &Config, kind: &str, addr: u32, mut op: F) -> Result<T, BridgeError>
+where
+    F: FnMut() -> Result<T, BridgeError>,
+{
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < cfg.retry_count && cfg.retry_on.contains(&e.kind()) => {
+                attempt += 1;
+                warn!(
+                    "{} of 0x{:08x} failed ({}), retrying ({}/{})",
+                    kind, addr, e, attempt, cfg.retry_count
+                );
+                thread::sleep(cfg.retry_delay_for(attempt));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Run a single bridge transaction `op`, warning if it took longer than
+/// `slow_threshold`.
+fn timed<T, F>(cfg: &Config, kind: &str, addr: u32, op: F) -> Result<T, BridgeError>
+where
+    F: FnOnce() -> Result<T, BridgeError>,
+{
+    let (result, warning) = timed_with_warning(cfg, kind, addr, op);
+    if let Some(warning) = warning {
+        warn!("{}", warning);
+    }
+    result
+}
+
+fn timed_with_warning<T, F>(
+    cfg: &Config,
+    kind: &str,
+    addr: u32,
+    op: F,
+) -> (Result<T, BridgeError>, Option<String>)
+where
+    F: FnOnce() -> Result<T, BridgeError>,
+{
+    let threshold = match cfg.slow_threshold {
+        Some(ms) => Duration::from_millis(ms.into()),
+        None => return (op(), None),
+    };
+    let start = std::time::Instant::now();
+    let result = op();
+    let elapsed = start.elapsed();
+    let warning = if elapsed > threshold {
+        Some(format!(
+            "slow {} at 0x{:08x}: took {} ms",
+            kind,
+            addr,
+            elapsed.as_millis()
+        ))
+    } else {
+        None
+    };
+    (result, warning)
+}
+
+/// Call `read` twice and return the value if both results agree. A
+/// disagreement is retried up to `retry_count` more times, waiting between
+/// attempts according to `retry_backoff`, before giving up.
+fn confirmed_read<F>(cfg: &Config, addr: u32, mut read: F) -> Result<u32, ServerError>
+where
+    F: FnMut() -> Result<u32, BridgeError>,
+{
+    let mut attempt = 0;
+    loop {
+        let first = read()?;
+        let second = read()?;
+        if first == second {
+            return Ok(first);
+        }
+        if attempt >= cfg.retry_count {
+            return Err(ServerError::ReadMismatch(addr, first, second));
+        }
+        attempt += 1;
+        warn!(
+            "reads of 0x{:08x} disagreed ({:08x} vs {:08x}), retrying ({}/{})",
+            addr, first, second, attempt, cfg.retry_count
+        );
+        thread::sleep(cfg.retry_delay_for(attempt));
+    }
+}
+
+/// Write the value at `addr` to `path` every `poll_interval` ms, either
+/// forever or until `poll_count` reads have been made.
+fn poll_to_file(cfg: &Config, bridge: &Bridge, addr: u32, path: &Path) -> Result<(), ServerError> {
+    info!(
+        "Writing value of 0x{:08x} to {} every {} ms",
+        addr,
+        path.display(),
+        cfg.poll_interval
+    );
+    let mut samples: u32 = 0;
+    loop {
+        if cfg.deadline_passed() {
+            info!("deadline reached after {} reads of 0x{:08x}", samples, addr);
+            return Ok(());
+        }
+        let val = read_word(cfg, bridge, addr)?;
+        if cfg.output_format == OutputFormat::Jsonl {
+            // JSON Lines keeps every sample rather than only the latest
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            append_poll_file(path, &jsonl_sample(cfg, now, addr, val))?;
+        } else {
+            write_poll_file(path, &format!("{:08x}\n", val))?;
+        }
+        samples += 1;
+        if cfg.poll_count == Some(samples) {
+            info!(
+                "read 0x{:08x} {} times, last value 0x{:08x}",
+                addr, samples, val
+            );
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(cfg.poll_interval.into()));
+    }
+}
+
+/// Replace the contents of `path` with `contents`. The data is written to a
+/// temporary file first and then renamed over `path`, so anything watching
+/// the file never sees a partial value.
+fn write_poll_file(path: &Path, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = Path::new(&tmp_name);
+    {
+        let mut f = File::create(tmp_path)?;
+        f.write_all(contents.as_bytes())?;
+    }
+    std::fs::rename(tmp_path, path)
+}
+
+/// Add `contents` to the end of `path`, creating it if needed.
+fn append_poll_file(path: &Path, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+/// The CRC-32 (IEEE 802.3) of `data`, as used by zlib and `crc32(1)`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
             } else {
-                let page = bridge.burst_read(addr, cfg.burst_length);
-                match page {
-                    Ok(array) => {
-                        if cfg.hexdump {
-                            for i in 0..array.len() {
-                                if (i % 16) == 0 {
-                                    println!(); // carriage return
-                                    print!("{:08x}: ", addr as usize + i);
-                                }
-                                print!("{:02x} ", array[i]);
-                            }
-                            println!("");
-                        } else {
-                            use std::io::Write;
-                            io::stdout().write_all(&array)?;
-                        }
-                    }
-                    _ => {
-                        error!("Error occured reading page");
-                    }
-                }
-            }
+                crc >> 1
+            };
         }
-    } else {
-        println!("No operation and no address specified!");
-        println!(
-            "Try specifying an address such as \"0x10000000\".  See --help for more information"
+    }
+    !crc
+}
+
+/// Read back the `len` bytes loaded at `addr` and compare their CRC32
+/// with that of the same bytes of `file_name`.
+fn verify_load_checksum(
+    cfg: &Config,
+    bridge: &Bridge,
+    file_name: &str,
+    addr: u32,
+    len: u32,
+) -> Result<(), ServerError> {
+    let data = std::fs::read(file_name)?;
+    let expected = crc32(&data[..(len as usize).min(data.len())]);
+    let actual = crc32(&read_range(cfg, bridge, addr, len)?);
+    if expected != actual {
+        error!(
+            "checksum mismatch: {} has CRC32 {:08x}, but memory at 0x{:08x} has {:08x}",
+            file_name, expected, addr, actual
         );
+        return Err(ServerError::ChecksumMismatch(expected, actual));
     }
+    info!("checksum verified: CRC32 {:08x}", actual);
     Ok(())
 }
 
 pub fn load_file(cfg: &Config, bridge: Bridge) -> Result<(), ServerError> {
-    let mut word_counter: u32 = 0;
     if let Some(file_name) = &cfg.load_name {
         if let Some(addr) = cfg.load_addr {
-            let mut f = File::open(file_name)?;
-            let f_len = f.metadata().unwrap().len() as u32;
+            let mut data = std::fs::read(file_name)?;
+            let f_len = data.len() as u32;
             info!(
                 "Loading {} bytes from {} to address 0x{:08x}",
                 f_len, file_name, addr
             );
-            while word_counter < f_len {
-                let value = match f.read_u32::<LittleEndian>() {
-                    Ok(x) => x,
-                    Err(e) => {
-                        error!("Error reading: {}", e);
-                        return Ok(());
-                    }
-                };
-                if (word_counter % 1024) == 0 {
+            let trailing = f_len & 3;
+            if trailing != 0 {
+                warn!(
+                    "{} is not a whole number of words, ignoring the last {} bytes",
+                    file_name, trailing
+                );
+                data.truncate((f_len & !3) as usize);
+            }
+            let len = data.len() as u32;
+            for (chunk_addr, chunk_len) in chunks(addr, len, cfg.chunk_bytes()) {
+                let offset = chunk_addr - addr;
+                if offset % 4096 < chunk_len {
                     info!(
-                        "write to {:08x}: ({:08x}) - {}%",
-                        addr + word_counter,
-                        value,
-                        (word_counter * 100 / f_len)
+                        "write to {:08x} - {}%",
+                        chunk_addr,
+                        (offset as u64 * 100 / f_len as u64)
                     );
                 }
-                bridge.poke(addr + word_counter, value)?;
-                word_counter = word_counter.wrapping_add(4);
+                let chunk = &data[offset as usize..(offset + chunk_len) as usize];
+                write_range(cfg, &bridge, chunk_addr, chunk)?;
+            }
+            info!("Done. Wrote {} bytes", len);
+            if cfg.verify_checksum {
+                verify_load_checksum(cfg, &bridge, file_name, addr, len)?;
             }
-            info!("Done. Wrote {} bytes", word_counter);
         } else {
             error!("No load address specified");
         }
@@ -976,3 +2468,1110 @@ pub fn messible_client(cfg: &Config, bridge: Bridge) -> Result<(), ServerError>
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::BridgeKind;
+    use crate::config::FillPattern;
+    use crate::config::RetryBackoff;
+
+    #[test]
+    fn poll_file_holds_latest_value() {
+        let path = std::env::temp_dir().join(format!("wishbone-poll-{}", std::process::id()));
+        for val in &[0x1234_5678u32, 0xdead_beef] {
+            write_poll_file(&path, &format!("{:08x}\n", val)).unwrap();
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(contents, "deadbeef\n");
+    }
+
+    #[test]
+    fn poll_count_limits_reads() {
+        // Only the fifth read is corrupted, so the file shows whether the
+        // loop stopped exactly there.
+        let bridge = wishbone_bridge::MockBridge::new()
+            .corrupt_every(5)
+            .create()
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("wishbone-poll-count-{}", std::process::id()));
+        let cfg = Config {
+            poll_interval: 0,
+            poll_count: Some(5),
+            ..Default::default()
+        };
+        poll_to_file(&cfg, &bridge, 0x10, &path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(contents, "ffffffff\n");
+    }
+
+    #[test]
+    fn jsonl_poll_file_keeps_every_sample() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let path = std::env::temp_dir().join(format!("wishbone-poll-jsonl-{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let cfg = Config {
+            poll_interval: 0,
+            poll_count: Some(4),
+            output_format: OutputFormat::Jsonl,
+            ..Default::default()
+        };
+        poll_to_file(&cfg, &bridge, 0x10, &path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(contents.lines().count(), 4);
+    }
+
+    #[test]
+    fn logic_capture_writes_jsonl_lines() {
+        let bridge = wishbone_bridge::MockBridge::new()
+            .value(0x10, 0xcafe)
+            .create()
+            .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("wishbone-logic-{}.jsonl", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let cfg = Config {
+            memory_address: Some(0x10),
+            logic_file: Some(path.to_string_lossy().into_owned()),
+            logic_max_samples: Some(2),
+            poll_interval: 0,
+            output_format: OutputFormat::Jsonl,
+            ..Default::default()
+        };
+        logic_capture(&cfg, bridge).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let ts = line
+                .strip_prefix("{\"ts\":")
+                .and_then(|rest| rest.strip_suffix(",\"address\":16,\"value\":51966}"))
+                .unwrap_or_else(|| panic!("unexpected line {}", line));
+            assert!(ts.parse::<f64>().is_ok(), "bad timestamp in {}", line);
+        }
+    }
+
+    #[test]
+    fn logic_row_has_millisecond_timestamp() {
+        let row = logic_row(Duration::from_millis(1_600_000_000_042), 0xcafe);
+        assert_eq!(row, "1600000000.042,0x0000cafe\n");
+    }
+
+    #[test]
+    fn trace_records_reads_and_writes() {
+        let path = std::env::temp_dir().join(format!("wishbone-trace-{}.csv", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let trace = Arc::new(TraceLog::open(path.to_str().unwrap(), false).unwrap());
+        let mut bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let observer = trace.clone();
+        bridge.observe(move |access| observer.record(access));
+        bridge.poke(0x10, 0xcafe).unwrap();
+        bridge.peek(0x10).unwrap();
+        trace.flush().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "timestamp,operation,address,value");
+        assert!(lines[1].ends_with(",write,0x00000010,0x0000cafe"));
+        assert!(lines[2].ends_with(",read,0x00000010,0x0000cafe"));
+        let ops: Vec<ReplayOp> = lines
+            .iter()
+            .filter_map(|line| parse_replay_line(line, None).unwrap())
+            .map(|entry| entry.op)
+            .collect();
+        assert_eq!(
+            ops,
+            vec![ReplayOp::Write(0x10, 0xcafe), ReplayOp::Read(0x10, 0xcafe)]
+        );
+    }
+
+    #[test]
+    fn only_changes_are_recorded() {
+        let dir = std::env::temp_dir();
+        let trace_path = dir.join(format!("wishbone-changes-{}.csv", std::process::id()));
+        std::fs::remove_file(&trace_path).ok();
+        let trace = TraceLog::open(trace_path.to_str().unwrap(), true).unwrap();
+        for value in &[1, 1, 2, 2, 2, 1] {
+            trace.record(Access::Read(0x10, *value));
+        }
+        trace.record(Access::Read(0x20, 1));
+        trace.record(Access::Write(0x10, 1));
+        trace.record(Access::Write(0x10, 1));
+        trace.flush().unwrap();
+        let contents = std::fs::read_to_string(&trace_path).unwrap();
+        std::fs::remove_file(&trace_path).ok();
+        let rows: Vec<&str> = contents
+            .lines()
+            .skip(1)
+            .map(|line| line.split_once(',').unwrap().1)
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                "read,0x00000010,0x00000001",
+                "read,0x00000010,0x00000002",
+                "read,0x00000010,0x00000001",
+                "read,0x00000020,0x00000001",
+                "write,0x00000010,0x00000001",
+                "write,0x00000010,0x00000001",
+            ]
+        );
+
+        // Every third read is inverted, so the capture sees 0, 0, !0, 0, 0, !0
+        let logic_path = dir.join(format!("wishbone-changes-{}.logic", std::process::id()));
+        std::fs::remove_file(&logic_path).ok();
+        let bridge = wishbone_bridge::MockBridge::new()
+            .corrupt_every(3)
+            .create()
+            .unwrap();
+        let cfg = Config {
+            memory_address: Some(0x10),
+            logic_file: Some(logic_path.to_str().unwrap().to_owned()),
+            poll_count: Some(6),
+            poll_interval: 0,
+            trace_changes_only: true,
+            ..Default::default()
+        };
+        logic_capture(&cfg, bridge).unwrap();
+        let contents = std::fs::read_to_string(&logic_path).unwrap();
+        std::fs::remove_file(&logic_path).ok();
+        let values: Vec<&str> = contents
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(1).unwrap())
+            .collect();
+        assert_eq!(
+            values,
+            vec!["0x00000000", "0xffffffff", "0x00000000", "0xffffffff"]
+        );
+    }
+
+    #[test]
+    fn client_beyond_limit_is_refused() {
+        let clients = ClientCounter::new();
+        let first = clients.try_acquire(2).unwrap();
+        let _second = clients.try_acquire(2).unwrap();
+        assert!(clients.try_acquire(2).is_none());
+
+        drop(first);
+        assert!(clients.try_acquire(2).is_some());
+    }
+
+    #[test]
+    fn shutdown_waits_for_request_in_flight() {
+        let shutdown = Shutdown::new();
+        let request = shutdown.begin().unwrap();
+        shutdown.request();
+        assert!(shutdown.begin().is_none());
+        thread::scope(|s| {
+            let drained = s.spawn(|| shutdown.wait_for_drain());
+            thread::sleep(Duration::from_millis(50));
+            assert!(!drained.is_finished());
+            drop(request);
+            drained.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn idle_server_stops_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let shutdown = Shutdown::new();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(shutdown.accept(&listener).unwrap().is_some());
+
+        thread::scope(|s| {
+            let accepting = s.spawn(|| shutdown.accept(&listener));
+            thread::sleep(Duration::from_millis(50));
+            assert!(!accepting.is_finished());
+            shutdown.request();
+            assert!(accepting.join().unwrap().unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn client_limit_of_zero_is_unbounded() {
+        let clients = ClientCounter::new();
+        let slots: Vec<_> = (0..100).map(|_| clients.try_acquire(0).unwrap()).collect();
+        assert_eq!(slots.len(), 100);
+    }
+
+    #[test]
+    fn replay_reads_logic_rows() {
+        assert_eq!(parse_replay_line("timestamp,value", Some(0x10)), Ok(None));
+        assert_eq!(
+            parse_replay_line("1600000000.042,0x0000cafe", Some(0x10)),
+            Ok(Some(ReplayEntry {
+                timestamp: Duration::from_millis(1_600_000_000_042),
+                op: ReplayOp::Read(0x10, 0xcafe),
+            }))
+        );
+        assert!(parse_replay_line("1.000,0x1", None).is_err());
+    }
+
+    #[test]
+    fn replay_reads_transaction_rows() {
+        assert_eq!(
+            parse_replay_line("2.5,write,0xe0000000,1", None),
+            Ok(Some(ReplayEntry {
+                timestamp: Duration::from_millis(2500),
+                op: ReplayOp::Write(0xe000_0000, 1),
+            }))
+        );
+        assert!(parse_replay_line("2.5,poke,0xe0000000,1", None).is_err());
+        assert!(parse_replay_line("2.5,read,0xe0000000", None).is_err());
+    }
+
+    #[test]
+    fn pattern_fills_range() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let cfg = Config {
+            memory_address: Some(0x100),
+            burst_length: 16,
+            fill_pattern: Some(FillPattern::Incrementing),
+            bridge_kind: BridgeKind::Mock,
+            ..Default::default()
+        };
+        memory_operation(&cfg, &bridge).unwrap();
+        let words: Vec<u32> = (0..4)
+            .map(|i| bridge.peek(0x100 + i * 4).unwrap())
+            .collect();
+        assert_eq!(words, vec![0x100, 0x101, 0x102, 0x103]);
+    }
+
+    #[test]
+    fn memtest_reports_corrupted_word() {
+        // Word-at-a-time reads, so only the third read, of 0x108, is corrupted
+        let bridge = wishbone_bridge::MockBridge::new()
+            .corrupt_every(3)
+            .no_burst()
+            .create()
+            .unwrap();
+        let cfg = Config::default();
+        let failures = memtest(&cfg, &bridge, 0x100, 16, &[FillPattern::Checker]).unwrap();
+        assert_eq!(
+            failures,
+            vec![MemtestFailure {
+                pattern: FillPattern::Checker,
+                address: 0x108,
+                expected: 0x5555_5555,
+                actual: 0xaaaa_aaaa,
+            }]
+        );
+
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let cfg = Config {
+            memory_address: Some(0x100),
+            burst_length: 64,
+            memtest: true,
+            bridge_kind: BridgeKind::Mock,
+            ..Default::default()
+        };
+        memory_access(&cfg, bridge).unwrap();
+    }
+
+    #[test]
+    fn memory_access_writes_through_mock_bridge() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let cfg = Config {
+            memory_address: Some(0x4000_0000),
+            memory_value: Some(0xa5a5_5a5a),
+            ..Default::default()
+        };
+        memory_access(&cfg, bridge.clone()).unwrap();
+
+        let cfg = Config {
+            double_read: true,
+            ..cfg
+        };
+        assert_eq!(read_word(&cfg, &bridge, 0x4000_0000).unwrap(), 0xa5a5_5a5a);
+    }
+
+    #[test]
+    fn unsupported_operations_are_refused() {
+        let uart = BridgeCapabilities {
+            supports_burst: false,
+            max_width: 4,
+            address_width: 4,
+            supports_raw_command: true,
+        };
+        let usb = BridgeCapabilities {
+            supports_burst: true,
+            supports_raw_command: false,
+            ..uart
+        };
+        let narrow = BridgeCapabilities {
+            address_width: 2,
+            ..uart
+        };
+
+        let raw = Config {
+            raw_command: Some(vec![0xca, 0xfe]),
+            bridge_kind: BridgeKind::Usb,
+            ..Default::default()
+        };
+        assert!(check_capabilities(&raw, &uart).is_ok());
+        match check_capabilities(&raw, &usb) {
+            Err(ServerError::Unsupported(reason)) => {
+                assert_eq!(reason, "the usb bridge can't send raw commands")
+            }
+            other => panic!("expected Unsupported, got {:?}", other),
+        }
+
+        let high = Config {
+            memory_address: Some(0xfffc),
+            burst_length: 8,
+            ..Default::default()
+        };
+        assert!(check_capabilities(&high, &uart).is_ok());
+        assert!(matches!(
+            check_capabilities(&high, &narrow),
+            Err(ServerError::Unsupported(_))
+        ));
+        let cfg = Config {
+            burst_length: 4,
+            ..high
+        };
+        assert!(check_capabilities(&cfg, &narrow).is_ok());
+    }
+
+    #[test]
+    fn value_list_is_written_to_consecutive_words() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let cfg = Config {
+            memory_address: Some(0x200),
+            value_file: Some("values.txt".to_owned()),
+            values: vec![0x11, 0x22, 0x33],
+            ..Default::default()
+        };
+        assert_eq!(
+            memory_operation(&cfg, &bridge).unwrap(),
+            OperationResult::RangeWrote {
+                start: 0x200,
+                length: 12
+            }
+        );
+        assert_eq!(bridge.peek(0x200).unwrap(), 0x11);
+        assert_eq!(bridge.peek(0x208).unwrap(), 0x33);
+    }
+
+    #[test]
+    fn reverse_reads_from_the_top_down() {
+        let addresses_read = |reverse| {
+            let read = Arc::new(Mutex::new(vec![]));
+            let mut bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+            let observer = read.clone();
+            bridge.observe(move |access| {
+                if let Access::Read(addr, _) = access {
+                    observer.lock().unwrap().push(addr);
+                }
+            });
+            let cfg = Config {
+                memory_address: Some(0x100),
+                burst_length: 16,
+                reverse,
+                bridge_kind: BridgeKind::Mock,
+                ..Default::default()
+            };
+            let result = memory_operation(&cfg, &bridge).unwrap();
+            let read = read.lock().unwrap().clone();
+            (result, read)
+        };
+
+        let (_, ascending) = addresses_read(false);
+        assert_eq!(ascending, vec![0x100, 0x104, 0x108, 0x10c]);
+        let (result, descending) = addresses_read(true);
+        assert_eq!(descending, vec![0x10c, 0x108, 0x104, 0x100]);
+        assert_eq!(
+            result,
+            OperationResult::WordsRead(vec![(0x10c, 0), (0x108, 0), (0x104, 0), (0x100, 0)])
+        );
+    }
+
+    #[test]
+    fn expected_value_is_checked() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        bridge.poke(0x4000_0000, 0x1234_5678).unwrap();
+        let cfg = Config {
+            memory_address: Some(0x4000_0000),
+            expect_value: Some(0x1234_5678),
+            ..Default::default()
+        };
+        memory_access(&cfg, bridge.clone()).unwrap();
+
+        let cfg = Config {
+            expect_value: Some(0x1234_0000),
+            ..cfg
+        };
+        match memory_access(&cfg, bridge.clone()) {
+            Err(ServerError::UnexpectedValue(0x4000_0000, 0x1234_0000, 0x1234_5678)) => (),
+            other => panic!("expected UnexpectedValue, got {:?}", other),
+        }
+
+        let cfg = Config {
+            expect_mask: Some(0xffff_0000),
+            ..cfg
+        };
+        memory_access(&cfg, bridge).unwrap();
+    }
+
+    #[test]
+    fn watch_waits_for_expression() {
+        let mut bridge = wishbone_bridge::MockBridge::new()
+            .value(0x100, 0x1)
+            .create()
+            .unwrap();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let observed = reads.clone();
+        bridge.observe(move |access| {
+            if let Access::Read(0x100, _) = access {
+                observed.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        // The device becomes ready once the status register has been read twice
+        let device = bridge.clone();
+        let polled = reads.clone();
+        let ready = thread::spawn(move || {
+            while polled.load(Ordering::SeqCst) < 2 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            device.poke(0x100, 0x6).unwrap();
+        });
+        let expr =
+            WatchExpr::parse("(reg(0x100) & 0x3) == 0x2 && reg(0x104) == 0", parse_u32).unwrap();
+        let cfg = Config {
+            watch_expr: Some(expr),
+            poll_interval: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            memory_operation(&cfg, &bridge).unwrap(),
+            OperationResult::WordsRead(vec![(0x100, 0x6), (0x104, 0)])
+        );
+        ready.join().unwrap();
+        assert!(reads.load(Ordering::SeqCst) >= 3);
+
+        let cfg = Config {
+            watch_expr: Some(WatchExpr::parse("reg(0x100) == 0x1", parse_u32).unwrap()),
+            watch_timeout: Some(10),
+            ..cfg
+        };
+        match memory_operation(&cfg, &bridge) {
+            Err(ServerError::IoError(e)) if e.kind() == io::ErrorKind::TimedOut => (),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn double_read_retries_on_mismatch() {
+        let reads = [1u32, 2, 3, 3];
+        let mut calls = 0;
+        let cfg = Config {
+            retry_count: 3,
+            retry_backoff: RetryBackoff::None,
+            ..Default::default()
+        };
+        let val = confirmed_read(&cfg, 0x1000_0000, || {
+            calls += 1;
+            Ok(reads[calls - 1])
+        })
+        .unwrap();
+        assert_eq!(val, 3);
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn double_read_gives_up_after_retries() {
+        let mut next = 0;
+        let cfg = Config {
+            retry_count: 1,
+            retry_backoff: RetryBackoff::None,
+            ..Default::default()
+        };
+        let result = confirmed_read(&cfg, 0x1000_0000, || {
+            next += 1;
+            Ok(next)
+        });
+        match result {
+            Err(ServerError::ReadMismatch(0x1000_0000, 3, 4)) => (),
+            other => panic!("unexpected result: {:?}", other.map_err(|_| ())),
+        }
+    }
+
+    #[test]
+    fn only_listed_errors_are_retried() {
+        let cfg = Config {
+            retry_count: 2,
+            retry_backoff: RetryBackoff::None,
+            ..Default::default()
+        };
+        let mut calls = 0;
+        let val = retried(&cfg, "read", 0x10, || {
+            calls += 1;
+            if calls == 1 {
+                Err(BridgeError::Timeout)
+            } else {
+                Ok(7)
+            }
+        })
+        .unwrap();
+        assert_eq!((val, calls), (7, 2));
+
+        let mut calls = 0;
+        let result: Result<u32, _> = retried(&cfg, "read", 0x10, || {
+            calls += 1;
+            Err(BridgeError::WrongResponse)
+        });
+        assert!(matches!(result, Err(BridgeError::WrongResponse)));
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result: Result<u32, _> = retried(&cfg, "read", 0x10, || {
+            calls += 1;
+            Err(BridgeError::Timeout)
+        });
+        assert!(matches!(result, Err(BridgeError::Timeout)));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn memory_operation_reports_what_was_done() {
+        let bridge = wishbone_bridge::MockBridge::new()
+            .value(0x10, 0x4433_2211)
+            .create()
+            .unwrap();
+        let cfg = Config {
+            memory_address: Some(0x10),
+            ..Default::default()
+        };
+        assert_eq!(
+            memory_operation(&cfg, &bridge).unwrap(),
+            OperationResult::ReadValue {
+                address: 0x10,
+                value: 0x4433_2211
+            }
+        );
+
+        let cfg = Config {
+            memory_value: Some(0xaa),
+            ..cfg
+        };
+        assert_eq!(
+            memory_operation(&cfg, &bridge).unwrap(),
+            OperationResult::Wrote {
+                address: 0x10,
+                value: 0xaa
+            }
+        );
+
+        let cfg = Config {
+            memory_value: None,
+            burst_length: 2,
+            ..cfg
+        };
+        assert_eq!(
+            memory_operation(&cfg, &bridge).unwrap(),
+            OperationResult::RangeRead {
+                start: 0x10,
+                data: vec![0xaa, 0]
+            }
+        );
+
+        assert_eq!(
+            memory_operation(&Config::default(), &bridge).unwrap(),
+            OperationResult::NoOperation
+        );
+    }
+
+    #[test]
+    fn slow_transaction_is_reported() {
+        let bridge = wishbone_bridge::MockBridge::new()
+            .latency(Duration::from_millis(20))
+            .create()
+            .unwrap();
+        let cfg = Config {
+            slow_threshold: Some(5),
+            ..Default::default()
+        };
+        let (result, warning) =
+            timed_with_warning(&cfg, "read", 0x1000_0000, || bridge.peek(0x1000_0000));
+        assert_eq!(result.unwrap(), 0);
+        assert!(warning
+            .unwrap()
+            .starts_with("slow read at 0x10000000: took "));
+
+        let cfg = Config {
+            slow_threshold: None,
+            ..cfg
+        };
+        let (_, warning) =
+            timed_with_warning(&cfg, "read", 0x1000_0000, || bridge.peek(0x1000_0000));
+        assert!(warning.is_none());
+    }
+
+    #[test]
+    fn defines_fill_in_replay_lines() {
+        let mut defines = std::collections::HashMap::new();
+        defines.insert("BASE".to_owned(), "0x10000000".to_owned());
+        defines.insert("MAGIC".to_owned(), "42".to_owned());
+        let parse = |line: &str| {
+            parse_replay_line(&substitute_defines(line, &defines).unwrap(), None)
+                .unwrap()
+                .unwrap()
+                .op
+        };
+        assert_eq!(
+            parse("0,write,${BASE},${MAGIC}"),
+            ReplayOp::Write(0x1000_0000, 42)
+        );
+        assert_eq!(parse("0,read,${BASE},0"), ReplayOp::Read(0x1000_0000, 0));
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn loaded_file_checksum_is_verified() {
+        let path = std::env::temp_dir().join(format!("wishbone-load-{}.bin", std::process::id()));
+        std::fs::write(&path, b"12345678").unwrap();
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let cfg = Config {
+            load_name: Some(path.to_string_lossy().into_owned()),
+            load_addr: Some(0x1000_0000),
+            verify_checksum: true,
+            ..Default::default()
+        };
+        let loaded = load_file(&cfg, bridge.clone());
+
+        // Corrupt one byte so the second check fails
+        bridge.poke(0x1000_0004, 0).unwrap();
+        let corrupted = verify_load_checksum(
+            &cfg,
+            &bridge,
+            cfg.load_name.as_ref().unwrap(),
+            0x1000_0000,
+            8,
+        );
+        std::fs::remove_file(&path).ok();
+
+        assert!(loaded.is_ok());
+        match corrupted {
+            Err(ServerError::ChecksumMismatch(expected, _)) => {
+                assert_eq!(expected, crc32(b"12345678"))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pulse_restores_original_value() {
+        use std::cell::RefCell;
+        let transactions = RefCell::new(vec![]);
+        let original = pulse(
+            0x1,
+            Duration::from_millis(0),
+            || {
+                transactions.borrow_mut().push("read".to_owned());
+                Ok(0x8000)
+            },
+            |v| {
+                transactions.borrow_mut().push(format!("write {:x}", v));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(original, 0x8000);
+        assert_eq!(
+            transactions.into_inner(),
+            vec!["read", "write 1", "write 8000"]
+        );
+    }
+
+    #[test]
+    fn same_seed_repeats_sequence() {
+        let sequence = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..100)
+                .map(|_| random_step(&mut rng, Some(0x1000)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sequence(42), sequence(42));
+        assert_ne!(sequence(42), sequence(43));
+        assert!(sequence(42)
+            .iter()
+            .all(|(offset, _)| offset % 4 == 0 && *offset < 0x1000));
+    }
+
+    #[test]
+    fn register_dump_skips_write_only() {
+        use crate::config::CsrRegister;
+        let bridge = wishbone_bridge::MockBridge::new()
+            .value(0xe000_0000, 0x1234)
+            .value(0xe000_0004, 0x5678)
+            .create()
+            .unwrap();
+        let register = |name: &str, address, write_only| CsrRegister {
+            name: name.to_owned(),
+            address,
+            write_only,
+        };
+        let cfg = Config {
+            dump_registers: true,
+            csr_registers: vec![
+                register("ctrl_scratch", 0xe000_0000, false),
+                register("ctrl_reset", 0xe000_0004, true),
+                register("timer0_value", 0xe000_0008, false),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            memory_operation(&cfg, &bridge).unwrap(),
+            OperationResult::Registers(vec![
+                RegisterValue {
+                    name: "ctrl_scratch".to_owned(),
+                    address: 0xe000_0000,
+                    value: Ok(0x1234)
+                },
+                RegisterValue {
+                    name: "timer0_value".to_owned(),
+                    address: 0xe000_0008,
+                    value: Ok(0)
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn ranges_are_split_into_chunks() {
+        assert_eq!(
+            chunks(0x1000, 40, 16).collect::<Vec<_>>(),
+            vec![(0x1000, 16), (0x1010, 16), (0x1020, 8)]
+        );
+        assert_eq!(chunks(0x1000, 4096, 4096).count(), 1);
+        assert_eq!(chunks(0x1000, 0, 16).count(), 0);
+    }
+
+    #[test]
+    fn chunked_range_round_trips() {
+        let data: Vec<u8> = (0..22).collect();
+        for &no_burst in &[false, true] {
+            let mut mock = wishbone_bridge::MockBridge::new();
+            mock.value(0x14, 0xaabb_ccdd);
+            if no_burst {
+                mock.no_burst();
+            }
+            let bridge = mock.create().unwrap();
+            let cfg = Config {
+                chunk_size: Some(2),
+                ..Default::default()
+            };
+            write_range(&cfg, &bridge, 0, &data).unwrap();
+            assert_eq!(read_range(&cfg, &bridge, 0, 22).unwrap(), data);
+            // The partial last word keeps its upper bytes
+            assert_eq!(bridge.peek(0x14).unwrap(), 0xaabb_1514);
+        }
+    }
+
+    #[test]
+    fn server_kinds_are_found_by_name() {
+        for kind in ServerKind::ALL {
+            assert_eq!(ServerKind::from_string(kind.name()).unwrap(), *kind);
+        }
+    }
+
+    #[test]
+    fn registers_are_compared_against_snapshot() {
+        use crate::config::CsrRegister;
+        let bridge = wishbone_bridge::MockBridge::new()
+            .value(0xe000_0000, 0x1234)
+            .value(0xe000_0004, 7)
+            .create()
+            .unwrap();
+        let register = |name: &str, address| CsrRegister {
+            name: name.to_owned(),
+            address,
+            write_only: false,
+        };
+        let dir = std::env::temp_dir();
+        let before = dir.join(format!("wishbone-snapshot-before-{}", std::process::id()));
+        let after = dir.join(format!("wishbone-snapshot-after-{}", std::process::id()));
+        std::fs::write(
+            &before,
+            "ctrl_scratch = 0x00001234\nctrl_bus_errors = 0x00000000\nctrl_gone = 0x00000001\n",
+        )
+        .unwrap();
+        let mut cfg = Config {
+            csr_registers: vec![
+                register("ctrl_scratch", 0xe000_0000),
+                register("ctrl_bus_errors", 0xe000_0004),
+                register("timer0_value", 0xe000_0008),
+            ],
+            compare_registers: Some(before.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            memory_operation(&cfg, &bridge).unwrap(),
+            OperationResult::RegisterChanges(vec![
+                RegisterChange {
+                    name: "ctrl_bus_errors".to_owned(),
+                    old: Some(0),
+                    new: Some(Ok(7)),
+                },
+                RegisterChange {
+                    name: "timer0_value".to_owned(),
+                    old: None,
+                    new: Some(Ok(0)),
+                },
+                RegisterChange {
+                    name: "ctrl_gone".to_owned(),
+                    old: Some(1),
+                    new: None,
+                },
+            ])
+        );
+
+        // A snapshot of the current state has nothing to report
+        std::fs::write(
+            &after,
+            OperationResult::Registers(read_registers(&cfg, &bridge)).render(&cfg),
+        )
+        .unwrap();
+        cfg.compare_registers = Some(after.to_string_lossy().into_owned());
+        assert_eq!(
+            memory_operation(&cfg, &bridge).unwrap(),
+            OperationResult::RegisterChanges(vec![])
+        );
+        std::fs::remove_file(before).ok();
+        std::fs::remove_file(after).ok();
+    }
+
+    #[test]
+    fn snapshot_lines_must_have_a_value() {
+        assert_eq!(
+            parse_register_snapshot("# taken before\n\na = 1\nb = error: Timeout\n").unwrap(),
+            vec![("a".to_owned(), 1)]
+        );
+        assert!(matches!(
+            parse_register_snapshot("a = 1\nb\n"),
+            Err(ServerError::SnapshotParseError(2, _))
+        ));
+    }
+
+    #[test]
+    fn txn_blocks_group_entries() {
+        let log = "0,write,0x10,1\ntxn {\n1,write,0x10,2\n1,read,0x10,2\n}\n";
+        assert_eq!(
+            parse_replay_log(log, &HashMap::new(), None).unwrap(),
+            vec![
+                ReplayStep::Single(ReplayEntry {
+                    timestamp: Duration::from_secs(0),
+                    op: ReplayOp::Write(0x10, 1),
+                }),
+                ReplayStep::Txn(vec![
+                    ReplayEntry {
+                        timestamp: Duration::from_secs(1),
+                        op: ReplayOp::Write(0x10, 2),
+                    },
+                    ReplayEntry {
+                        timestamp: Duration::from_secs(1),
+                        op: ReplayOp::Read(0x10, 2),
+                    },
+                ]),
+            ]
+        );
+        for bad in &["txn {\ntxn {\n}\n}\n", "}\n", "0,read,0x10,0\ntxn {\n"] {
+            assert!(matches!(
+                parse_replay_log(bad, &HashMap::new(), None),
+                Err(ServerError::ReplayParseError(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn random_test_stops_at_failure_limit() {
+        let bridge = || {
+            wishbone_bridge::MockBridge::new()
+                .corrupt_every(3)
+                .create()
+                .unwrap()
+        };
+        let cfg = Config {
+            random_address: Some(0x1000_0000),
+            random_loops: Some(20),
+            random_seed: Some(1),
+            ..Default::default()
+        };
+        // Reads 3, 6, 9... are corrupted, which are loops 2, 5, 8...
+        assert!(matches!(
+            random_test(&cfg, bridge()),
+            Err(ServerError::RandomValueError(2, _, _))
+        ));
+
+        let cfg = Config {
+            abort_after_failures: Some(4),
+            ..cfg
+        };
+        assert!(matches!(
+            random_test(&cfg, bridge()),
+            Err(ServerError::RandomTestFailed(4, 12))
+        ));
+
+        let cfg = Config {
+            abort_after_failures: Some(100),
+            ..cfg
+        };
+        assert!(matches!(
+            random_test(&cfg, bridge()),
+            Err(ServerError::RandomTestFailed(7, 21))
+        ));
+    }
+
+    #[test]
+    fn success_rate_decides_pass_or_fail() {
+        let bridge = || {
+            wishbone_bridge::MockBridge::new()
+                .corrupt_every(3)
+                .no_burst()
+                .create()
+                .unwrap()
+        };
+        // 7 of 21 loops fail, so 66.67% succeed
+        let cfg = Config {
+            random_address: Some(0x1000_0000),
+            random_loops: Some(20),
+            random_seed: Some(1),
+            min_success_rate: Some(66.0),
+            ..Default::default()
+        };
+        random_test(&cfg, bridge()).unwrap();
+        let cfg = Config {
+            min_success_rate: Some(67.0),
+            ..cfg
+        };
+        match random_test(&cfg, bridge()) {
+            Err(ServerError::SuccessRateTooLow(rate, required)) => {
+                assert!((rate - 200.0 / 3.0).abs() < 1e-9);
+                assert_eq!(required, 67.0);
+            }
+            other => panic!("expected the success rate to be too low, got {:?}", other),
+        }
+
+        // 1 of 4 words fails, so 75% succeed
+        let cfg = Config {
+            burst_length: 16,
+            fill_pattern: Some(FillPattern::Checker),
+            min_success_rate: Some(75.0),
+            ..Default::default()
+        };
+        run_memtest(&cfg, &bridge(), 0x100).unwrap();
+        let cfg = Config {
+            min_success_rate: Some(75.1),
+            ..cfg
+        };
+        assert!(matches!(
+            run_memtest(&cfg, &bridge(), 0x100),
+            Err(ServerError::SuccessRateTooLow(_, _))
+        ));
+        let cfg = Config {
+            min_success_rate: None,
+            ..cfg
+        };
+        assert!(matches!(
+            run_memtest(&cfg, &bridge(), 0x100),
+            Err(ServerError::MemtestFailed(1))
+        ));
+    }
+
+    #[test]
+    fn passed_deadline_stops_loops_at_once() {
+        let mut bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let accesses = Arc::new(AtomicUsize::new(0));
+        let observed = accesses.clone();
+        bridge.observe(move |_| {
+            observed.fetch_add(1, Ordering::SeqCst);
+        });
+        let cfg = Config {
+            random_address: Some(0x1000_0000),
+            deadline: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            ..Default::default()
+        };
+        random_test(&cfg, bridge.clone()).unwrap();
+
+        let path = std::env::temp_dir().join(format!("wishbone-deadline-{}", std::process::id()));
+        poll_to_file(&cfg, &bridge, 0x1000_0000, &path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(accesses.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn raw_command_is_sent_as_is() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let cfg = Config {
+            raw_command: Some(vec![0xde, 0xad]),
+            ..Default::default()
+        };
+        let result = memory_operation(&cfg, &bridge).unwrap();
+        assert_eq!(
+            result,
+            OperationResult::RawCommand {
+                sent: vec![0xde, 0xad],
+                received: vec![0xde, 0xad],
+            }
+        );
+        assert_eq!(result.render(&cfg), b"Received 2 bytes: de ad\n".to_vec());
+    }
+
+    #[test]
+    fn preflight_stops_servers_on_dead_device() {
+        let bridge = wishbone_bridge::MockBridge::new()
+            .value(0x10, 0x1234_5678)
+            .unresponsive(0x20)
+            .create()
+            .unwrap();
+        let cfg = Config {
+            preflight: true,
+            preflight_address: Some(0x10),
+            server_kind: vec![ServerKind::Wishbone],
+            ..Default::default()
+        };
+        preflight(&cfg, &bridge).unwrap();
+
+        let cfg = Config {
+            preflight_address: Some(0x20),
+            ..cfg
+        };
+        match preflight(&cfg, &bridge) {
+            Err(ServerError::BridgeError(e)) if e.is_timeout() => (),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+
+        // Without --preflight, nothing is read
+        let cfg = Config {
+            preflight: false,
+            ..cfg
+        };
+        preflight(&cfg, &bridge).unwrap();
+    }
+
+    #[test]
+    fn keepalive_reads_after_idle_interval() {
+        let bridge = wishbone_bridge::MockBridge::new().create().unwrap();
+        let interval = Duration::from_millis(20);
+        bridge.peek(0).unwrap();
+        assert!(keepalive_step(&bridge, interval, 0x10).unwrap() > Duration::from_millis(0));
+
+        thread::sleep(interval);
+        assert!(bridge.idle_time() >= interval);
+        assert_eq!(keepalive_step(&bridge, interval, 0x10).unwrap(), interval);
+        assert!(bridge.idle_time() < interval);
+    }
+}
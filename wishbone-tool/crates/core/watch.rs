@@ -0,0 +1,345 @@
+//! The small expression language of `--watch-expr`, such as
+//! `(reg(0xe0000800) & 0x3) == 0x2 && (reg(ctrl_flags) & 0x80) != 0`.
+//!
+//! Every value is a 32-bit word. As in Rust, `&` binds tighter than `|`,
+//! which binds tighter than `==` and `!=`, which bind tighter than `&&`
+//! and then `||`. Comparisons give 1 or 0, and `&&` and `||` treat any
+//! non-zero value as true.
+
+use crate::config::{parse_u32, ConfigError};
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operator {
+    BitAnd,
+    BitOr,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+impl Operator {
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::BitAnd => "&",
+            Operator::BitOr => "|",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum WatchExpr {
+    /// A number written in the expression
+    Literal(u32),
+
+    /// The word at this address, read from the bus each time the
+    /// expression is evaluated
+    Register(u32),
+
+    Binary(Operator, Box<WatchExpr>, Box<WatchExpr>),
+}
+
+impl WatchExpr {
+    /// Parse `text`, using `resolve` to turn the argument of each `reg()`
+    /// into an address.
+    pub fn parse<F>(text: &str, resolve: F) -> Result<WatchExpr, ConfigError>
+    where
+        F: Fn(&str) -> Result<u32, ConfigError>,
+    {
+        let mut parser = Parser {
+            text,
+            pos: 0,
+            resolve: &resolve,
+        };
+        let expr = parser.or()?;
+        parser.skip_whitespace();
+        if parser.pos < text.len() {
+            return Err(parser.error("expected an operator"));
+        }
+        Ok(expr)
+    }
+
+    /// Every address the expression reads, in the order they appear.
+    pub fn registers(&self) -> Vec<u32> {
+        match self {
+            WatchExpr::Literal(_) => vec![],
+            WatchExpr::Register(addr) => vec![*addr],
+            WatchExpr::Binary(_, left, right) => {
+                let mut registers = left.registers();
+                registers.extend(right.registers());
+                registers
+            }
+        }
+    }
+
+    /// Move every address read by `f`, such as when an address offset
+    /// is applied.
+    pub fn map_registers<F>(self, f: &F) -> Result<WatchExpr, ConfigError>
+    where
+        F: Fn(u32) -> Result<u32, ConfigError>,
+    {
+        Ok(match self {
+            WatchExpr::Literal(value) => WatchExpr::Literal(value),
+            WatchExpr::Register(addr) => WatchExpr::Register(f(addr)?),
+            WatchExpr::Binary(op, left, right) => WatchExpr::Binary(
+                op,
+                Box::new(left.map_registers(f)?),
+                Box::new(right.map_registers(f)?),
+            ),
+        })
+    }
+
+    /// Work out the value of the expression, calling `read` for each
+    /// register. The right side of `&&` and `||` is only evaluated when
+    /// it's needed.
+    pub fn evaluate<E, F>(&self, read: &mut F) -> Result<u32, E>
+    where
+        F: FnMut(u32) -> Result<u32, E>,
+    {
+        Ok(match self {
+            WatchExpr::Literal(value) => *value,
+            WatchExpr::Register(addr) => read(*addr)?,
+            WatchExpr::Binary(op, left, right) => {
+                let left = left.evaluate(read)?;
+                match op {
+                    Operator::And if left == 0 => 0,
+                    Operator::Or if left != 0 => 1,
+                    Operator::And | Operator::Or => (right.evaluate(read)? != 0) as u32,
+                    Operator::BitAnd => left & right.evaluate(read)?,
+                    Operator::BitOr => left | right.evaluate(read)?,
+                    Operator::Equal => (left == right.evaluate(read)?) as u32,
+                    Operator::NotEqual => (left != right.evaluate(read)?) as u32,
+                }
+            }
+        })
+    }
+}
+
+impl fmt::Display for WatchExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatchExpr::Literal(value) => write!(f, "0x{:x}", value),
+            WatchExpr::Register(addr) => write!(f, "reg(0x{:08x})", addr),
+            WatchExpr::Binary(op, left, right) => {
+                write!(f, "({} {} {})", left, op.symbol(), right)
+            }
+        }
+    }
+}
+
+/// A recursive descent parser, with one function for each level of
+/// precedence.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    resolve: &'a dyn Fn(&str) -> Result<u32, ConfigError>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, reason: &str) -> ConfigError {
+        let found = match self.rest().chars().next() {
+            Some(c) => format!("\"{}\"", c),
+            None => "the end".to_owned(),
+        };
+        ConfigError::ExpressionParseError(self.pos + 1, format!("{}, found {}", reason, found))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consume `token` if it comes next. `not_followed_by` stops `&` from
+    /// matching the start of `&&`.
+    fn eat(&mut self, token: &str, not_followed_by: Option<char>) -> bool {
+        self.skip_whitespace();
+        let rest = self.rest();
+        if !rest.starts_with(token) {
+            return false;
+        }
+        if not_followed_by.is_some() && rest[token.len()..].chars().next() == not_followed_by {
+            return false;
+        }
+        self.pos += token.len();
+        true
+    }
+
+    fn or(&mut self) -> Result<WatchExpr, ConfigError> {
+        let mut expr = self.and()?;
+        while self.eat("||", None) {
+            expr = WatchExpr::Binary(Operator::Or, Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<WatchExpr, ConfigError> {
+        let mut expr = self.comparison()?;
+        while self.eat("&&", None) {
+            expr = WatchExpr::Binary(Operator::And, Box::new(expr), Box::new(self.comparison()?));
+        }
+        Ok(expr)
+    }
+
+    /// Comparisons don't chain, so `a == b == c` needs parentheses.
+    fn comparison(&mut self) -> Result<WatchExpr, ConfigError> {
+        let left = self.bit_or()?;
+        let op = if self.eat("==", None) {
+            Operator::Equal
+        } else if self.eat("!=", None) {
+            Operator::NotEqual
+        } else {
+            return Ok(left);
+        };
+        Ok(WatchExpr::Binary(
+            op,
+            Box::new(left),
+            Box::new(self.bit_or()?),
+        ))
+    }
+
+    fn bit_or(&mut self) -> Result<WatchExpr, ConfigError> {
+        let mut expr = self.bit_and()?;
+        while self.eat("|", Some('|')) {
+            expr = WatchExpr::Binary(Operator::BitOr, Box::new(expr), Box::new(self.bit_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn bit_and(&mut self) -> Result<WatchExpr, ConfigError> {
+        let mut expr = self.primary()?;
+        while self.eat("&", Some('&')) {
+            expr = WatchExpr::Binary(Operator::BitAnd, Box::new(expr), Box::new(self.primary()?));
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<WatchExpr, ConfigError> {
+        if self.eat("(", None) {
+            let expr = self.or()?;
+            if !self.eat(")", None) {
+                return Err(self.error("expected \")\""));
+            }
+            return Ok(expr);
+        }
+        self.skip_whitespace();
+        let word_len = self
+            .rest()
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or_else(|| self.rest().len());
+        if word_len == 0 {
+            return Err(self.error("expected a number, reg() or \"(\""));
+        }
+        let word = &self.rest()[..word_len];
+        if word == "reg" {
+            self.pos += word_len;
+            if !self.eat("(", None) {
+                return Err(self.error("expected \"(\" after reg"));
+            }
+            self.skip_whitespace();
+            let start = self.pos;
+            let end = match self.rest().find(')') {
+                Some(len) => start + len,
+                None => {
+                    self.pos = self.text.len();
+                    return Err(self.error("expected \")\""));
+                }
+            };
+            let addr = (self.resolve)(self.text[start..end].trim())?;
+            self.pos = end + 1;
+            return Ok(WatchExpr::Register(addr));
+        }
+        let value = parse_u32(word).map_err(|_| self.error("expected a number, reg() or \"(\""))?;
+        self.pos += word_len;
+        Ok(WatchExpr::Literal(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(text: &str) -> Result<WatchExpr, ConfigError> {
+        WatchExpr::parse(text, |addr| match addr {
+            "status" => Ok(0x100),
+            addr => parse_u32(addr),
+        })
+    }
+
+    fn evaluate(expr: &WatchExpr, status: u32, flags: u32) -> u32 {
+        expr.evaluate(&mut |addr| match addr {
+            0x100 => Ok::<u32, ()>(status),
+            0x104 => Ok(flags),
+            _ => Err(()),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn expressions_are_parsed() {
+        let expr = parse("(reg(status) & 0x3) == 0x2 && (reg(0x104) & 0x80) != 0").unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "(((reg(0x00000100) & 0x3) == 0x2) && ((reg(0x00000104) & 0x80) != 0x0))"
+        );
+        assert_eq!(expr.registers(), vec![0x100, 0x104]);
+        assert_eq!(evaluate(&expr, 0x6, 0x80), 1);
+        assert_eq!(evaluate(&expr, 0x6, 0x7f), 0);
+        assert_eq!(evaluate(&expr, 0x1, 0x80), 0);
+
+        // & binds tighter than |, which binds tighter than ==
+        assert_eq!(
+            parse("reg(0x100) | 1 & 2 == 3").unwrap().to_string(),
+            "((reg(0x00000100) | (0x1 & 0x2)) == 0x3)"
+        );
+        assert_eq!(
+            parse("1 || 0 && 0").unwrap().to_string(),
+            "(0x1 || (0x0 && 0x0))"
+        );
+    }
+
+    #[test]
+    fn right_side_is_only_read_when_needed() {
+        let expr = parse("reg(0x100) || reg(0x104)").unwrap();
+        let mut reads = vec![];
+        let value = expr
+            .evaluate(&mut |addr| {
+                reads.push(addr);
+                Ok::<u32, ()>(1)
+            })
+            .unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(reads, vec![0x100]);
+    }
+
+    #[test]
+    fn syntax_errors_give_the_column() {
+        for (text, column) in &[
+            ("(reg(status) & 0x3", 19),
+            ("reg(status) === 2", 15),
+            ("reg status", 5),
+            ("0x1 &", 6),
+            ("0x1 0x2", 5),
+            ("", 1),
+        ] {
+            match parse(text) {
+                Err(ConfigError::ExpressionParseError(c, _)) => {
+                    assert_eq!(c, *column, "for {}", text)
+                }
+                other => panic!("expected a parse error for {}, got {:?}", text, other),
+            }
+        }
+        match parse("reg(0x100000000)") {
+            Err(ConfigError::NumberParseError(_, _)) => (),
+            other => panic!("expected a number error, got {:?}", other),
+        }
+    }
+}
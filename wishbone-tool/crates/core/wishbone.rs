@@ -2,11 +2,12 @@ extern crate byteorder;
 
 use std::io;
 use std::io::{Cursor, Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 
-use super::Config;
+use crate::config::{check_write_allowed, Config, ConfigError};
+use crate::server::Shutdown;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use wishbone_bridge::{Bridge, BridgeError};
+use wishbone_bridge::{Bridge, BridgeError, Transaction};
 
 /* The network protocol looks like this:
 
@@ -47,6 +48,12 @@ pub struct WishboneConnection {
     connection: TcpStream,
 }
 
+/// A request that has been received in full but not yet carried out.
+pub struct WishbonePacket {
+    header: [u8; 16],
+    buffer: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub enum WishboneServerError {
     /// An error with TCP
@@ -63,6 +70,9 @@ pub enum WishboneServerError {
 
     /// There was a problem with the device bridge
     BridgeError(BridgeError),
+
+    /// `--safe` refused a write, which was dropped
+    UnsafeWrite(ConfigError),
 }
 
 impl std::convert::From<io::Error> for WishboneServerError {
@@ -80,18 +90,25 @@ impl std::convert::From<BridgeError> for WishboneServerError {
 impl WishboneServer {
     pub fn new(cfg: &Config) -> Result<WishboneServer, WishboneServerError> {
         Ok(WishboneServer {
-            listener: TcpListener::bind(format!("{}:{}", cfg.bind_addr, cfg.bind_port))?,
+            listener: TcpListener::bind(cfg.bind_socket_addr(cfg.bind_port))?,
         })
     }
 
-    pub fn connect(&mut self) -> Result<WishboneConnection, WishboneServerError> {
-        let (connection, _sockaddr) = self.listener.accept()?;
-        Ok(WishboneConnection { connection })
+    /// Wait for the next client, or return `None` once `shutdown` has been
+    /// requested.
+    pub fn connect(
+        &mut self,
+        shutdown: &Shutdown,
+    ) -> Result<Option<(WishboneConnection, SocketAddr)>, WishboneServerError> {
+        Ok(shutdown
+            .accept(&self.listener)?
+            .map(|(connection, sockaddr)| (WishboneConnection { connection }, sockaddr)))
     }
 }
 
 impl WishboneConnection {
-    pub fn process(&mut self, bridge: &Bridge) -> Result<(), WishboneServerError> {
+    /// Wait for the next request from the client.
+    pub fn receive(&mut self) -> Result<WishbonePacket, WishboneServerError> {
         let mut header = [0; 16];
         let mut offset = 0;
         let mut byte = [0; 1];
@@ -128,13 +145,36 @@ impl WishboneConnection {
             buffer[offset] = byte[0];
             offset += 1;
         }
+        Ok(WishbonePacket { header, buffer })
+    }
+
+    /// Carry out a request on the bridge and send any reply. Writes that
+    /// `cfg` doesn't allow are refused before any word is written. All the
+    /// words of a request are one transaction, so requests from other
+    /// clients can't land in the middle of it.
+    pub fn execute(
+        &mut self,
+        cfg: &Config,
+        bridge: &Bridge,
+        packet: WishbonePacket,
+    ) -> Result<(), WishboneServerError> {
+        let WishbonePacket {
+            mut header,
+            mut buffer,
+        } = packet;
+        let connection = &mut self.connection;
+        let wcount = header[10];
+        let rcount = header[11];
 
         // Figure out if it's a read or a write
         if wcount > 0 {
             // Write
             let mut addr_vec = Cursor::new(vec![header[12], header[13], header[14], header[15]]);
             let mut addr = addr_vec.read_u32::<BigEndian>()?;
+            check_write_allowed(cfg, addr, 4 * u32::from(wcount))
+                .map_err(WishboneServerError::UnsafeWrite)?;
             let mut count = 0;
+            let mut steps = vec![];
 
             while count < wcount {
                 let mut value_vec = Cursor::new(vec![
@@ -144,28 +184,28 @@ impl WishboneConnection {
                     buffer[(4 * count + 3) as usize],
                 ]);
                 let value = value_vec.read_u32::<BigEndian>()?;
-                bridge.poke(addr, value)?;
+                steps.push(Transaction::Write(addr, value));
                 count += 1;
                 addr += 4;
             }
+            bridge.transaction(&steps)?;
             Ok(())
         } else if rcount > 0 {
             // Read
             let mut addr_vec = Cursor::new(vec![buffer[0], buffer[1], buffer[2], buffer[3]]);
-            let mut addr = addr_vec.read_u32::<BigEndian>()?;
-            let mut count = 0;
-            while count < rcount {
-                let value = bridge.peek(addr)?;
+            let addr = addr_vec.read_u32::<BigEndian>()?;
+            let steps: Vec<Transaction> = (0..u32::from(rcount))
+                .map(|i| Transaction::Read(addr + 4 * i))
+                .collect();
+            let values = bridge.transaction(&steps)?;
+            for (count, value) in values.into_iter().enumerate() {
                 let mut value_vec = vec![];
                 value_vec.write_u32::<BigEndian>(value)?;
 
-                buffer[(count * 4) as usize] = value_vec[0];
-                buffer[(count * 4 + 1) as usize] = value_vec[1];
-                buffer[(count * 4 + 2) as usize] = value_vec[2];
-                buffer[(count * 4 + 3) as usize] = value_vec[3];
-
-                count += 1;
-                addr += 4;
+                buffer[count * 4] = value_vec[0];
+                buffer[count * 4 + 1] = value_vec[1];
+                buffer[count * 4 + 2] = value_vec[2];
+                buffer[count * 4 + 3] = value_vec[3];
             }
 
             // Response goes back as a write
@@ -179,3 +219,56 @@ impl WishboneConnection {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    /// A connection to a local socket, and the client end that sees its
+    /// replies.
+    fn connection() -> (WishboneConnection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+        (WishboneConnection { connection }, client)
+    }
+
+    #[test]
+    fn request_is_one_transaction() {
+        let bridge = wishbone_bridge::MockBridge::new()
+            .latency(Duration::from_millis(1))
+            .create()
+            .unwrap();
+        let other = bridge.clone();
+        let writer = thread::spawn(move || {
+            for i in 1..=40 {
+                other
+                    .transaction(&[Transaction::Write(0x10, i), Transaction::Write(0x14, i)])
+                    .unwrap();
+            }
+        });
+
+        // Both words are always written together, so a request that reads
+        // them as one transaction always sees them match.
+        let (mut connection, mut client) = connection();
+        let mut header = [0; 16];
+        header[0] = 0x4e;
+        header[1] = 0x6f;
+        header[11] = 2;
+        for _ in 0..20 {
+            let packet = WishbonePacket {
+                header,
+                buffer: vec![0, 0, 0, 0x10, 0, 0, 0, 0],
+            };
+            connection
+                .execute(&Config::default(), &bridge, packet)
+                .unwrap();
+            let mut reply = [0; 24];
+            client.read_exact(&mut reply).unwrap();
+            assert_eq!(reply[16..20], reply[20..24]);
+        }
+        writer.join().unwrap();
+    }
+}
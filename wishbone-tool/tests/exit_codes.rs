@@ -0,0 +1,69 @@
+//! Check that failures produce the exit codes documented in `--help`, using
+//! the mock bridge so no hardware is needed.
+
+use std::process::Command;
+
+fn exit_code(args: &[&str]) -> i32 {
+    Command::new(env!("CARGO_BIN_EXE_wishbone-tool"))
+        .args(args)
+        .output()
+        .expect("couldn't run wishbone-tool")
+        .status
+        .code()
+        .expect("wishbone-tool was killed by a signal")
+}
+
+#[test]
+fn success_is_zero() {
+    assert_eq!(exit_code(&["--mock", "0x10000000"]), 0);
+}
+
+#[test]
+fn config_errors_are_one() {
+    assert_eq!(exit_code(&[]), 1);
+    assert_eq!(exit_code(&["--mock", "ctrl_scratch"]), 1);
+}
+
+#[test]
+fn io_errors_are_two() {
+    assert_eq!(
+        exit_code(&[
+            "--mock",
+            "-s",
+            "load-file",
+            "--load-name",
+            "/nonexistent/image.bin",
+            "--load-address",
+            "0x10000000",
+        ]),
+        2
+    );
+}
+
+#[test]
+fn mismatches_are_three() {
+    assert_eq!(exit_code(&["--mock", "0x10000000", "--expect", "5"]), 3);
+}
+
+#[test]
+fn timeouts_are_four() {
+    assert_eq!(
+        exit_code(&[
+            "--mock",
+            "--watch-expr",
+            "reg(0x10000000) == 1",
+            "--timeout",
+            "100",
+            "--poll-interval",
+            "10",
+        ]),
+        4
+    );
+}
+
+#[test]
+fn server_panics_are_five() {
+    // The messible server panics when stdout isn't a terminal, as it
+    // isn't when the output is captured
+    assert_eq!(exit_code(&["--mock", "-s", "messible"]), 5);
+}
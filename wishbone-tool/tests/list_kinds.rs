@@ -0,0 +1,30 @@
+//! Check the names printed by `--list-server-kinds` and
+//! `--list-bridge-kinds`, which scripts and shell completions rely on.
+
+use std::process::Command;
+
+fn stdout(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_wishbone-tool"))
+        .args(args)
+        .output()
+        .expect("couldn't run wishbone-tool");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn server_kinds_are_listed() {
+    assert_eq!(
+        stdout(&["--list-server-kinds"]),
+        "gdb\nwishbone\nrandom-test\nload-file\nterminal\nmessible\n\
+         memory-access\nflash-program\nlogic\nreplay\nfile\n"
+    );
+}
+
+#[test]
+fn bridge_kinds_are_listed() {
+    assert_eq!(
+        stdout(&["--list-bridge-kinds"]),
+        "usb\nuart\nethernet\npcie\nspi\nmock\n"
+    );
+}
@@ -0,0 +1,37 @@
+//! Check that `--output` moves results into a file while diagnostics keep
+//! going to stderr.
+
+use std::process::Command;
+
+#[test]
+fn results_go_to_output_file() {
+    let path =
+        std::env::temp_dir().join(format!("wishbone-tool-output-{}.txt", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_wishbone-tool"))
+        .args(["--mock", "0x10000000", "--output"])
+        .arg(&path)
+        .env("RUST_LOG", "wishbone_bridge=debug")
+        .output()
+        .expect("couldn't run wishbone-tool");
+    let results = std::fs::read_to_string(&path);
+    std::fs::remove_file(&path).ok();
+
+    assert!(output.status.success());
+    assert_eq!(results.unwrap(), "Value at 10000000: 00000000\n");
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("PEEK @ 10000000"));
+}
+
+#[test]
+fn bad_output_path_is_a_config_error() {
+    let status = Command::new(env!("CARGO_BIN_EXE_wishbone-tool"))
+        .args([
+            "--mock",
+            "0x10000000",
+            "--output",
+            "/nonexistent/results.txt",
+        ])
+        .status()
+        .expect("couldn't run wishbone-tool");
+    assert_eq!(status.code(), Some(1));
+}